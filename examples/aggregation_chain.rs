@@ -1,29 +1,23 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{num, CandleGenerator, Timeframe, Trade, Instrument, Side};
 use chrono::{Utc, TimeZone};
 
 fn main() {
-    let trades = vec![
+    let instrument = Instrument::from_exchange_symbol("binance", "BTCUSDT").unwrap();
+    println!("{}", instrument);
+    let trades = [
         Trade {
-            instrument: Instrument {
-                pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
-                exchange: "binance".into(),
-                market_type: MarketType::Spot,
-            },
+            instrument: instrument.clone(),
             id: "t1".into(),
-            price: 50000.0,
-            amount: 0.1,
+            price: num(50000.0),
+            amount: num(0.1),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
         Trade {
-            instrument: Instrument {
-                pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
-                exchange: "binance".into(),
-                market_type: MarketType::Spot,
-            },
+            instrument: instrument.clone(),
             id: "t2".into(),
-            price: 50100.0,
-            amount: 0.2,
+            price: num(50100.0),
+            amount: num(0.2),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(1714000060000).unwrap(),
         },
@@ -1,13 +1,20 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{num, CandleGenerator, TradeColumns, Timeframe, Trade, Instrument, Pair, MarketType, Side};
 use chrono::{Utc, TimeZone};
+use legacy::LegacyBuilder;
 use std::time::Instant;
 use std::fs::File;
 use std::io::Write;
 
+// BENCH_TRADES — число трейдов, BENCH_BATCH — трейдов на свечу:
+//   BENCH_TRADES=5000000 BENCH_BATCH=1 cargo run --release --example bench
+//
+// «До» — копия прежнего горячего пути (`legacy`): свеча владеет `Instrument` (три `String` на
+// свечу), `push` возвращает `Vec`, выданная свеча клонируется для `fill_gaps`. «После» —
+// `aggregate` (`TradeRef`, общий `Arc<Instrument>`, свечи переносятся без клонов). Данные одни и те же
 fn main() {
     let t0 = 1_700_000_000_000;
-    let n = 300_000_000; // 300 миллионов трейдов
-    let batch = 1000; // 1000 трейдов на одну свечу
+    let n: usize = std::env::var("BENCH_TRADES").ok().and_then(|v| v.parse().ok()).unwrap_or(300_000_000); // по умолчанию 300 миллионов трейдов
+    let batch: usize = std::env::var("BENCH_BATCH").ok().and_then(|v| v.parse().ok()).unwrap_or(1000); // трейдов на одну свечу
     println!("Генерация {} трейдов ({} трейдов на свечу)...", n, batch);
     let trades: Vec<_> = (0..n)
         .map(|i| Trade {
@@ -17,8 +24,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: format!("t{}", i),
-            price: 100.0 + (i % 10) as f64,
-            amount: 1.0,
+            price: num(100.0 + (i % 10) as f64),
+            amount: num(1.0),
             side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
             timestamp: Utc.timestamp_millis_opt(t0 + ((i / batch) as i64) * 60_000).unwrap(),
         })
@@ -26,6 +33,15 @@ fn main() {
     println!("Агрегация...");
     let generator = CandleGenerator::default();
     let start = Instant::now();
+    let mut builder = LegacyBuilder::new(&generator.config, Timeframe::m1);
+    let mut baseline = Vec::new();
+    for trade in &trades {
+        baseline.extend(builder.push(trade));
+    }
+    baseline.extend(builder.flush());
+    let baseline_elapsed = start.elapsed();
+    let baseline_throughput = n as f64 / baseline_elapsed.as_secs_f64();
+    let start = Instant::now();
     let candles = generator.aggregate(trades.iter(), Timeframe::m1);
     let elapsed = start.elapsed();
     let candles_len = candles.len();
@@ -35,6 +51,33 @@ fn main() {
     println!("Трейдов на свечу: {}", batch);
     println!("Время агрегации: {:.3?}", elapsed);
     println!("Пропускная способность: {:.2} трейдов/сек", throughput);
+    assert_eq!(baseline.len(), candles_len);
+    println!(
+        "До (владеющие свечи): {:.3?}, {:.2} трейдов/сек (aggregate быстрее в {:.2} раза)",
+        baseline_elapsed,
+        baseline_throughput,
+        throughput / baseline_throughput
+    );
+    // cargo run --release --example bench --features parallel
+    #[cfg(feature = "parallel")]
+    {
+        let start = Instant::now();
+        let par = generator.par_aggregate(&trades, Timeframe::m1);
+        let par_elapsed = start.elapsed();
+        assert_eq!(par.len(), candles_len);
+        println!("Параллельно: {:.3?}, {:.2} трейдов/сек", par_elapsed, n as f64 / par_elapsed.as_secs_f64());
+    }
+    // Те же трейды столбцами (как из Parquet): без Trade и Candle на строку
+    let timestamps: Vec<i64> = trades.iter().map(|t| t.timestamp.timestamp_millis()).collect();
+    let prices: Vec<_> = trades.iter().map(|t| t.price).collect();
+    let amounts: Vec<_> = trades.iter().map(|t| t.amount).collect();
+    let sides: Vec<_> = trades.iter().map(|t| t.side).collect();
+    let columns = TradeColumns { instrument: &trades[0].instrument, timestamps: &timestamps, prices: &prices, amounts: &amounts, sides: &sides };
+    let start = Instant::now();
+    let columnar = generator.aggregate_columns(&columns, &Timeframe::m1).unwrap();
+    let columnar_elapsed = start.elapsed();
+    assert_eq!(columnar.len(), candles_len);
+    println!("Столбцами: {:.3?}, {:.2} трейдов/сек", columnar_elapsed, n as f64 / columnar_elapsed.as_secs_f64());
     // Запись результатов в файл
     let mut file = File::create("bench_output.txt").expect("cannot create bench_output.txt");
     writeln!(file, "trades={}", n).unwrap();
@@ -42,4 +85,85 @@ fn main() {
     writeln!(file, "batch={}", batch).unwrap();
     writeln!(file, "elapsed_secs={:.6}", elapsed_secs).unwrap();
     writeln!(file, "throughput={:.2}", throughput).unwrap();
-} 
\ No newline at end of file
+    writeln!(file, "baseline_throughput={:.2}", baseline_throughput).unwrap();
+} 
+/// Прежний горячий путь `CandleBuilder` для упорядоченного потока с конфигом по умолчанию
+mod legacy {
+    use candle_generator::{num, CandleConfig, Instrument, Num, Timeframe, Trade};
+    use chrono::{DateTime, Utc};
+    use std::collections::HashMap;
+
+    /// Свеча со своим `Instrument`, как до `Arc<Instrument>`
+    #[derive(Clone)]
+    #[allow(dead_code)]
+    pub struct LegacyCandle {
+        instrument: Instrument,
+        interval: Timeframe,
+        timestamp: DateTime<Utc>,
+        open: Num,
+        high: Num,
+        low: Num,
+        close: Num,
+        volume: Num,
+        trade_count: u64,
+        volume_usdt: Option<Num>,
+        custom: HashMap<String, f64>,
+    }
+
+    pub struct LegacyBuilder<'a> {
+        config: &'a CandleConfig,
+        timeframe: Timeframe,
+        current: Option<LegacyCandle>,
+        last_emitted: Option<LegacyCandle>,
+    }
+
+    impl<'a> LegacyBuilder<'a> {
+        pub fn new(config: &'a CandleConfig, timeframe: Timeframe) -> Self {
+            Self { config, timeframe, current: None, last_emitted: None }
+        }
+
+        pub fn push(&mut self, trade: &Trade) -> Vec<LegacyCandle> {
+            let mut out = Vec::new();
+            if !self.config.calendar.is_open(trade.timestamp) {
+                return out;
+            }
+            let ts = self.config.alignment.truncate(trade.timestamp, &self.timeframe);
+            let volume_usdt = (trade.instrument.pair.quote_id == "USDT").then_some(trade.price * trade.amount);
+            match &mut self.current {
+                Some(c) if c.timestamp == ts => {
+                    c.high = c.high.max(trade.price);
+                    c.low = c.low.min(trade.price);
+                    c.close = trade.price;
+                    c.volume += trade.amount;
+                    c.trade_count += 1;
+                    if let Some(vu) = volume_usdt {
+                        c.volume_usdt = Some(c.volume_usdt.unwrap_or(num(0.0)) + vu);
+                    }
+                }
+                _ => {
+                    out.extend(self.flush());
+                    self.current = Some(LegacyCandle {
+                        instrument: trade.instrument.clone(),
+                        interval: self.timeframe.clone(),
+                        timestamp: ts,
+                        open: trade.price,
+                        high: trade.price,
+                        low: trade.price,
+                        close: trade.price,
+                        volume: trade.amount,
+                        trade_count: 1,
+                        volume_usdt,
+                        custom: HashMap::new(),
+                    });
+                }
+            }
+            out
+        }
+
+        pub fn flush(&mut self) -> Option<LegacyCandle> {
+            let candle = self.current.take()?;
+            self.last_emitted = Some(candle.clone());
+            Some(candle)
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{num, CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
 use chrono::{Utc, TimeZone};
 use rand::seq::SliceRandom;
 
@@ -12,8 +12,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: format!("t{}", i),
-            price: 100.0 + (i % 10) as f64,
-            amount: 1.0,
+            price: num(100.0 + (i % 10) as f64),
+            amount: num(1.0),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(t0 + i * 60_000).unwrap(),
         })
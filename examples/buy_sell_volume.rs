@@ -1,27 +1,29 @@
-use candle_generator::{CandleGenerator, CandleConfig, CandleMetric, Timeframe, Trade, Instrument, Pair, MarketType, Side};
-use chrono::Utc;
-use std::collections::HashMap;
+use candle_generator::{num, num_to_f64, CandleGenerator, CandleConfig, CandleMetric, MergeRule, Timeframe, Trade, TradeRef, Instrument, Pair, MarketType, Side};
+use chrono::{TimeZone, Utc};
 
 struct BuySellVolume;
 impl CandleMetric for BuySellVolume {
-    fn update(&self, trade: &Trade, candle: &mut candle_generator::Candle) {
+    fn update(&self, trade: &TradeRef, candle: &mut candle_generator::Candle) {
         let buy = candle.custom.get("buy_volume").cloned().unwrap_or(0.0);
         let sell = candle.custom.get("sell_volume").cloned().unwrap_or(0.0);
         match trade.side {
             Side::Buy => {
-                candle.custom.insert("buy_volume".to_string(), buy + trade.amount);
+                candle.custom.insert("buy_volume".to_string(), buy + num_to_f64(trade.amount));
             }
             Side::Sell => {
-                candle.custom.insert("sell_volume".to_string(), sell + trade.amount);
+                candle.custom.insert("sell_volume".to_string(), sell + num_to_f64(trade.amount));
             }
             _ => {}
         }
     }
-    fn aggregate(&self, _src: &[candle_generator::Candle], _dst: &mut candle_generator::Candle) {}
+    fn aggregate(&self, src: &[candle_generator::Candle], dst: &mut candle_generator::Candle) {
+        MergeRule::Sum.apply("buy_volume", src, dst);
+        MergeRule::Sum.apply("sell_volume", src, dst);
+    }
 }
 
 fn main() {
-    let trades = vec![
+    let trades = [
         Trade {
             instrument: Instrument {
                 pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
@@ -29,8 +31,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t1".into(),
-            price: 50000.0,
-            amount: 0.1,
+            price: num(50000.0),
+            amount: num(0.1),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
@@ -41,8 +43,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t2".into(),
-            price: 50100.0,
-            amount: 0.2,
+            price: num(50100.0),
+            amount: num(0.2),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(1714000060000).unwrap(),
         },
@@ -1,5 +1,7 @@
-use candle_generator::{CandleGenerator, CandleConfig, CandleMetric, Timeframe, Trade, Instrument, Pair, MarketType, Side};
-use chrono::Utc;
+use candle_generator::{num, num_to_f64, CandleGenerator, CandleConfig, CandleMetric, Timeframe, Trade, TradeRef, Instrument, Pair, MarketType, Side};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
 // --- TRADE STATS STRUCTURE AND METRICS ---
 #[derive(Default)]
@@ -34,9 +36,9 @@ pub struct TradeStats {
 }
 
 impl TradeStats {
-    pub fn on_trade(&mut self, trade: &Trade) {
-        let price = trade.price;
-        let amount = trade.amount;
+    pub fn on_trade(&mut self, trade: &TradeRef) {
+        let price = num_to_f64(trade.price);
+        let amount = num_to_f64(trade.amount);
         let quote = price * amount;
         let ts = trade.timestamp.timestamp();
         if self.open.is_none() {
@@ -141,6 +143,8 @@ pub struct SuperCandleMetric {
     pub orders: Vec<OrderEvent>,
     pub orderbooks: Vec<OrderBookSnapshot>,
     pub futures: Vec<FuturesEvent>,
+    // `CandleMetric::update` получает `&self`: статистика трейдов по началу свечи
+    pub per_candle: Mutex<HashMap<DateTime<Utc>, TradeStats>>,
 }
 
 impl SuperCandleMetric {
@@ -148,7 +152,7 @@ impl SuperCandleMetric {
         Self::default()
     }
     pub fn on_trade(&mut self, trade: Trade) {
-        self.trade_stats.on_trade(&trade);
+        self.trade_stats.on_trade(&trade.view());
     }
     pub fn on_order(&mut self, order: OrderEvent) {
         self.orders.push(order);
@@ -165,13 +169,25 @@ impl SuperCandleMetric {
     }
 }
 
+impl CandleMetric for SuperCandleMetric {
+    fn update(&self, trade: &TradeRef, candle: &mut candle_generator::Candle) {
+        let mut per_candle = self.per_candle.lock().unwrap();
+        let stats = per_candle.entry(candle.timestamp).or_default();
+        stats.on_trade(trade);
+        stats.finalize(candle);
+    }
+    fn aggregate(&self, _src: &[candle_generator::Candle], _dst: &mut candle_generator::Candle) {
+        // TODO: сводить TradeStats младших свечей для цепочки агрегации
+    }
+}
+
 // --- STUB STRUCTS FOR EVENTS ---
 pub struct OrderEvent {/* TODO: fields for order events */}
 pub struct OrderBookSnapshot {/* TODO: fields for orderbook snapshot */}
 pub struct FuturesEvent {/* TODO: fields for futures events */}
 
 fn main() {
-    let trades = vec![
+    let trades = [
         Trade {
             instrument: Instrument {
                 pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
@@ -179,8 +195,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t1".into(),
-            price: 100.0,
-            amount: 1.0,
+            price: num(100.0),
+            amount: num(1.0),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
@@ -191,8 +207,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t2".into(),
-            price: 110.0,
-            amount: 2.0,
+            price: num(110.0),
+            amount: num(2.0),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000060000).unwrap(),
         },
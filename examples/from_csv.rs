@@ -1,20 +1,22 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+// cargo run --example from_csv --features csv
+use candle_generator::{CandleGenerator, CsvTradeParser, Timeframe, TradeParser};
 use std::fs::File;
-use csv::Reader;
-use chrono::Utc;
-use chrono::TimeZone;
 
-fn main() {
-    let file = File::open("trades.csv").expect("cannot open trades.csv");
-    let mut rdr = Reader::from_reader(file);
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Заголовок: timestamp,exchange,base_id,quote_id,market_type,id,price,amount,side (время в мс)
+    let parser = CsvTradeParser::default();
+    let file = File::open("trades.csv")?;
     let mut trades = Vec::new();
-    for result in rdr.deserialize() {
-        let trade: Trade = result.expect("invalid trade row");
-        trades.push(trade);
+    for trade in parser.parse(Box::new(file)) {
+        match trade {
+            Ok(trade) => trades.push(trade),
+            Err(e) => eprintln!("skip: {}", e),
+        }
     }
     let generator = CandleGenerator::default();
     let candles = generator.aggregate(trades.iter(), Timeframe::m1);
     for candle in candles {
         println!("{:?}", candle);
     }
-} 
\ No newline at end of file
+    Ok(())
+}
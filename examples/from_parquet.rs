@@ -1,49 +1,25 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
-use polars::prelude::*;
-use chrono::Utc;
-use chrono::TimeZone;
+// cargo run --example from_parquet --features parquet
+use candle_generator::{read_trades_filtered, CandleGenerator, Instrument, MarketType, Pair, Timeframe, TradeColumnMapping, TradeFilter};
+use chrono::{TimeZone, Utc};
 
-fn main() -> polars::prelude::PolarsResult<()> {
-    let df = LazyFrame::scan_parquet("trades.parquet", Default::default())?.collect()?;
-    let mut trades = Vec::new();
-    for row in df.iter_rows() {
-        let (timestamp, exchange, base_id, quote_id, market_type, id, price, amount, side): (i64, String, String, String, String, String, f64, f64, String) = (
-            row[0].try_extract()?,
-            row[1].try_extract()?,
-            row[2].try_extract()?,
-            row[3].try_extract()?,
-            row[4].try_extract()?,
-            row[5].try_extract()?,
-            row[6].try_extract()?,
-            row[7].try_extract()?,
-            row[8].try_extract()?,
-        );
-        trades.push(Trade {
-            instrument: Instrument {
-                pair: Pair { base_id, quote_id },
-                exchange,
-                market_type: match market_type.as_str() {
-                    "Spot" => MarketType::Spot,
-                    "Futures" => MarketType::Futures,
-                    "Margin" => MarketType::Margin,
-                    _ => MarketType::Unknown,
-                },
-            },
-            id,
-            price,
-            amount,
-            side: match side.as_str() {
-                "Buy" => Side::Buy,
-                "Sell" => Side::Sell,
-                _ => Side::Unknown,
-            },
-            timestamp: Utc.timestamp_millis_opt(timestamp).unwrap(),
-        });
-    }
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Колонки: timestamp, price, amount, side, id, exchange, base_id, quote_id, market_type
+    let mapping = TradeColumnMapping::default();
+    // Читаются только row group'ы с нужным инструментом и временем
+    let filter = TradeFilter {
+        from: Some(Utc.with_ymd_and_hms(2024, 4, 25, 0, 0, 0).unwrap()),
+        to: Some(Utc.with_ymd_and_hms(2024, 4, 26, 0, 0, 0).unwrap()),
+        instrument: Some(Instrument {
+            pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
+            exchange: "binance".into(),
+            market_type: MarketType::Spot,
+        }),
+    };
+    let trades = read_trades_filtered("trades.parquet", &mapping, &filter)?;
     let generator = CandleGenerator::default();
     let candles = generator.aggregate(trades.iter(), Timeframe::m1);
     for candle in candles {
         println!("{:?}", candle);
     }
     Ok(())
-} 
\ No newline at end of file
+}
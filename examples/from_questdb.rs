@@ -1,4 +1,5 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+// cargo run --example from_questdb --features http-examples
+use candle_generator::{CandleGenerator, Num, Timeframe, Trade, Instrument, Pair, MarketType, Side};
 use chrono::Utc;
 use chrono::TimeZone;
 use reqwest::blocking::get;
@@ -18,8 +19,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let quote_id = row[3].to_string();
         let market_type = row[4].to_string();
         let id = row[5].to_string();
-        let price: f64 = row[6].parse()?;
-        let amount: f64 = row[7].parse()?;
+        let price: Num = row[6].parse()?;
+        let amount: Num = row[7].parse()?;
         let side = row[8].to_string();
         trades.push(Trade {
             instrument: Instrument {
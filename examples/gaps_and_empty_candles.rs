@@ -1,10 +1,10 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{num, CandleConfig, CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
 use chrono::{Utc, TimeZone};
 
 fn main() {
     let t0 = 1_700_000_000_000;
     let t5 = t0 + 5 * 60_000;
-    let trades = vec![
+    let trades = [
         Trade {
             instrument: Instrument {
                 pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
@@ -12,8 +12,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t1".into(),
-            price: 100.0,
-            amount: 1.0,
+            price: num(100.0),
+            amount: num(1.0),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(t0).unwrap(),
         },
@@ -24,8 +24,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t2".into(),
-            price: 110.0,
-            amount: 2.0,
+            price: num(110.0),
+            amount: num(2.0),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(t5).unwrap(),
         },
@@ -35,4 +35,10 @@ fn main() {
     for candle in m1 {
         println!("{:?}", candle);
     }
+    // С заполнением пропусков: свечи за 00:01..00:04 с synthetic = true
+    let config = CandleConfig { fill_gaps: true, ..Default::default() };
+    let generator = CandleGenerator { config };
+    for candle in generator.aggregate(trades.iter(), Timeframe::m1) {
+        println!("{:?}", candle);
+    }
 } 
\ No newline at end of file
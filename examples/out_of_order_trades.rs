@@ -1,10 +1,10 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
-use chrono::{Utc, TimeZone};
+use candle_generator::{num, CandleConfig, CandleGenerator, LatePolicy, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use chrono::{Duration, Utc, TimeZone};
 
 fn main() {
     let t0 = 1_700_000_000_000;
     let t1 = t0 + 60_000;
-    let trades = vec![
+    let trades = [
         Trade {
             instrument: Instrument {
                 pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
@@ -12,8 +12,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t1".into(),
-            price: 100.0,
-            amount: 1.0,
+            price: num(100.0),
+            amount: num(1.0),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(t0).unwrap(),
         },
@@ -24,8 +24,8 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t2".into(),
-            price: 110.0,
-            amount: 2.0,
+            price: num(110.0),
+            amount: num(2.0),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(t1).unwrap(),
         },
@@ -36,16 +36,18 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t3".into(),
-            price: 120.0,
-            amount: 0.5,
+            price: num(120.0),
+            amount: num(0.5),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(t0 + 10_000).unwrap(), // out-of-order
         },
     ];
-    let generator = CandleGenerator::default();
-    let mut m1 = generator.aggregate(trades.iter(), Timeframe::m1);
-    m1.sort_by_key(|c| c.timestamp);
+    // Поздний трейд вливается в уже выданную свечу (до 5 минут назад)
+    let config = CandleConfig { late_policy: LatePolicy::Merge(Duration::minutes(5)), ..Default::default() };
+    let generator = CandleGenerator { config };
+    let (m1, stats) = generator.aggregate_with_stats(trades.iter(), Timeframe::m1);
     for candle in m1 {
         println!("{:?}", candle);
     }
+    println!("{:?}", stats);
 } 
\ No newline at end of file
@@ -1,42 +1,40 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
-use chrono::Utc;
+// cargo run --example to_csv --features csv
+use candle_generator::{num, CandleGenerator, CandleSerializer, CsvCandleSerializer, Instrument, MarketType, Pair, Side, Timeframe, TimestampFormat, Trade};
+use chrono::{TimeZone, Utc};
 use std::fs::File;
-use csv::Writer;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let trades = vec![
+    let instrument = Instrument {
+        pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
+        exchange: "binance".into(),
+        market_type: MarketType::Spot,
+    };
+    let trades = [
         Trade {
-            instrument: Instrument {
-                pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
-                exchange: "binance".into(),
-                market_type: MarketType::Spot,
-            },
+            instrument: instrument.clone(),
             id: "t1".into(),
-            price: 50000.0,
-            amount: 0.1,
+            price: num(50000.0),
+            amount: num(0.1),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
         Trade {
-            instrument: Instrument {
-                pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
-                exchange: "binance".into(),
-                market_type: MarketType::Spot,
-            },
+            instrument,
             id: "t2".into(),
-            price: 50100.0,
-            amount: 0.2,
+            price: num(50100.0),
+            amount: num(0.2),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(1714000060000).unwrap(),
         },
     ];
     let generator = CandleGenerator::default();
     let candles = generator.aggregate(trades.iter(), Timeframe::m1);
-    let mut wtr = Writer::from_writer(File::create("candles.csv")?);
-    for candle in candles {
-        wtr.serialize(candle)?;
+    let format = CsvCandleSerializer { timestamp: TimestampFormat::Rfc3339, ..CsvCandleSerializer::default() };
+    let mut writer = format.writer(Box::new(File::create("candles.csv")?));
+    for candle in &candles {
+        writer.write(candle)?;
     }
-    wtr.flush()?;
+    writer.finish()?;
     println!("Candles exported to candles.csv");
     Ok(())
-} 
\ No newline at end of file
+}
@@ -1,11 +1,11 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{num, CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side};
 use chrono::Utc;
 use chrono::TimeZone;
 use std::fs::File;
 use std::io::Write;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let trades = vec![
+    let trades = [
         Trade {
             instrument: Instrument {
                 pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
@@ -13,8 +13,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 market_type: MarketType::Spot,
             },
             id: "t1".into(),
-            price: 50000.0,
-            amount: 0.1,
+            price: num(50000.0),
+            amount: num(0.1),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
@@ -25,8 +25,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 market_type: MarketType::Spot,
             },
             id: "t2".into(),
-            price: 50100.0,
-            amount: 0.2,
+            price: num(50100.0),
+            amount: num(0.2),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(1714000060000).unwrap(),
         },
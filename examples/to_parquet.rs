@@ -1,64 +1,35 @@
-use candle_generator::{CandleGenerator, Timeframe, Trade, Instrument, Pair, MarketType, Side, Candle};
-use chrono::{Utc, TimeZone};
-use polars::prelude::*;
+// cargo run --example to_parquet --features parquet
+use candle_generator::{num, write_candles_with_options, CandleGenerator, Compression, Instrument, MarketType, Pair, ParquetWriteOptions, Side, Timeframe, Trade};
+use chrono::{TimeZone, Utc};
 
-fn main() -> polars::prelude::PolarsResult<()> {
-    let trades = vec![
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let instrument = Instrument {
+        pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
+        exchange: "binance".into(),
+        market_type: MarketType::Spot,
+    };
+    let trades = [
         Trade {
-            instrument: Instrument {
-                pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
-                exchange: "binance".into(),
-                market_type: MarketType::Spot,
-            },
+            instrument: instrument.clone(),
             id: "t1".into(),
-            price: 50000.0,
-            amount: 0.1,
+            price: num(50000.0),
+            amount: num(0.1),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
         Trade {
-            instrument: Instrument {
-                pair: Pair { base_id: "BTC".into(), quote_id: "USDT".into() },
-                exchange: "binance".into(),
-                market_type: MarketType::Spot,
-            },
+            instrument,
             id: "t2".into(),
-            price: 50100.0,
-            amount: 0.2,
+            price: num(50100.0),
+            amount: num(0.2),
             side: Side::Sell,
             timestamp: Utc.timestamp_millis_opt(1714000060000).unwrap(),
         },
     ];
     let generator = CandleGenerator::default();
     let candles = generator.aggregate(trades.iter(), Timeframe::m1);
-    // Преобразуем свечи в DataFrame
-    let timestamps: Vec<_> = candles.iter().map(|c| c.timestamp.timestamp_millis()).collect();
-    let exchanges: Vec<_> = candles.iter().map(|c| c.instrument.exchange.clone()).collect();
-    let base_ids: Vec<_> = candles.iter().map(|c| c.instrument.pair.base_id.clone()).collect();
-    let quote_ids: Vec<_> = candles.iter().map(|c| c.instrument.pair.quote_id.clone()).collect();
-    let intervals: Vec<_> = candles.iter().map(|c| format!("{:?}", c.interval)).collect();
-    let opens: Vec<_> = candles.iter().map(|c| c.open).collect();
-    let highs: Vec<_> = candles.iter().map(|c| c.high).collect();
-    let lows: Vec<_> = candles.iter().map(|c| c.low).collect();
-    let closes: Vec<_> = candles.iter().map(|c| c.close).collect();
-    let volumes: Vec<_> = candles.iter().map(|c| c.volume).collect();
-    let trade_counts: Vec<_> = candles.iter().map(|c| c.trade_count as i64).collect();
-    let volume_usdts: Vec<_> = candles.iter().map(|c| c.volume_usdt.unwrap_or(0.0)).collect();
-    let df = DataFrame::new(vec![
-        Series::new("timestamp", timestamps),
-        Series::new("exchange", exchanges),
-        Series::new("base_id", base_ids),
-        Series::new("quote_id", quote_ids),
-        Series::new("interval", intervals),
-        Series::new("open", opens),
-        Series::new("high", highs),
-        Series::new("low", lows),
-        Series::new("close", closes),
-        Series::new("volume", volumes),
-        Series::new("trade_count", trade_counts),
-        Series::new("volume_usdt", volume_usdts),
-    ])?;
-    df.write_parquet("candles.parquet", ParquetWriteOptions::default())?;
+    let options = ParquetWriteOptions { row_group_size: 100_000, compression: Compression::SNAPPY };
+    write_candles_with_options("candles.parquet", &candles, &options)?;
     println!("Candles exported to candles.parquet");
     Ok(())
-} 
\ No newline at end of file
+}
@@ -1,8 +1,8 @@
-use candle_generator::{CandleGenerator, CandleConfig, UsdtVolumeSource, Timeframe, Trade, Instrument, Pair, MarketType, Side};
+use candle_generator::{num, CandleGenerator, CandleConfig, UsdtVolumeSource, Timeframe, Trade, Instrument, Pair, MarketType, Side};
 use chrono::{Utc, TimeZone};
 
 fn main() {
-    let trades = vec![
+    let trades = [
         Trade {
             instrument: Instrument {
                 pair: Pair { base_id: "ETH".into(), quote_id: "BTC".into() },
@@ -10,31 +10,31 @@ fn main() {
                 market_type: MarketType::Spot,
             },
             id: "t1".into(),
-            price: 0.06, // 0.06 BTC за 1 ETH
-            amount: 2.0,
+            price: num(0.06), // 0.06 BTC за 1 ETH
+            amount: num(2.0),
             side: Side::Buy,
             timestamp: Utc.timestamp_millis_opt(1714000000000).unwrap(),
         },
     ];
     // Fixed rate (BTC/USDT = 50000)
-    let mut config = CandleConfig::default();
-    config.volume_in_usdt = UsdtVolumeSource::Fixed(50000.0);
+    let config = CandleConfig { volume_in_usdt: UsdtVolumeSource::Fixed(50000.0), ..Default::default() };
     let gen = CandleGenerator { config };
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     println!("Fixed: volume_usdt = {:?}", candles[0].volume_usdt);
 
     // Callback (динамический курс)
-    let mut config = CandleConfig::default();
-    config.volume_in_usdt = UsdtVolumeSource::Callback(Box::new(|pair, _| {
-        if pair.quote_id == "BTC" { Some(50000.0) } else { None }
-    }));
+    let config = CandleConfig {
+        volume_in_usdt: UsdtVolumeSource::Callback(Box::new(|pair, _| {
+            if pair.quote_id == "BTC" { Some(50000.0) } else { None }
+        })),
+        ..Default::default()
+    };
     let gen = CandleGenerator { config };
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     println!("Callback: volume_usdt = {:?}", candles[0].volume_usdt);
 
     // None (не считать)
-    let mut config = CandleConfig::default();
-    config.volume_in_usdt = UsdtVolumeSource::None;
+    let config = CandleConfig { volume_in_usdt: UsdtVolumeSource::None, ..Default::default() };
     let gen = CandleGenerator { config };
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     println!("None: volume_usdt = {:?}", candles[0].volume_usdt);
@@ -0,0 +1,107 @@
+use crate::{next_bucket, truncate_to_tf, Timeframe};
+use chrono::{DateTime, Datelike, Duration, DurationRound, LocalResult, Months, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+
+/// Начало отсчёта для дневных и старших бакетов: часовой пояс биржи (IANA) и начало торговой сессии.
+///
+/// `h4`, `d1`, `w1` и `M1` выравниваются по локальному торговому дню: например, Мосбиржа —
+/// `Europe/Moscow` с 10:00, CME — `America/Chicago` с −7 ч (17:00 накануне). Переходы на летнее
+/// время учитываются: в такие дни бакет длиннее или короче на час. Остальные таймфреймы — по UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    pub tz: Tz,
+    /// Смещение начала торгового дня от локальной полуночи его даты; отрицательное — сессия
+    /// начинается накануне (важно для `w1` и `M1`: неделя CME начинается в воскресенье вечером)
+    pub session_start: Duration,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Self { tz: Tz::UTC, session_start: Duration::zero() }
+    }
+}
+
+impl Alignment {
+    pub fn new(tz: Tz, session_start: Duration) -> Self {
+        Self { tz, session_start }
+    }
+
+    fn is_utc(&self) -> bool {
+        self.tz == Tz::UTC && self.session_start.is_zero()
+    }
+
+    fn is_aligned(tf: &Timeframe) -> bool {
+        matches!(tf, Timeframe::h4 | Timeframe::d1 | Timeframe::w1 | Timeframe::M1)
+    }
+
+    /// Начало бакета, в который попадает `ts`
+    pub fn truncate(&self, ts: DateTime<Utc>, tf: &Timeframe) -> DateTime<Utc> {
+        if self.is_utc() || !Self::is_aligned(tf) {
+            return truncate_to_tf(ts, tf);
+        }
+        self.resolve(self.local_bucket(ts, tf) + self.session_start)
+    }
+
+    /// Ширина бакета в миллисекундах, если его начало — просто `ts` с точностью до кратного ширине
+    /// от Unix epoch (UTC-сетка до `d1` включительно и `Custom`); `None` — нужен `truncate`
+    pub(crate) fn fixed_step_ms(&self, tf: &Timeframe) -> Option<i64> {
+        if !self.is_utc() && Self::is_aligned(tf) {
+            return None;
+        }
+        match tf {
+            Timeframe::w1 | Timeframe::M1 | Timeframe::Bar(_) => None,
+            _ => Some(tf.duration().num_milliseconds()).filter(|ms| *ms > 0),
+        }
+    }
+
+    /// Начало следующего бакета после бакета, в который попадает `ts`
+    pub fn next(&self, ts: DateTime<Utc>, tf: &Timeframe) -> DateTime<Utc> {
+        if self.is_utc() || !Self::is_aligned(tf) {
+            return next_bucket(ts, tf);
+        }
+        let start = self.local_bucket(ts, tf);
+        let next = match tf {
+            Timeframe::h4 => start + Duration::hours(4),
+            Timeframe::w1 => start + Duration::weeks(1),
+            Timeframe::M1 => start + Months::new(1),
+            _ => start + Duration::days(1),
+        };
+        self.resolve(next + self.session_start)
+    }
+
+    /// Номинальное начало бакета в локальном времени, сдвинутом так, что сессия начинается в полночь
+    fn local_bucket(&self, ts: DateTime<Utc>, tf: &Timeframe) -> NaiveDateTime {
+        let shifted = ts.with_timezone(&self.tz).naive_local() - self.session_start;
+        let date = shifted.date();
+        match tf {
+            Timeframe::h4 => date.and_time(NaiveTime::MIN) + Duration::hours((shifted.hour() / 4 * 4) as i64),
+            Timeframe::w1 => (date - Duration::days(date.weekday().num_days_from_monday() as i64)).and_time(NaiveTime::MIN),
+            Timeframe::M1 => (date - Duration::days(date.day0() as i64)).and_time(NaiveTime::MIN),
+            _ => date.and_time(NaiveTime::MIN),
+        }
+    }
+
+    fn resolve(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        resolve_local(&self.tz, local)
+    }
+}
+
+/// Локальное время биржи → UTC: при неоднозначности (осенний переход) — более раннее,
+/// для несуществующего (весенний переход) — первый момент после разрыва
+pub(crate) fn resolve_local(tz: &Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) => t.with_timezone(&Utc),
+        LocalResult::Ambiguous(a, _) => a.with_timezone(&Utc),
+        LocalResult::None => {
+            // Разрывы начинаются и кончаются на границах 15 минут; шаг от границы, а не от `local`,
+            // иначе 02:07 в разрыве 02:00–03:00 даёт 03:07 вместо 03:00
+            let mut probe = local.duration_trunc(Duration::minutes(15)).unwrap_or(local);
+            loop {
+                probe += Duration::minutes(15);
+                if let Some(t) = tz.from_local_datetime(&probe).earliest() {
+                    return t.with_timezone(&Utc);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use crate::format::CANDLE_COLUMNS;
+use crate::{num_to_f64, try_num, Candle, Instrument, MarketType, Num, Pair, Side, Timeframe, Trade};
+use arrow_array::types::ArrowPrimitiveType;
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float64Array, Int64Array, PrimitiveArray, RecordBatch, StringArray, TimestampNanosecondArray,
+    UInt32Array, UInt64Array,
+};
+use arrow_cast::cast;
+use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// Откуда брать инструмент трейдов `RecordBatch`
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstrumentMapping {
+    /// Весь батч — один инструмент
+    Fixed(Instrument),
+    /// Имена колонок; без `market_type` — `MarketType::Unknown`
+    Columns { exchange: String, base: String, quote: String, market_type: Option<String> },
+}
+
+/// Имена колонок `RecordBatch` с трейдами.
+///
+/// Время — `Timestamp` любой единицы или целое число миллисекунд; цена и объём — любой числовой
+/// тип или строки с числами. Сторона — строки `buy`/`sell` (`b`/`s`, регистр не важен) или `bool`
+/// is_buyer_maker (`true` — продажа)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeColumnMapping {
+    pub timestamp: String,
+    pub price: String,
+    pub amount: String,
+    /// `None` — `Side::Unknown`
+    pub side: Option<String>,
+    /// `None` — id = номер строки в батче
+    pub id: Option<String>,
+    pub instrument: InstrumentMapping,
+}
+
+impl Default for TradeColumnMapping {
+    /// Колонки названы как поля `Trade` и `Instrument`
+    fn default() -> Self {
+        Self {
+            timestamp: "timestamp".to_string(),
+            price: "price".to_string(),
+            amount: "amount".to_string(),
+            side: Some("side".to_string()),
+            id: Some("id".to_string()),
+            instrument: InstrumentMapping::Columns {
+                exchange: "exchange".to_string(),
+                base: "base_id".to_string(),
+                quote: "quote_id".to_string(),
+                market_type: Some("market_type".to_string()),
+            },
+        }
+    }
+}
+
+/// Трейды из `RecordBatch` по `mapping`. Null в обязательной колонке — ошибка с номером строки.
+/// Значения читаются прямо из массивов arrow; инструмент собирается заново, только когда колонки
+/// инструмента отличаются от предыдущей строки (для `Fixed` — ни разу)
+pub fn trades_from_batch(batch: &RecordBatch, mapping: &TradeColumnMapping) -> Result<Vec<Trade>, ArrowError> {
+    let timestamps = timestamp_column(batch, &mapping.timestamp)?;
+    let prices = float_column(batch, &mapping.price)?;
+    let amounts = float_column(batch, &mapping.amount)?;
+    let sides = mapping.side.as_deref().map(|name| side_column(batch, name)).transpose()?;
+    let ids = mapping.id.as_deref().map(|name| string_column(batch, name)).transpose()?;
+    let instruments = match &mapping.instrument {
+        InstrumentMapping::Fixed(instrument) => InstrumentSource::Fixed(instrument),
+        InstrumentMapping::Columns { exchange, base, quote, market_type } => InstrumentSource::Columns(Box::new(InstrumentColumns {
+            exchange: string_column(batch, exchange)?,
+            base: string_column(batch, base)?,
+            quote: string_column(batch, quote)?,
+            market_type: market_type.as_deref().map(|name| string_column(batch, name)).transpose()?,
+        })),
+    };
+
+    let mut trades: Vec<Trade> = Vec::with_capacity(batch.num_rows());
+    for row in 0..batch.num_rows() {
+        let instrument = match (&instruments, trades.last()) {
+            (InstrumentSource::Fixed(instrument), _) => (*instrument).clone(),
+            (InstrumentSource::Columns(columns), Some(prev)) if columns.same_as_previous(row)? => prev.instrument.clone(),
+            (InstrumentSource::Columns(columns), _) => columns.instrument(row)?,
+        };
+        trades.push(Trade {
+            instrument,
+            id: match &ids {
+                Some(col) => col.required(row)?.to_string(),
+                None => row.to_string(),
+            },
+            price: required_num(&prices, row)?,
+            amount: required_num(&amounts, row)?,
+            side: match &sides {
+                Some(col) => col.required(row)?,
+                None => Side::Unknown,
+            },
+            timestamp: timestamps.required(row)?,
+        });
+    }
+    Ok(trades)
+}
+
+/// Инструмент трейдов батча: общий или по колонкам
+enum InstrumentSource<'m> {
+    Fixed(&'m Instrument),
+    Columns(Box<InstrumentColumns>),
+}
+
+/// Колонки инструмента трейдов (`InstrumentMapping::Columns`)
+struct InstrumentColumns {
+    exchange: Column<StringArray>,
+    base: Column<StringArray>,
+    quote: Column<StringArray>,
+    market_type: Option<Column<StringArray>>,
+}
+
+impl InstrumentColumns {
+    fn instrument(&self, row: usize) -> Result<Instrument, ArrowError> {
+        Ok(Instrument {
+            pair: Pair { base_id: self.base.required(row)?.to_string(), quote_id: self.quote.required(row)?.to_string() },
+            exchange: self.exchange.required(row)?.to_string(),
+            market_type: match &self.market_type {
+                Some(col) => MarketType::from(col.required(row)?),
+                None => MarketType::Unknown,
+            },
+        })
+    }
+
+    /// Те же значения, что в строке `row - 1` (null — ошибка, как в `instrument`)
+    fn same_as_previous(&self, row: usize) -> Result<bool, ArrowError> {
+        let same = |col: &Column<StringArray>| Ok::<_, ArrowError>(col.required(row)? == col.required(row - 1)?);
+        Ok(same(&self.exchange)? && same(&self.base)? && same(&self.quote)? && self.market_type.as_ref().map_or(Ok(true), same)?)
+    }
+}
+
+/// Схема `candles_to_batch`: фиксированные колонки (время — наносекунды UTC, цены и объёмы —
+/// `Float64`, `volume_usdt` — nullable) и nullable `Float64` на каждую кастомную метрику
+/// в порядке `custom`
+pub fn candle_schema<S: AsRef<str>>(custom: &[S]) -> Schema {
+    let mut fields: Vec<Field> = CANDLE_COLUMNS
+        .iter()
+        .map(|&name| {
+            let data_type = match name {
+                "timestamp" => DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+                "exchange" | "base_id" | "quote_id" | "market_type" | "interval" => DataType::Utf8,
+                "trade_count" => DataType::UInt64,
+                "revision" => DataType::UInt32,
+                "incomplete" | "synthetic" => DataType::Boolean,
+                _ => DataType::Float64,
+            };
+            Field::new(name, data_type, name == "volume_usdt")
+        })
+        .collect();
+    fields.extend(custom.iter().map(|name| Field::new(name.as_ref(), DataType::Float64, true)));
+    Schema::new(fields)
+}
+
+/// Свечи в `RecordBatch` со схемой `candle_schema`. Кастомные метрики — по колонке на ключ
+/// (объединение по всем свечам, по алфавиту), null у свечей без этого ключа. Ключ, совпадающий
+/// с фиксированной колонкой, — ошибка
+pub fn candles_to_batch(candles: &[Candle]) -> Result<RecordBatch, ArrowError> {
+    let custom: BTreeSet<&str> = candles.iter().flat_map(|c| c.custom.keys().map(String::as_str)).collect();
+    if let Some(name) = custom.iter().find(|name| CANDLE_COLUMNS.contains(name)) {
+        return Err(ArrowError::SchemaError(format!("custom metric {:?} clashes with a candle column", name)));
+    }
+    let custom: Vec<&str> = custom.into_iter().collect();
+    let strings = |f: fn(&Candle) -> String| Arc::new(StringArray::from_iter_values(candles.iter().map(f))) as ArrayRef;
+    let floats = |f: fn(&Candle) -> f64| Arc::new(Float64Array::from_iter_values(candles.iter().map(f))) as ArrayRef;
+    let flags = |f: fn(&Candle) -> bool| Arc::new(BooleanArray::from(candles.iter().map(f).collect::<Vec<_>>())) as ArrayRef;
+    let timestamps = candles
+        .iter()
+        .map(|c| {
+            c.timestamp
+                .timestamp_nanos_opt()
+                .ok_or_else(|| ArrowError::ComputeError(format!("timestamp {} out of nanosecond range", c.timestamp)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut columns: Vec<ArrayRef> = vec![
+        strings(|c| c.instrument.exchange.clone()),
+        strings(|c| c.instrument.pair.base_id.clone()),
+        strings(|c| c.instrument.pair.quote_id.clone()),
+        strings(|c| c.instrument.market_type.to_string()),
+        strings(|c| c.interval.to_string()),
+        Arc::new(TimestampNanosecondArray::from(timestamps).with_timezone("UTC")),
+        floats(|c| num_to_f64(c.open)),
+        floats(|c| num_to_f64(c.high)),
+        floats(|c| num_to_f64(c.low)),
+        floats(|c| num_to_f64(c.close)),
+        floats(|c| num_to_f64(c.volume)),
+        Arc::new(UInt64Array::from_iter_values(candles.iter().map(|c| c.trade_count))),
+        Arc::new(Float64Array::from(candles.iter().map(|c| c.volume_usdt.map(num_to_f64)).collect::<Vec<_>>())),
+        flags(|c| c.incomplete),
+        Arc::new(UInt32Array::from_iter_values(candles.iter().map(|c| c.revision))),
+        flags(|c| c.synthetic),
+    ];
+    for name in &custom {
+        columns.push(Arc::new(Float64Array::from(candles.iter().map(|c| c.custom.get(*name).copied()).collect::<Vec<_>>())));
+    }
+    RecordBatch::try_new(Arc::new(candle_schema(&custom)), columns)
+}
+
+/// Обратно к `candles_to_batch`: колонки вне фиксированного набора читаются как кастомные метрики.
+/// Подряд идущие свечи одного инструмента делят `Arc<Instrument>`
+pub fn candles_from_batch(batch: &RecordBatch) -> Result<Vec<Candle>, ArrowError> {
+    let instruments = InstrumentColumns {
+        exchange: string_column(batch, "exchange")?,
+        base: string_column(batch, "base_id")?,
+        quote: string_column(batch, "quote_id")?,
+        market_type: Some(string_column(batch, "market_type")?),
+    };
+    let interval = string_column(batch, "interval")?;
+    let timestamps = timestamp_column(batch, "timestamp")?;
+    let [open, high, low, close, volume, volume_usdt] =
+        ["open", "high", "low", "close", "volume", "volume_usdt"].map(|name| float_column(batch, name));
+    let (open, high, low, close, volume, volume_usdt) = (open?, high?, low?, close?, volume?, volume_usdt?);
+    let trade_count = typed_column::<UInt64Array>(batch, "trade_count", &DataType::UInt64)?;
+    let revision = typed_column::<UInt32Array>(batch, "revision", &DataType::UInt32)?;
+    let incomplete = typed_column::<BooleanArray>(batch, "incomplete", &DataType::Boolean)?;
+    let synthetic = typed_column::<BooleanArray>(batch, "synthetic", &DataType::Boolean)?;
+    let custom = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.name())
+        .filter(|name| !CANDLE_COLUMNS.contains(&name.as_str()))
+        .map(|name| Ok((name.clone(), float_column(batch, name)?)))
+        .collect::<Result<Vec<_>, ArrowError>>()?;
+
+    let mut candles: Vec<Candle> = Vec::with_capacity(batch.num_rows());
+    for row in 0..batch.num_rows() {
+        let instrument = match candles.last() {
+            Some(prev) if instruments.same_as_previous(row)? => prev.instrument.clone(),
+            _ => Arc::new(instruments.instrument(row)?),
+        };
+        let interval: Timeframe = interval
+            .required(row)?
+            .parse()
+            .map_err(|e| ArrowError::ParseError(format!("column interval, row {}: {}", row, e)))?;
+        candles.push(Candle {
+            instrument,
+            interval,
+            timestamp: timestamps.required(row)?,
+            open: required_num(&open, row)?,
+            high: required_num(&high, row)?,
+            low: required_num(&low, row)?,
+            close: required_num(&close, row)?,
+            volume: required_num(&volume, row)?,
+            trade_count: trade_count.required(row)?,
+            volume_usdt: volume_usdt.get(row).map(|v| finite(&volume_usdt, row, v)).transpose()?,
+            custom: custom
+                .iter()
+                .filter_map(|(name, col)| col.get(row).map(|v| (name.clone(), v)))
+                .collect::<HashMap<_, _>>(),
+            incomplete: incomplete.required(row)?,
+            revision: revision.required(row)?,
+            synthetic: synthetic.required(row)?,
+        });
+    }
+    Ok(candles)
+}
+
+/// Значение строки типизированного массива arrow (без проверки на null)
+pub(crate) trait ValueAt: Array {
+    type Value<'a>
+    where
+        Self: 'a;
+    fn value_at(&self, row: usize) -> Self::Value<'_>;
+}
+
+impl<T: ArrowPrimitiveType> ValueAt for PrimitiveArray<T> {
+    type Value<'a> = T::Native;
+    fn value_at(&self, row: usize) -> T::Native {
+        self.value(row)
+    }
+}
+
+impl ValueAt for StringArray {
+    type Value<'a> = &'a str;
+    fn value_at(&self, row: usize) -> &str {
+        self.value(row)
+    }
+}
+
+impl ValueAt for BooleanArray {
+    type Value<'a> = bool;
+    fn value_at(&self, row: usize) -> bool {
+        self.value(row)
+    }
+}
+
+/// Колонка после приведения типа; значения читаются из массива по строке, без копирования
+pub(crate) struct Column<A> {
+    name: String,
+    pub(crate) array: A,
+}
+
+impl<A: ValueAt> Column<A> {
+    /// `None` — null
+    pub(crate) fn get(&self, row: usize) -> Option<A::Value<'_>> {
+        self.array.is_valid(row).then(|| self.array.value_at(row))
+    }
+
+    fn required(&self, row: usize) -> Result<A::Value<'_>, ArrowError> {
+        self.get(row)
+            .ok_or_else(|| ArrowError::InvalidArgumentError(format!("column {}, row {}: unexpected null", self.name, row)))
+    }
+}
+
+/// Обязательное число; NaN и бесконечности — ошибка, а не молчаливый 0 под `decimal`
+fn required_num(col: &Column<Float64Array>, row: usize) -> Result<Num, ArrowError> {
+    finite(col, row, col.required(row)?)
+}
+
+fn finite(col: &Column<Float64Array>, row: usize, v: f64) -> Result<Num, ArrowError> {
+    try_num(v).ok_or_else(|| ArrowError::InvalidArgumentError(format!("column {}, row {}: {} is not a finite number", col.name, row, v)))
+}
+
+fn missing(name: &str) -> ArrowError {
+    ArrowError::SchemaError(format!("column {:?} not found", name))
+}
+
+fn array<'b>(batch: &'b RecordBatch, name: &str) -> Result<&'b ArrayRef, ArrowError> {
+    batch.column_by_name(name).ok_or_else(|| missing(name))
+}
+
+/// Колонка, приведённая к типу `data_type` массива `A`
+fn typed_column<A: Array + Clone + 'static>(batch: &RecordBatch, name: &str, data_type: &DataType) -> Result<Column<A>, ArrowError> {
+    let values = cast(array(batch, name)?, data_type)?;
+    let array = values.as_any().downcast_ref::<A>().cloned().ok_or_else(|| ArrowError::CastError(format!("column {}: not {}", name, data_type)))?;
+    Ok(Column { name: name.to_string(), array })
+}
+
+fn float_column(batch: &RecordBatch, name: &str) -> Result<Column<Float64Array>, ArrowError> {
+    typed_column(batch, name, &DataType::Float64)
+}
+
+pub(crate) fn string_column(batch: &RecordBatch, name: &str) -> Result<Column<StringArray>, ArrowError> {
+    typed_column(batch, name, &DataType::Utf8)
+}
+
+pub(crate) fn datetime_from(v: i64, unit: TimeUnit) -> Option<DateTime<Utc>> {
+    match unit {
+        TimeUnit::Second => DateTime::from_timestamp(v, 0),
+        TimeUnit::Millisecond => DateTime::from_timestamp_millis(v),
+        TimeUnit::Microsecond => DateTime::from_timestamp_micros(v),
+        TimeUnit::Nanosecond => Some(DateTime::from_timestamp_nanos(v)),
+    }
+}
+
+/// Единица колонки времени: своя у `Timestamp`, миллисекунды у целых
+pub(crate) fn timestamp_unit(name: &str, data_type: &DataType) -> Result<TimeUnit, ArrowError> {
+    match data_type {
+        DataType::Timestamp(unit, _) => Ok(*unit),
+        t if t.is_integer() => Ok(TimeUnit::Millisecond),
+        t => Err(ArrowError::CastError(format!("column {}: {} is not a timestamp", name, t))),
+    }
+}
+
+/// Колонка времени: целые в единице `unit`
+pub(crate) struct TimestampColumn {
+    ints: Column<Int64Array>,
+    unit: TimeUnit,
+}
+
+impl TimestampColumn {
+    /// `None` — null; время вне диапазона `DateTime` — ошибка
+    pub(crate) fn get(&self, row: usize) -> Result<Option<DateTime<Utc>>, ArrowError> {
+        self.ints.get(row).map(|v| self.datetime(row, v)).transpose()
+    }
+
+    fn required(&self, row: usize) -> Result<DateTime<Utc>, ArrowError> {
+        self.datetime(row, self.ints.required(row)?)
+    }
+
+    fn datetime(&self, row: usize, v: i64) -> Result<DateTime<Utc>, ArrowError> {
+        datetime_from(v, self.unit)
+            .ok_or_else(|| ArrowError::ComputeError(format!("column {}, row {}: timestamp {} out of range", self.ints.name, row, v)))
+    }
+}
+
+/// `Timestamp` в своей единице или целое число миллисекунд
+pub(crate) fn timestamp_column(batch: &RecordBatch, name: &str) -> Result<TimestampColumn, ArrowError> {
+    let unit = timestamp_unit(name, array(batch, name)?.data_type())?;
+    Ok(TimestampColumn { ints: typed_column(batch, name, &DataType::Int64)?, unit })
+}
+
+/// Сторона: bool is_buyer_maker или строки
+enum SideColumn {
+    BuyerMaker(Column<BooleanArray>),
+    Words(Column<StringArray>),
+}
+
+impl SideColumn {
+    /// `buy`/`b` и `sell`/`s` без учёта регистра, остальное — `Side::Unknown`
+    fn required(&self, row: usize) -> Result<Side, ArrowError> {
+        Ok(match self {
+            SideColumn::BuyerMaker(col) => match col.required(row)? {
+                true => Side::Sell,
+                false => Side::Buy,
+            },
+            SideColumn::Words(col) => match col.required(row)? {
+                s if s.eq_ignore_ascii_case("buy") || s.eq_ignore_ascii_case("b") => Side::Buy,
+                s if s.eq_ignore_ascii_case("sell") || s.eq_ignore_ascii_case("s") => Side::Sell,
+                _ => Side::Unknown,
+            },
+        })
+    }
+}
+
+/// Строки `buy`/`sell`/`b`/`s` или bool is_buyer_maker
+fn side_column(batch: &RecordBatch, name: &str) -> Result<SideColumn, ArrowError> {
+    Ok(match array(batch, name)?.data_type() {
+        DataType::Boolean => SideColumn::BuyerMaker(typed_column(batch, name, &DataType::Boolean)?),
+        _ => SideColumn::Words(string_column(batch, name)?),
+    })
+}
@@ -0,0 +1,364 @@
+use crate::{calc_volume_usdt, new_candle, num, num_to_f64, synthetic_candle, update_candle, BarKind, Candle, CandleConfig, CandleError, Instrument, Num, Side, Timeframe, Trade, TradeRef};
+use std::sync::Arc;
+
+/// Инкрементальная сборка баров по потоку трейдов (tick / volume / dollar / range bars, Renko,
+/// imbalance и run bars).
+///
+/// Бар закрывается, как только набран порог `BarKind`. Timestamp бара — время его первого трейда,
+/// `interval` — `Timeframe::Bar(kind)`. Кастомные метрики и объём в USDT считаются через `CandleConfig`.
+pub struct BarBuilder<'a> {
+    config: &'a CandleConfig,
+    kind: BarKind,
+    split: bool,
+    current: Option<Candle>,
+    // Общий для всех баров; для `Validation::Full` — инструмент первого трейда
+    instrument: Option<Arc<Instrument>>,
+    filled: f64,
+    renko: RenkoState,
+    info: InfoState,
+    invalid: u64,
+}
+
+/// Close последнего кирпича и направление тренда (1 — вверх, −1 — вниз, 0 — ещё не было кирпичей)
+#[derive(Default)]
+struct RenkoState {
+    anchor: Option<Num>,
+    direction: i8,
+}
+
+/// Состояние imbalance/run bars: EWMA-ожидания по закрытым барам и суммы текущего бара
+#[derive(Default)]
+struct InfoState {
+    // None до закрытия первого (разогревочного) бара
+    expected_len: Option<f64>,
+    expected_imbalance: f64,
+    expected_buy: f64,
+    expected_sell: f64,
+    len: u64,
+    imbalance: f64,
+    buy: f64,
+    sell: f64,
+    last_sign: f64,
+    last_price: Option<Num>,
+}
+
+impl InfoState {
+    /// Направление трейда: `Side`, а для `Side::Unknown` — tick rule по изменению цены
+    fn sign(&mut self, trade: &TradeRef) -> f64 {
+        let sign = match trade.side {
+            Side::Buy => 1.0,
+            Side::Sell => -1.0,
+            Side::Unknown => match self.last_price {
+                Some(p) if trade.price > p => 1.0,
+                Some(p) if trade.price < p => -1.0,
+                _ => self.last_sign,
+            },
+        };
+        self.last_price = Some(trade.price);
+        self.last_sign = sign;
+        sign
+    }
+
+    /// Учитывает трейд; true — бар пора закрывать
+    fn push(&mut self, sign: f64, v: f64, initial: u64, run: bool) -> bool {
+        self.len += 1;
+        self.imbalance += sign * v;
+        if sign > 0.0 {
+            self.buy += v;
+        } else if sign < 0.0 {
+            self.sell += v;
+        }
+        let Some(expected_len) = self.expected_len else {
+            return self.len >= initial;
+        };
+        if run {
+            self.buy.max(self.sell) >= expected_len * self.expected_buy.max(self.expected_sell)
+        } else {
+            self.imbalance.abs() >= self.imbalance_threshold(expected_len)
+        }
+    }
+
+    /// E[T]·|E[b·v]|, но не меньше √E[T]·E[v] — типичного |imbalance| случайного блуждания длины
+    /// E[T]. Иначе после сбалансированного бара (E[b·v] = 0) порог нулевой и бар закрывает каждый трейд
+    fn imbalance_threshold(&self, expected_len: f64) -> f64 {
+        let floor = (self.expected_buy + self.expected_sell) / expected_len.max(1.0).sqrt();
+        expected_len * self.expected_imbalance.abs().max(floor)
+    }
+
+    /// Обновляет ожидания по закрытому бару и сбрасывает суммы
+    fn close(&mut self, span: u32) {
+        let n = self.len.max(1) as f64;
+        let (len, imbalance, buy, sell) = (self.len as f64, self.imbalance / n, self.buy / n, self.sell / n);
+        match self.expected_len {
+            None => {
+                self.expected_len = Some(len);
+                self.expected_imbalance = imbalance;
+                self.expected_buy = buy;
+                self.expected_sell = sell;
+            }
+            Some(prev) => {
+                let alpha = 2.0 / (span as f64 + 1.0);
+                let ewma = |old: f64, new: f64| old + alpha * (new - old);
+                self.expected_len = Some(ewma(prev, len));
+                self.expected_imbalance = ewma(self.expected_imbalance, imbalance);
+                self.expected_buy = ewma(self.expected_buy, buy);
+                self.expected_sell = ewma(self.expected_sell, sell);
+            }
+        }
+        self.len = 0;
+        self.imbalance = 0.0;
+        self.buy = 0.0;
+        self.sell = 0.0;
+    }
+}
+
+impl<'a> BarBuilder<'a> {
+    /// `split` — делить трейд, переполняющий порог, между барами пропорционально `amount`.
+    /// Иначе трейд целиком попадает в бар, который он закрывает. Ошибка — для некорректного
+    /// порога (`BarKind::is_valid`)
+    pub fn new(config: &'a CandleConfig, kind: BarKind, split: bool) -> Result<Self, CandleError> {
+        if !kind.is_valid() {
+            return Err(CandleError::InvalidBarKind(kind));
+        }
+        Ok(Self {
+            config,
+            kind,
+            split,
+            current: None,
+            instrument: None,
+            filled: 0.0,
+            renko: RenkoState::default(),
+            info: InfoState::default(),
+            invalid: 0,
+        })
+    }
+
+    pub fn kind(&self) -> &BarKind {
+        &self.kind
+    }
+
+    /// Число отброшенных некорректных трейдов (как `LateStats::invalid` у `CandleBuilder`)
+    pub fn invalid(&self) -> u64 {
+        self.invalid
+    }
+
+    /// Добавляет трейд; возвращает закрытые им бары (при `split` или в Renko один трейд может закрыть несколько).
+    /// Некорректный трейд (`CandleConfig::validation`) пропускается и считается в `invalid`
+    pub fn push(&mut self, trade: &Trade) -> Vec<Candle> {
+        let mut out = Vec::new();
+        self.push_ref(&trade.view(), &mut out);
+        out
+    }
+
+    /// `push`, возвращающий ошибку для некорректного трейда
+    pub fn try_push(&mut self, trade: &Trade) -> Result<Vec<Candle>, CandleError> {
+        let mut out = Vec::new();
+        self.try_push_ref(&trade.view(), &mut out)?;
+        Ok(out)
+    }
+
+    /// `push` без аллокаций: закрытые бары дописываются в `out`
+    pub fn push_ref(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) {
+        if self.try_push_ref(trade, out).is_err() {
+            self.invalid += 1;
+        }
+    }
+
+    /// `try_push` без аллокаций: закрытые бары дописываются в `out`
+    pub fn try_push_ref(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) -> Result<(), CandleError> {
+        self.config.validation.check(trade, self.instrument.as_deref())?;
+        if self.instrument.is_none() {
+            self.instrument = Some(Arc::new(trade.instrument.clone()));
+        }
+        self.accept(trade, out);
+        Ok(())
+    }
+
+    fn accept(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) {
+        let (measure, threshold) = match self.kind {
+            BarKind::Tick(n) => (1.0, n as f64),
+            BarKind::Volume(v) => (num_to_f64(trade.amount), v),
+            // Без курса USDT трейд не продвигает dollar bar
+            BarKind::Dollar(v) => (calc_volume_usdt(trade, &self.config.volume_in_usdt).map_or(0.0, num_to_f64), v),
+            BarKind::Range(range) => return self.push_range(trade, range, out),
+            BarKind::Renko { brick, reversal } => return self.push_renko(trade, brick, reversal, out),
+            BarKind::TickImbalance { initial, span } => return self.push_info(trade, 1.0, initial, span, false, out),
+            BarKind::VolumeImbalance { initial, span } => return self.push_info(trade, num_to_f64(trade.amount), initial, span, false, out),
+            BarKind::TickRun { initial, span } => return self.push_info(trade, 1.0, initial, span, true, out),
+            BarKind::VolumeRun { initial, span } => return self.push_info(trade, num_to_f64(trade.amount), initial, span, true, out),
+        };
+        let mut rest = measure;
+        loop {
+            let room = threshold - self.filled;
+            if self.split && rest > room && measure > 0.0 {
+                let part = TradeRef { amount: trade.amount * num(room / measure), ..*trade };
+                self.add(&part);
+                out.extend(self.close());
+                rest -= room;
+                // Остаток — погрешность округления: трейд целиком разошёлся по закрытым барам
+                if rest <= measure * 1e-12 {
+                    return;
+                }
+                continue;
+            }
+            let part = if rest < measure {
+                TradeRef { amount: trade.amount * num(rest / measure), ..*trade }
+            } else {
+                *trade
+            };
+            self.add(&part);
+            self.filled += rest;
+            if self.filled >= threshold {
+                out.extend(self.close());
+            }
+            return;
+        }
+    }
+
+    fn push_info(&mut self, trade: &TradeRef, v: f64, initial: u64, span: u32, run: bool, out: &mut Vec<Candle>) {
+        self.add(trade);
+        let sign = self.info.sign(trade);
+        if self.info.push(sign, v, initial, run) {
+            self.info.close(span);
+            out.extend(self.close());
+        }
+    }
+
+    /// Текущий порог imbalance/run бара: E[T]·|E[b·v]| с нижней границей √E[T]·E[v] (или E[T]·max(E[buy], E[sell]) для run);
+    /// `None` для остальных видов и до закрытия первого бара
+    pub fn expected_threshold(&self) -> Option<f64> {
+        let info = &self.info;
+        let expected_len = info.expected_len?;
+        match self.kind {
+            BarKind::TickImbalance { .. } | BarKind::VolumeImbalance { .. } => Some(info.imbalance_threshold(expected_len)),
+            BarKind::TickRun { .. } | BarKind::VolumeRun { .. } => Some(expected_len * info.expected_buy.max(info.expected_sell)),
+            _ => None,
+        }
+    }
+
+    fn push_range(&mut self, trade: &TradeRef, range: f64, out: &mut Vec<Candle>) {
+        self.add(trade);
+        let c = self.current.as_ref().unwrap();
+        self.filled = num_to_f64(c.high - c.low);
+        if self.filled >= range {
+            out.extend(self.close());
+        }
+    }
+
+    /// Трейды копятся в текущем кирпиче; когда цена уходит от последнего кирпича на `brick`
+    /// (или на `reversal` кирпичей против тренда), выдаются кирпичи с OHLC по сетке.
+    /// Объём и метрики достаются первому кирпичу, остальные кирпичи того же трейда — пустые
+    fn push_renko(&mut self, trade: &TradeRef, brick: f64, reversal: u32, out: &mut Vec<Candle>) {
+        self.add(trade);
+        let Some(anchor) = self.renko.anchor else {
+            self.renko.anchor = Some(trade.price);
+            return;
+        };
+        let mut anchor = anchor;
+        let brick = num(brick);
+        let back = brick * num(reversal.saturating_sub(1) as f64);
+        loop {
+            let dir = self.renko.direction;
+            let up = if dir >= 0 { anchor + brick } else { anchor + back + brick };
+            let down = if dir <= 0 { anchor - brick } else { anchor - back - brick };
+            let (close, step, new_dir) = if trade.price >= up {
+                (up, brick, 1)
+            } else if trade.price <= down {
+                (down, -brick, -1)
+            } else {
+                break;
+            };
+            // Разворот идёт от открытия последнего кирпича: все кирпичи до `close` подряд
+            let count = if dir != 0 && dir != new_dir { reversal.saturating_sub(1).max(1) } else { 1 };
+            for k in (0..count).rev() {
+                let brick_close = close - step * num(k as f64);
+                let open = brick_close - step;
+                let mut c = match self.current.take() {
+                    Some(c) => c,
+                    None => {
+                        // Предыдущий кирпич этого же трейда
+                        let mut c = synthetic_candle(out.last().unwrap(), trade.timestamp);
+                        c.synthetic = false;
+                        c
+                    }
+                };
+                c.open = open;
+                c.close = brick_close;
+                c.high = open.max(brick_close);
+                c.low = open.min(brick_close);
+                out.push(c);
+            }
+            anchor = close;
+            self.renko.direction = new_dir;
+        }
+        self.renko.anchor = Some(anchor);
+    }
+
+    fn add(&mut self, trade: &TradeRef) {
+        match &mut self.current {
+            Some(c) => update_candle(c, trade, self.config),
+            None => {
+                // Без `Validation::Full` инструмент может смениться
+                let instrument = match &self.instrument {
+                    Some(i) if **i == *trade.instrument => i.clone(),
+                    _ => {
+                        let i = Arc::new(trade.instrument.clone());
+                        self.instrument = Some(i.clone());
+                        i
+                    }
+                };
+                let tf = Timeframe::Bar(self.kind);
+                self.current = Some(new_candle(trade, instrument, tf, trade.timestamp, self.config));
+            }
+        }
+    }
+
+    fn close(&mut self) -> Option<Candle> {
+        self.filled = 0.0;
+        self.current.take()
+    }
+
+    /// Текущий (ещё не закрытый) бар
+    pub fn current(&self) -> Option<&Candle> {
+        self.current.as_ref()
+    }
+
+    /// Доля порога, набранная текущим баром (для Renko, imbalance и run bars — всегда 0)
+    pub fn progress(&self) -> f64 {
+        let threshold = match self.kind {
+            BarKind::Tick(n) => n as f64,
+            BarKind::Volume(v) | BarKind::Dollar(v) | BarKind::Range(v) => v,
+            _ => return 0.0,
+        };
+        self.filled / threshold
+    }
+
+    /// Закрывает незавершённый бар; он помечается `incomplete`
+    pub fn flush(&mut self) -> Option<Candle> {
+        self.close().map(|mut c| {
+            c.incomplete = true;
+            c
+        })
+    }
+}
+
+/// Heikin-Ashi поверх любой серии свечей: close = (O+H+L+C)/4, open = среднее open/close
+/// предыдущей HA-свечи (для первой — исходных open/close), high/low расширяются до HA open/close.
+/// Остальные поля (объём, метрики, флаги) копируются как есть
+pub fn heikin_ashi(candles: &[Candle]) -> Vec<Candle> {
+    let mut result: Vec<Candle> = Vec::with_capacity(candles.len());
+    for c in candles {
+        let close = (c.open + c.high + c.low + c.close) / num(4.0);
+        let open = match result.last() {
+            Some(prev) => (prev.open + prev.close) / num(2.0),
+            None => (c.open + c.close) / num(2.0),
+        };
+        let mut ha = c.clone();
+        ha.open = open;
+        ha.close = close;
+        ha.high = c.high.max(open).max(close);
+        ha.low = c.low.min(open).min(close);
+        result.push(ha);
+    }
+    result
+}
@@ -0,0 +1,414 @@
+use crate::{gap_candles, new_candle, update_candle, Candle, CandleConfig, CandleError, Dedup, Instrument, LatePolicy, LateStats, Timeframe, Trade, TradeRef};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+/// Открытая (или удерживаемая для корректировок) свеча и границы её трейдов по времени
+struct Slot {
+    candle: Candle,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    emitted: bool,
+    // id трейдов свечи (`Dedup::PerCandle`)
+    seen: HashSet<String>,
+}
+
+impl Slot {
+    fn open(trade: &TradeRef, instrument: Arc<Instrument>, timeframe: &Timeframe, ts: DateTime<Utc>, config: &CandleConfig) -> Self {
+        let mut slot = Self {
+            candle: new_candle(trade, instrument, timeframe.clone(), ts, config),
+            first: trade.timestamp,
+            last: trade.timestamp,
+            emitted: false,
+            seen: HashSet::new(),
+        };
+        slot.remember(trade, config);
+        slot
+    }
+
+    fn remember(&mut self, trade: &TradeRef, config: &CandleConfig) {
+        if config.dedup == Dedup::PerCandle {
+            self.seen.insert(trade.id.to_string());
+        }
+    }
+
+    /// Вливает трейд с учётом его времени: open/close определяются самым ранним/поздним трейдом
+    fn merge(&mut self, trade: &TradeRef, config: &CandleConfig) {
+        let (open, close) = (self.candle.open, self.candle.close);
+        self.remember(trade, config);
+        update_candle(&mut self.candle, trade, config);
+        if trade.timestamp < self.first {
+            self.first = trade.timestamp;
+            self.candle.open = trade.price;
+        } else {
+            self.candle.open = open;
+        }
+        if trade.timestamp >= self.last {
+            self.last = trade.timestamp;
+        } else {
+            self.candle.close = close;
+        }
+    }
+}
+
+/// Инкрементальная (push) агрегация живого потока трейдов в свечи одного таймфрейма.
+///
+/// Хранит только открытые свечи (а при `LatePolicy::Merge` — недавно выданные, для корректировок).
+/// `push` возвращает свечи, закрытые этим трейдом. Результат идентичен
+/// `CandleGenerator::aggregate` по тем же трейдам.
+///
+/// Горячий путь — `push_ref`: заимствованный трейд, общий `Arc<Instrument>` для всех свечей и
+/// внешний буфер для закрытых свечей, так что на трейд нет аллокаций.
+pub struct CandleBuilder<'a> {
+    config: &'a CandleConfig,
+    timeframe: Timeframe,
+    // Отсортированы по timestamp, последняя — текущая
+    slots: VecDeque<Slot>,
+    max_seen: Option<DateTime<Utc>>,
+    released_until: Option<DateTime<Utc>>,
+    // Начало последней выданной по порядку свечи
+    last_ts: Option<DateTime<Utc>>,
+    // Сама эта свеча — от неё заполняются пропуски (только при `fill_gaps`)
+    last_emitted: Option<Candle>,
+    // Инструмент свечей; для `Validation::Full` — инструмент первого трейда
+    instrument: Option<Arc<Instrument>>,
+    // Последние id трейдов (`Dedup::Window`)
+    recent: RecentIds,
+    stats: LateStats,
+}
+
+impl<'a> CandleBuilder<'a> {
+    pub fn new(config: &'a CandleConfig, timeframe: Timeframe) -> Self {
+        Self {
+            config,
+            timeframe,
+            slots: VecDeque::new(),
+            max_seen: None,
+            released_until: None,
+            last_ts: None,
+            last_emitted: None,
+            instrument: None,
+            recent: RecentIds::default(),
+            stats: LateStats::default(),
+        }
+    }
+
+    /// Инструмент для `Validation::Full` до первого трейда (куски параллельной агрегации)
+    #[cfg(feature = "parallel")]
+    pub(crate) fn expect_instrument(&mut self, instrument: Instrument) {
+        self.instrument = Some(Arc::new(instrument));
+    }
+
+    pub fn timeframe(&self) -> &Timeframe {
+        &self.timeframe
+    }
+
+    /// Счётчики поздних (`CandleConfig::late_policy`) и отброшенных трейдов
+    pub fn late_stats(&self) -> &LateStats {
+        &self.stats
+    }
+
+    /// Добавляет трейд; возвращает свечи, закрытые этим трейдом.
+    /// При `LatePolicy::Merge` сюда же попадают корректировки уже выданных свечей (`revision > 0`)
+    /// Некорректный трейд (`CandleConfig::validation`) отбрасывается и считается в `LateStats::invalid`
+    pub fn push(&mut self, trade: &Trade) -> Vec<Candle> {
+        let mut out = Vec::new();
+        self.push_ref(&trade.view(), &mut out);
+        out
+    }
+
+    /// `push`, возвращающий ошибку для некорректного трейда (билдер при этом не меняется)
+    pub fn try_push(&mut self, trade: &Trade) -> Result<Vec<Candle>, CandleError> {
+        let mut out = Vec::new();
+        self.try_push_ref(&trade.view(), &mut out)?;
+        Ok(out)
+    }
+
+    /// `push` без аллокаций: закрытые свечи дописываются в `out`
+    pub fn push_ref(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) {
+        if self.try_push_ref(trade, out).is_err() {
+            self.stats.invalid += 1;
+        }
+    }
+
+    /// `try_push` без аллокаций: закрытые свечи дописываются в `out`
+    pub fn try_push_ref(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) -> Result<(), CandleError> {
+        self.config.validation.check(trade, self.instrument.as_deref())?;
+        if self.instrument.is_none() {
+            self.instrument = Some(Arc::new(trade.instrument.clone()));
+        }
+        self.accept(trade, out);
+        Ok(())
+    }
+
+    fn accept(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) {
+        if !self.config.calendar.is_open(trade.timestamp) {
+            self.stats.outside_session += 1;
+            return;
+        }
+        let ts = self.config.alignment.truncate(trade.timestamp, &self.timeframe);
+        if self.is_duplicate(trade, ts) {
+            self.stats.duplicates += 1;
+            return;
+        }
+        self.max_seen = Some(self.max_seen.map_or(trade.timestamp, |m| m.max(trade.timestamp)));
+        let Some(back) = self.slots.back_mut() else {
+            let slot = self.open_slot(trade, ts);
+            self.slots.push_back(slot);
+            return;
+        };
+        if back.candle.timestamp == ts {
+            back.merge(trade, self.config);
+        } else if back.candle.timestamp < ts {
+            self.open_next(trade, ts, out);
+        } else {
+            self.stats.late += 1;
+            self.push_late(trade, ts, out);
+        }
+        if let LatePolicy::Buffer(lateness) = self.config.late_policy {
+            self.release(lateness, out);
+        }
+    }
+
+    /// Новая свеча с общим `Arc<Instrument>` (без `Validation::Full` инструмент может смениться)
+    fn open_slot(&mut self, trade: &TradeRef, ts: DateTime<Utc>) -> Slot {
+        let instrument = match &self.instrument {
+            Some(i) if **i == *trade.instrument => i.clone(),
+            _ => {
+                let i = Arc::new(trade.instrument.clone());
+                self.instrument = Some(i.clone());
+                i
+            }
+        };
+        Slot::open(trade, instrument, &self.timeframe, ts, self.config)
+    }
+
+    /// Билдер ведёт один инструмент, поэтому ключ — только `Trade.id`
+    fn is_duplicate(&mut self, trade: &TradeRef, ts: DateTime<Utc>) -> bool {
+        match self.config.dedup {
+            Dedup::Off => false,
+            Dedup::Window(size) => !self.recent.insert(trade.id, size),
+            Dedup::PerCandle => self.slots.iter().rev().any(|s| s.candle.timestamp == ts && s.seen.contains(trade.id)),
+        }
+    }
+
+    fn open_next(&mut self, trade: &TradeRef, ts: DateTime<Utc>, out: &mut Vec<Candle>) {
+        match self.config.late_policy {
+            LatePolicy::Reopen | LatePolicy::Drop => {
+                if let Some(slot) = self.slots.pop_back() {
+                    self.emit(slot.candle, out);
+                }
+            }
+            LatePolicy::Merge(horizon) => {
+                if let Some(back) = self.slots.back_mut() {
+                    back.emitted = true;
+                    let candle = back.candle.clone();
+                    self.emit(candle, out);
+                }
+                while self.slots.front().is_some_and(|s| s.candle.timestamp < ts - horizon) {
+                    self.slots.pop_front();
+                }
+            }
+            LatePolicy::Buffer(_) => {}
+        }
+        // Пустые интервалы до нового трейда уже известны — выдаём их сразу (кроме `Buffer`)
+        if !matches!(self.config.late_policy, LatePolicy::Buffer(_)) {
+            self.fill_until(ts, out);
+        }
+        let slot = self.open_slot(trade, ts);
+        self.slots.push_back(slot);
+    }
+
+    fn push_late(&mut self, trade: &TradeRef, ts: DateTime<Utc>, out: &mut Vec<Candle>) {
+        match self.config.late_policy {
+            LatePolicy::Reopen => {
+                // Поздний трейд закрывает текущую свечу и открывает новую с более ранним timestamp
+                self.stats.reopened += 1;
+                if let Some(slot) = self.slots.pop_back() {
+                    self.emit(slot.candle, out);
+                }
+                let slot = self.open_slot(trade, ts);
+                self.slots.push_back(slot);
+            }
+            LatePolicy::Drop => self.stats.dropped += 1,
+            LatePolicy::Merge(horizon) => {
+                let back_ts = self.slots.back().unwrap().candle.timestamp;
+                if ts < back_ts - horizon {
+                    self.stats.dropped += 1;
+                    return;
+                }
+                self.stats.merged += 1;
+                let emitted_after = self.last_ts.is_some_and(|t| t > ts);
+                let fill_gaps = self.config.fill_gaps;
+                let slot = self.slot_for(trade, ts);
+                if slot.emitted || (fill_gaps && emitted_after) {
+                    // Корректировка выданной свечи (в том числе синтетической за пустой интервал)
+                    slot.candle.revision += 1;
+                    slot.emitted = true;
+                    out.push(slot.candle.clone());
+                } else {
+                    slot.emitted = true;
+                    let candle = slot.candle.clone();
+                    self.emit(candle, out);
+                }
+            }
+            LatePolicy::Buffer(_) => {
+                if self.released_until.is_some_and(|r| ts < r) {
+                    self.stats.dropped += 1;
+                    return;
+                }
+                self.stats.buffered += 1;
+                self.slot_for(trade, ts);
+            }
+        }
+    }
+
+    /// Вливает поздний трейд в удерживаемую свечу его интервала (или создаёт её на своём месте)
+    fn slot_for(&mut self, trade: &TradeRef, ts: DateTime<Utc>) -> &mut Slot {
+        let pos = self.slots.partition_point(|s| s.candle.timestamp < ts);
+        if self.slots.get(pos).is_some_and(|s| s.candle.timestamp == ts) {
+            self.slots[pos].merge(trade, self.config);
+        } else {
+            let slot = self.open_slot(trade, ts);
+            self.slots.insert(pos, slot);
+        }
+        &mut self.slots[pos]
+    }
+
+    /// Выдаёт удерживаемые свечи, чей интервал закончился до watermark (max timestamp − lateness)
+    fn release(&mut self, lateness: chrono::Duration, out: &mut Vec<Candle>) {
+        let Some(watermark) = self.max_seen.map(|m| m - lateness) else { return };
+        while self.slots.front().is_some_and(|s| self.config.alignment.next(s.candle.timestamp, &self.timeframe) <= watermark) {
+            let slot = self.slots.pop_front().unwrap();
+            self.released_until = Some(self.config.alignment.next(slot.candle.timestamp, &self.timeframe));
+            self.emit(slot.candle, out);
+        }
+    }
+
+    /// Выдаёт свечу, предваряя её синтетическими свечами за пустые интервалы (`fill_gaps`)
+    fn emit(&mut self, candle: Candle, out: &mut Vec<Candle>) {
+        if self.last_ts.is_some_and(|t| t >= candle.timestamp) {
+            out.push(candle);
+            return;
+        }
+        self.fill_until(candle.timestamp, out);
+        self.last_ts = Some(candle.timestamp);
+        if self.config.fill_gaps {
+            self.last_emitted = Some(candle.clone());
+        }
+        out.push(candle);
+    }
+
+    /// Синтетические свечи за пустые интервалы от последней выданной до `ts`
+    fn fill_until(&mut self, ts: DateTime<Utc>, out: &mut Vec<Candle>) {
+        if !self.config.fill_gaps {
+            return;
+        }
+        let Some(prev) = &self.last_emitted else { return };
+        let filler = gap_candles(prev, ts, self.config);
+        if let Some(last) = filler.last() {
+            self.last_ts = Some(last.timestamp);
+            self.last_emitted = Some(last.clone());
+        }
+        out.extend(filler);
+    }
+
+    /// Текущая (ещё не закрытая) свеча
+    pub fn current(&self) -> Option<&Candle> {
+        self.slots.back().map(|s| &s.candle)
+    }
+
+    /// Закрывает и возвращает все ещё не выданные свечи (например, по таймеру или в конце потока)
+    pub fn flush(&mut self) -> Vec<Candle> {
+        let slots = std::mem::take(&mut self.slots);
+        if let Some(last) = slots.back() {
+            self.released_until = Some(self.config.alignment.next(last.candle.timestamp, &self.timeframe));
+        }
+        let mut out = Vec::new();
+        for slot in slots.into_iter().filter(|s| !s.emitted) {
+            self.emit(slot.candle, &mut out);
+        }
+        out
+    }
+}
+
+/// Окно последних N id трейдов. Строка id одна на множество и очередь
+#[derive(Default)]
+struct RecentIds {
+    ids: HashSet<Arc<str>>,
+    order: VecDeque<Arc<str>>,
+}
+
+impl RecentIds {
+    /// Запоминает id; false — он уже есть в окне
+    fn insert(&mut self, id: &str, size: usize) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        let id: Arc<str> = Arc::from(id);
+        self.ids.insert(id.clone());
+        self.order.push_back(id);
+        while self.order.len() > size {
+            if let Some(old) = self.order.pop_front() {
+                self.ids.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// Потоковая агрегация смешанного потока трейдов: одна открытая свеча на `Instrument`
+pub struct InstrumentCandleBuilder<'a> {
+    config: &'a CandleConfig,
+    timeframe: Timeframe,
+    builders: HashMap<Instrument, CandleBuilder<'a>>,
+}
+
+impl<'a> InstrumentCandleBuilder<'a> {
+    pub fn new(config: &'a CandleConfig, timeframe: Timeframe) -> Self {
+        Self { config, timeframe, builders: HashMap::new() }
+    }
+
+    /// Добавляет трейд; возвращает свечи его инструмента, закрытые этим трейдом
+    pub fn push(&mut self, trade: &Trade) -> Vec<Candle> {
+        self.builder_for(&trade.instrument).push(trade)
+    }
+
+    /// `push`, возвращающий ошибку для некорректного трейда
+    pub fn try_push(&mut self, trade: &Trade) -> Result<Vec<Candle>, CandleError> {
+        self.builder_for(&trade.instrument).try_push(trade)
+    }
+
+    /// `CandleBuilder::push_ref` для инструмента трейда
+    pub fn push_ref(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) {
+        self.builder_for(trade.instrument).push_ref(trade, out)
+    }
+
+    /// `CandleBuilder::try_push_ref` для инструмента трейда
+    pub fn try_push_ref(&mut self, trade: &TradeRef, out: &mut Vec<Candle>) -> Result<(), CandleError> {
+        self.builder_for(trade.instrument).try_push_ref(trade, out)
+    }
+
+    fn builder_for(&mut self, instrument: &Instrument) -> &mut CandleBuilder<'a> {
+        if !self.builders.contains_key(instrument) {
+            let b = CandleBuilder::new(self.config, self.timeframe.clone());
+            self.builders.insert(instrument.clone(), b);
+        }
+        self.builders.get_mut(instrument).unwrap()
+    }
+
+    /// Текущая свеча инструмента
+    pub fn current(&self, instrument: &Instrument) -> Option<&Candle> {
+        self.builders.get(instrument).and_then(|b| b.current())
+    }
+
+    /// Суммарные счётчики поздних и отброшенных трейдов по всем инструментам
+    pub fn late_stats(&self) -> LateStats {
+        self.builders.values().fold(LateStats::default(), |acc, b| acc + b.late_stats())
+    }
+
+    /// Закрывает текущие свечи всех инструментов
+    pub fn flush(&mut self) -> Vec<Candle> {
+        self.builders.values_mut().flat_map(|b| b.flush()).collect()
+    }
+}
@@ -0,0 +1,190 @@
+use crate::alignment::resolve_local;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+/// Торговый календарь: когда рынок открыт.
+///
+/// `CandleGenerator` не открывает свечи по трейдам вне сессии (они считаются в
+/// `LateStats::outside_session`) и не выдаёт синтетические свечи (`fill_gaps`) за интервалы,
+/// целиком попадающие на перерывы, выходные и праздники.
+pub trait SessionCalendar: Send + Sync {
+    fn is_open(&self, ts: DateTime<Utc>) -> bool;
+
+    /// Ближайший момент открытия, не раньше `ts` (сам `ts`, если рынок открыт)
+    fn next_open(&self, ts: DateTime<Utc>) -> Option<DateTime<Utc>>;
+
+    /// Есть ли в интервале `[from, to)` время торгов
+    fn is_open_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> bool {
+        self.next_open(from).is_some_and(|t| t < to)
+    }
+
+    /// Рынок открыт всегда — проверку сессии можно пропустить (`aggregate_columns`)
+    fn is_always_open(&self) -> bool {
+        false
+    }
+}
+
+/// Круглосуточный рынок без выходных (крипто) — календарь по умолчанию
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysOpen;
+
+impl SessionCalendar for AlwaysOpen {
+    fn is_open(&self, _ts: DateTime<Utc>) -> bool {
+        true
+    }
+
+    fn next_open(&self, ts: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        Some(ts)
+    }
+
+    fn is_always_open(&self) -> bool {
+        true
+    }
+}
+
+/// Сессия по дням недели в локальном времени биржи; `end <= start` — сессия переходит через полночь
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeeklySession {
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+/// Недельное расписание сессий (с перерывами между ними) и праздники.
+///
+/// Формат файла — строка на директиву, `#` — комментарий:
+///
+/// ```text
+/// timezone Europe/Moscow
+/// session mon-fri 10:00-14:00
+/// session mon-fri 14:05-18:50   # клиринг 14:00–14:05
+/// holiday 2024-01-01
+/// ```
+///
+/// Праздник закрывает все сессии, начинающиеся в эту локальную дату.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeeklyCalendar {
+    pub tz: Tz,
+    pub sessions: Vec<WeeklySession>,
+    pub holidays: HashSet<NaiveDate>,
+}
+
+/// Ошибка разбора файла календаря; `line` — номер строки с 1 (0 — ошибка чтения файла)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CalendarParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "calendar line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CalendarParseError {}
+
+impl WeeklyCalendar {
+    pub fn new(tz: Tz) -> Self {
+        Self { tz, sessions: Vec::new(), holidays: HashSet::new() }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, CalendarParseError> {
+        let text = std::fs::read_to_string(path).map_err(|e| CalendarParseError { line: 0, message: e.to_string() })?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, CalendarParseError> {
+        let mut calendar = WeeklyCalendar::new(Tz::UTC);
+        for (i, raw) in text.lines().enumerate() {
+            let err = |message: String| CalendarParseError { line: i + 1, message };
+            let line = raw.split('#').next().unwrap().trim();
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next(), words.next()) {
+                (None, ..) => {}
+                (Some("timezone"), Some(tz), None, _) => {
+                    calendar.tz = tz.parse().map_err(|_| err(format!("unknown timezone {:?}", tz)))?;
+                }
+                (Some("holiday"), Some(date), None, _) => {
+                    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| err(format!("bad date {:?}: {}", date, e)))?;
+                    calendar.holidays.insert(date);
+                }
+                (Some("session"), Some(days), Some(hours), None) => {
+                    let days = parse_days(days).ok_or_else(|| err(format!("bad days {:?}", days)))?;
+                    let (start, end) = hours
+                        .split_once('-')
+                        .and_then(|(s, e)| Some((parse_time(s)?, parse_time(e)?)))
+                        .ok_or_else(|| err(format!("bad hours {:?}", hours)))?;
+                    calendar.sessions.push(WeeklySession { days, start, end });
+                }
+                _ => return Err(err(format!("unknown directive {:?}", line))),
+            }
+        }
+        Ok(calendar)
+    }
+
+    /// Интервалы сессий (локальное время), начинающихся в дату `date`
+    fn sessions_on(&self, date: NaiveDate) -> impl Iterator<Item = (NaiveDateTime, NaiveDateTime)> + '_ {
+        let closed = self.holidays.contains(&date);
+        self.sessions
+            .iter()
+            .filter(move |s| !closed && s.days.contains(&date.weekday()))
+            .map(move |s| {
+                let start = date.and_time(s.start);
+                let end_date = if s.end <= s.start { date + Duration::days(1) } else { date };
+                (start, end_date.and_time(s.end))
+            })
+    }
+}
+
+impl SessionCalendar for WeeklyCalendar {
+    fn is_open(&self, ts: DateTime<Utc>) -> bool {
+        let local = ts.with_timezone(&self.tz).naive_local();
+        let today = local.date();
+        [today - Duration::days(1), today]
+            .into_iter()
+            .flat_map(|d| self.sessions_on(d))
+            .any(|(start, end)| start <= local && local < end)
+    }
+
+    fn next_open(&self, ts: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.is_open(ts) {
+            return Some(ts);
+        }
+        let local = ts.with_timezone(&self.tz).naive_local();
+        // Праздники могут идти подряд, но не дольше года
+        (0..=366)
+            .map(|n| local.date() + Duration::days(n))
+            .find_map(|d| self.sessions_on(d).map(|(start, _)| start).filter(|s| *s > local).min())
+            .map(|start| resolve_local(&self.tz, start))
+    }
+}
+
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    if s == "24:00" {
+        return Some(NaiveTime::MIN);
+    }
+    NaiveTime::parse_from_str(s, "%H:%M").ok()
+}
+
+/// `mon-fri`, `sat,sun`, `mon`
+fn parse_days(s: &str) -> Option<Vec<Weekday>> {
+    let mut days = Vec::new();
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (mut day, to) = (from.parse::<Weekday>().ok()?, to.parse::<Weekday>().ok()?);
+                days.push(day);
+                while day != to {
+                    day = day.succ();
+                    days.push(day);
+                }
+            }
+            None => days.push(part.parse().ok()?),
+        }
+    }
+    Some(days)
+}
@@ -0,0 +1,195 @@
+use crate::{num, num_to_f64, try_num, CandleError, CandleGenerator, Dedup, Instrument, LatePolicy, Num, Side, Timeframe, UsdtVolumeSource, Validation};
+use chrono::{DateTime, Utc};
+
+/// Трейды одного инструмента по столбцам (struct of arrays), например срезы колонок Parquet или
+/// ClickHouse без сборки `Vec<Trade>`. Время — миллисекунды Unix epoch; `sides` может быть пустым
+#[derive(Debug, Clone, Copy)]
+pub struct TradeColumns<'a> {
+    pub instrument: &'a Instrument,
+    pub timestamps: &'a [i64],
+    pub prices: &'a [Num],
+    pub amounts: &'a [Num],
+    pub sides: &'a [Side],
+}
+
+/// Свечи по столбцам: строка `i` — одна свеча, `timestamps` — начало интервала в миллисекундах.
+/// `buy_volume` / `sell_volume` заполняются, только если переданы `sides`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CandleColumns {
+    pub timestamps: Vec<i64>,
+    pub open: Vec<Num>,
+    pub high: Vec<Num>,
+    pub low: Vec<Num>,
+    pub close: Vec<Num>,
+    pub volume: Vec<Num>,
+    pub trade_count: Vec<u64>,
+    pub volume_usdt: Vec<Option<Num>>,
+    pub buy_volume: Vec<Num>,
+    pub sell_volume: Vec<Num>,
+}
+
+impl CandleColumns {
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+}
+
+/// Курс в USDT, разрешённый один раз на весь батч (кроме `Callback`)
+enum UsdtRate {
+    Quote,
+    Fixed(Num),
+    PerRow(Vec<Option<Num>>),
+    None,
+}
+
+impl CandleGenerator {
+    /// Агрегация столбцов одного инструмента. Бакеты считаются отдельным проходом по `timestamps`
+    /// (для UTC-сетки — целочисленным делением), затем подряд идущие трейды одного бакета сворачиваются
+    /// в свечу без промежуточных `Trade` и `Candle`.
+    ///
+    /// OHLCV, `trade_count` и `volume_usdt` совпадают с `aggregate` при `LatePolicy::Reopen`
+    /// (по умолчанию): некорректные по `validation` трейды и трейды вне сессии отбрасываются, поздний
+    /// трейд открывает новую свечу. Кастомные метрики, дедупликация, `fill_gaps` и другие `LatePolicy`
+    /// не поддерживаются: если они заданы, возвращается `CandleError::Unsupported`
+    pub fn aggregate_columns(&self, trades: &TradeColumns, timeframe: &Timeframe) -> Result<CandleColumns, CandleError> {
+        self.check_columnar_config()?;
+        let n = trades.timestamps.len();
+        for (column, len) in [("prices", trades.prices.len()), ("amounts", trades.amounts.len())] {
+            if len != n {
+                return Err(CandleError::ColumnLength { column, expected: n, found: len });
+            }
+        }
+        if !trades.sides.is_empty() && trades.sides.len() != n {
+            return Err(CandleError::ColumnLength { column: "sides", expected: n, found: trades.sides.len() });
+        }
+
+        let buckets = self.column_buckets(trades.timestamps, timeframe);
+        let keep = self.column_mask(trades);
+        let rate = self.column_usdt_rate(trades);
+        let (prices, amounts) = (trades.prices, trades.amounts);
+
+        let mut out = CandleColumns::default();
+        let mut current = None;
+        for i in 0..n {
+            if !keep.as_ref().is_none_or(|k| k[i]) {
+                continue;
+            }
+            let (price, amount) = (prices[i], amounts[i]);
+            let vu = match &rate {
+                UsdtRate::Quote => Some(price * amount),
+                UsdtRate::Fixed(r) => Some(price * amount * *r),
+                UsdtRate::PerRow(rates) => rates[i].map(|r| price * amount * r),
+                UsdtRate::None => None,
+            };
+            let (buy, sell) = match trades.sides.get(i) {
+                Some(Side::Buy) => (amount, Num::default()),
+                Some(Side::Sell) => (Num::default(), amount),
+                _ => (Num::default(), Num::default()),
+            };
+            if current != Some(buckets[i]) {
+                current = Some(buckets[i]);
+                out.timestamps.push(buckets[i]);
+                out.open.push(price);
+                out.high.push(price);
+                out.low.push(price);
+                out.close.push(price);
+                out.volume.push(amount);
+                out.trade_count.push(1);
+                out.volume_usdt.push(vu);
+                if !trades.sides.is_empty() {
+                    out.buy_volume.push(buy);
+                    out.sell_volume.push(sell);
+                }
+                continue;
+            }
+            let k = out.timestamps.len() - 1;
+            out.high[k] = out.high[k].max(price);
+            out.low[k] = out.low[k].min(price);
+            out.close[k] = price;
+            out.volume[k] += amount;
+            out.trade_count[k] += 1;
+            if let Some(vu) = vu {
+                out.volume_usdt[k] = Some(out.volume_usdt[k].unwrap_or_default() + vu);
+            }
+            if !trades.sides.is_empty() {
+                out.buy_volume[k] += buy;
+                out.sell_volume[k] += sell;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Опции конфига, при которых результат разошёлся бы с `aggregate`
+    fn check_columnar_config(&self) -> Result<(), CandleError> {
+        let config = &self.config;
+        let unsupported = [
+            ("late_policy", config.late_policy != LatePolicy::Reopen),
+            ("dedup", config.dedup != Dedup::Off),
+            ("custom_metrics", !config.custom_metrics.is_empty()),
+            ("fill_gaps", config.fill_gaps),
+        ];
+        match unsupported.into_iter().find(|&(_, set)| set) {
+            Some((option, _)) => Err(CandleError::Unsupported { option }),
+            None => Ok(()),
+        }
+    }
+
+    /// Начало бакета каждого трейда, мс
+    fn column_buckets(&self, timestamps: &[i64], timeframe: &Timeframe) -> Vec<i64> {
+        match self.config.alignment.fixed_step_ms(timeframe) {
+            Some(step) => timestamps.iter().map(|ts| ts - ts.rem_euclid(step)).collect(),
+            None => timestamps
+                .iter()
+                .map(|&ts| DateTime::from_timestamp_millis(ts).map_or(ts, |t| self.config.alignment.truncate(t, timeframe).timestamp_millis()))
+                .collect(),
+        }
+    }
+
+    /// Какие строки агрегировать (`None` — все): проверка значений и торговая сессия
+    fn column_mask(&self, trades: &TradeColumns) -> Option<Vec<bool>> {
+        let calendar = &self.config.calendar;
+        let check_values = self.config.validation != Validation::Off;
+        if !check_values && calendar.is_always_open() {
+            return None;
+        }
+        let values = trades.prices.iter().zip(trades.amounts);
+        let mut mask: Vec<bool> = if check_values {
+            values.map(|(&price, &amount)| valid_values(price, amount)).collect()
+        } else {
+            vec![true; trades.timestamps.len()]
+        };
+        if !calendar.is_always_open() {
+            for (keep, &ts) in mask.iter_mut().zip(trades.timestamps) {
+                *keep = *keep && DateTime::from_timestamp_millis(ts).is_some_and(|t: DateTime<Utc>| calendar.is_open(t));
+            }
+        }
+        Some(mask).filter(|m| m.contains(&false))
+    }
+
+    fn column_usdt_rate(&self, trades: &TradeColumns) -> UsdtRate {
+        let pair = &trades.instrument.pair;
+        if pair.quote_id == "USDT" {
+            return UsdtRate::Quote;
+        }
+        match &self.config.volume_in_usdt {
+            UsdtVolumeSource::Fixed(rate) => UsdtRate::Fixed(num(*rate)),
+            UsdtVolumeSource::Callback(cb) => UsdtRate::PerRow(
+                trades
+                    .timestamps
+                    .iter()
+                    .map(|&ts| DateTime::from_timestamp_millis(ts).and_then(|t| cb(pair, t)).and_then(try_num))
+                    .collect(),
+            ),
+            UsdtVolumeSource::None => UsdtRate::None,
+        }
+    }
+}
+
+/// То же, что `Validation::Values`, без сборки `TradeRef`
+fn valid_values(price: Num, amount: Num) -> bool {
+    num_to_f64(price).is_finite() && num_to_f64(amount).is_finite() && price > Num::default() && amount >= Num::default()
+}
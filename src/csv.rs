@@ -0,0 +1,218 @@
+use crate::format::CANDLE_COLUMNS;
+use crate::types::parse_num;
+use crate::{
+    Candle, CandleSerializer, CandleWriter, FormatError, Instrument, MarketType, Num, Pair, Side, SideEncoding, TimestampFormat, Trade,
+    TradeParser,
+};
+use std::io::{Read, Write};
+
+/// Поле трейда, которое хранит колонка CSV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeField {
+    Timestamp,
+    Price,
+    Amount,
+    Side,
+    Id,
+    Exchange,
+    Base,
+    Quote,
+    MarketType,
+    /// Колонка не используется (для файлов без заголовка)
+    Ignore,
+}
+
+/// Разбор трейдов из CSV.
+///
+/// С заголовком колонки ищутся по имени из `columns` (порядок в файле любой, лишние колонки
+/// пропускаются), без заголовка — берутся по позиции в `columns`. Обязательны `Timestamp`, `Price`,
+/// `Amount` и инструмент: колонки `Exchange`, `Base`, `Quote` или `instrument`. Без `Id` id — номер
+/// строки, без `Side` — `Side::Unknown`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvTradeParser {
+    pub delimiter: u8,
+    pub has_headers: bool,
+    pub columns: Vec<(String, TradeField)>,
+    pub timestamp: TimestampFormat,
+    pub side: SideEncoding,
+    /// Инструмент по умолчанию; колонки инструмента, если есть, его переопределяют
+    pub instrument: Option<Instrument>,
+}
+
+impl Default for CsvTradeParser {
+    /// Колонки как в выгрузке `SELECT timestamp, exchange, base_id, quote_id, market_type, id, price,
+    /// amount, side`, время в миллисекундах
+    fn default() -> Self {
+        let columns = [
+            ("timestamp", TradeField::Timestamp),
+            ("exchange", TradeField::Exchange),
+            ("base_id", TradeField::Base),
+            ("quote_id", TradeField::Quote),
+            ("market_type", TradeField::MarketType),
+            ("id", TradeField::Id),
+            ("price", TradeField::Price),
+            ("amount", TradeField::Amount),
+            ("side", TradeField::Side),
+        ];
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            columns: columns.iter().map(|(name, field)| (name.to_string(), *field)).collect(),
+            timestamp: TimestampFormat::Millis,
+            side: SideEncoding::Words,
+            instrument: None,
+        }
+    }
+}
+
+impl CsvTradeParser {
+    /// Позиция в записи для каждого используемого поля
+    fn resolve<R: Read>(&self, rdr: &mut ::csv::Reader<R>) -> Result<Vec<(usize, TradeField)>, FormatError> {
+        let fields: Vec<(usize, TradeField)> = if self.has_headers {
+            let headers = rdr.headers().map_err(csv_error)?;
+            self.columns
+                .iter()
+                .filter(|(_, field)| *field != TradeField::Ignore)
+                .map(|(name, field)| {
+                    let pos = headers.iter().position(|h| h.trim() == name);
+                    pos.map(|pos| (pos, *field)).ok_or_else(|| FormatError::new(1, format!("missing column {:?}", name)))
+                })
+                .collect::<Result<_, _>>()?
+        } else {
+            self.columns.iter().enumerate().filter(|(_, (_, field))| *field != TradeField::Ignore).map(|(pos, (_, field))| (pos, *field)).collect()
+        };
+        let has = |field| fields.iter().any(|(_, f)| *f == field);
+        for field in [TradeField::Timestamp, TradeField::Price, TradeField::Amount] {
+            if !has(field) {
+                return Err(FormatError::new(0, format!("no {:?} column", field)));
+            }
+        }
+        if self.instrument.is_none() && ![TradeField::Exchange, TradeField::Base, TradeField::Quote].into_iter().all(has) {
+            return Err(FormatError::new(0, "no instrument: set `instrument` or map Exchange, Base and Quote columns"));
+        }
+        Ok(fields)
+    }
+
+    fn trade(&self, record: &::csv::StringRecord, fields: &[(usize, TradeField)]) -> Result<Trade, FormatError> {
+        let line = record.position().map_or(0, |p| p.line() as usize);
+        let err = |message: String| FormatError::new(line, message);
+        let empty = Instrument {
+            pair: Pair { base_id: String::new(), quote_id: String::new() },
+            exchange: String::new(),
+            market_type: MarketType::Unknown,
+        };
+        let mut trade = Trade {
+            instrument: self.instrument.clone().unwrap_or(empty),
+            id: line.to_string(),
+            price: Num::default(),
+            amount: Num::default(),
+            side: Side::Unknown,
+            timestamp: Default::default(),
+        };
+        for &(pos, field) in fields {
+            let value = record.get(pos).ok_or_else(|| err(format!("no column {} for {:?}", pos + 1, field)))?;
+            let number = |name: &str| parse_num(value.trim()).ok_or_else(|| err(format!("bad {} {:?}", name, value)));
+            match field {
+                TradeField::Timestamp => trade.timestamp = self.timestamp.parse(value).map_err(err)?,
+                TradeField::Price => trade.price = number("price")?,
+                TradeField::Amount => trade.amount = number("amount")?,
+                TradeField::Side => trade.side = self.side.parse(value).map_err(err)?,
+                TradeField::Id => trade.id = value.to_string(),
+                TradeField::Exchange => trade.instrument.exchange = value.to_string(),
+                TradeField::Base => trade.instrument.pair.base_id = value.to_string(),
+                TradeField::Quote => trade.instrument.pair.quote_id = value.to_string(),
+                TradeField::MarketType => trade.instrument.market_type = MarketType::from(value.trim()),
+                TradeField::Ignore => {}
+            }
+        }
+        Ok(trade)
+    }
+}
+
+impl TradeParser for CsvTradeParser {
+    fn parse<'r>(&'r self, reader: Box<dyn Read + 'r>) -> Box<dyn Iterator<Item = Result<Trade, FormatError>> + 'r> {
+        let mut rdr = ::csv::ReaderBuilder::new().delimiter(self.delimiter).has_headers(self.has_headers).from_reader(reader);
+        let fields = match self.resolve(&mut rdr) {
+            Ok(fields) => fields,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        Box::new(rdr.into_records().map(move |record| self.trade(&record.map_err(csv_error)?, &fields)))
+    }
+}
+
+/// Запись свечей в CSV: колонки `exchange, base_id, quote_id, market_type, interval, timestamp,
+/// open, high, low, close, volume, trade_count, volume_usdt, incomplete, revision, synthetic`
+/// и затем `custom`. Пустое значение — `volume_usdt = None` или метрики нет у свечи
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvCandleSerializer {
+    pub delimiter: u8,
+    pub has_headers: bool,
+    pub timestamp: TimestampFormat,
+    /// Кастомные метрики — колонками после фиксированных, в этом порядке
+    pub custom: Vec<String>,
+}
+
+impl Default for CsvCandleSerializer {
+    fn default() -> Self {
+        Self { delimiter: b',', has_headers: true, timestamp: TimestampFormat::Millis, custom: Vec::new() }
+    }
+}
+
+impl CandleSerializer for CsvCandleSerializer {
+    fn writer<'w>(&'w self, out: Box<dyn Write + 'w>) -> Box<dyn CandleWriter + 'w> {
+        let inner = ::csv::WriterBuilder::new().delimiter(self.delimiter).from_writer(out);
+        Box::new(CsvCandleWriter { format: self, inner, header_pending: self.has_headers })
+    }
+}
+
+struct CsvCandleWriter<'w> {
+    format: &'w CsvCandleSerializer,
+    inner: ::csv::Writer<Box<dyn Write + 'w>>,
+    header_pending: bool,
+}
+
+impl CsvCandleWriter<'_> {
+    fn write_header(&mut self) -> Result<(), FormatError> {
+        if std::mem::take(&mut self.header_pending) {
+            let header = CANDLE_COLUMNS.iter().copied().chain(self.format.custom.iter().map(String::as_str));
+            self.inner.write_record(header).map_err(csv_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl CandleWriter for CsvCandleWriter<'_> {
+    fn write(&mut self, c: &Candle) -> Result<(), FormatError> {
+        self.write_header()?;
+        let mut row = vec![
+            c.instrument.exchange.clone(),
+            c.instrument.pair.base_id.clone(),
+            c.instrument.pair.quote_id.clone(),
+            c.instrument.market_type.to_string(),
+            c.interval.to_string(),
+            self.format.timestamp.format(c.timestamp).map_err(|e| FormatError::new(0, e))?,
+            c.open.to_string(),
+            c.high.to_string(),
+            c.low.to_string(),
+            c.close.to_string(),
+            c.volume.to_string(),
+            c.trade_count.to_string(),
+            c.volume_usdt.map(|v| v.to_string()).unwrap_or_default(),
+            c.incomplete.to_string(),
+            c.revision.to_string(),
+            c.synthetic.to_string(),
+        ];
+        row.extend(self.format.custom.iter().map(|name| c.custom.get(name).map(|v| v.to_string()).unwrap_or_default()));
+        self.inner.write_record(&row).map_err(csv_error)
+    }
+
+    /// Пустой поток с заголовком — только заголовок
+    fn finish(&mut self) -> Result<(), FormatError> {
+        self.write_header()?;
+        self.inner.flush().map_err(FormatError::from)
+    }
+}
+
+fn csv_error(e: ::csv::Error) -> FormatError {
+    FormatError::new(e.position().map_or(0, |p| p.line() as usize), e.to_string())
+}
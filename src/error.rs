@@ -0,0 +1,82 @@
+use crate::{num_to_f64, BarKind, Instrument, Num, TradeRef};
+use std::fmt;
+
+/// Некорректный входной трейд (см. `CandleConfig::validation`) или параметры агрегации
+#[derive(Debug, Clone, PartialEq)]
+pub enum CandleError {
+    /// NaN или бесконечность в `price` / `amount`
+    NonFinite { id: String, field: &'static str },
+    NonPositivePrice { id: String, price: Num },
+    NegativeAmount { id: String, amount: Num },
+    /// Трейд другого инструмента в билдере одного инструмента
+    InstrumentMismatch { id: String, expected: Box<Instrument>, found: Box<Instrument> },
+    /// Длина столбца `TradeColumns` не совпадает с длиной `timestamps`
+    ColumnLength { column: &'static str, expected: usize, found: usize },
+    /// Неположительный или бесконечный порог бара (см. `BarKind::is_valid`)
+    InvalidBarKind(BarKind),
+    /// Опция `CandleConfig`, которую не поддерживает выбранный путь агрегации (например `aggregate_columns`)
+    Unsupported { option: &'static str },
+}
+
+impl fmt::Display for CandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CandleError::NonFinite { id, field } => write!(f, "trade {}: {} is not finite", id, field),
+            CandleError::NonPositivePrice { id, price } => write!(f, "trade {}: non-positive price {}", id, price),
+            CandleError::NegativeAmount { id, amount } => write!(f, "trade {}: negative amount {}", id, amount),
+            CandleError::InstrumentMismatch { id, expected, found } => {
+                write!(f, "trade {}: instrument {:?} does not match {:?}", id, found, expected)
+            }
+            CandleError::ColumnLength { column, expected, found } => {
+                write!(f, "column {}: {} rows, expected {}", column, found, expected)
+            }
+            CandleError::InvalidBarKind(kind) => write!(f, "invalid bar threshold {:?}", kind),
+            CandleError::Unsupported { option } => write!(f, "config option {} is not supported here", option),
+        }
+    }
+}
+
+impl std::error::Error for CandleError {}
+
+/// Уровень проверки трейдов. `try_*` методы возвращают `CandleError` на первом некорректном трейде,
+/// остальные — отбрасывают его и считают в `LateStats::invalid`. По умолчанию проверок нет: их
+/// включают явно, например для `try_aggregate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Без проверок — горячий путь с заведомо чистыми данными (по умолчанию)
+    #[default]
+    Off,
+    /// Цена и объём: конечные, цена > 0, объём ≥ 0
+    Values,
+    /// `Values` и совпадение инструмента с первым трейдом билдера
+    Full,
+}
+
+impl Validation {
+    /// Проверяет трейд; `expected` — инструмент билдера (если уже известен)
+    pub fn check(&self, trade: &TradeRef, expected: Option<&Instrument>) -> Result<(), CandleError> {
+        if *self == Validation::Off {
+            return Ok(());
+        }
+        let id = || trade.id.to_string();
+        for (field, v) in [("price", trade.price), ("amount", trade.amount)] {
+            if !num_to_f64(v).is_finite() {
+                return Err(CandleError::NonFinite { id: id(), field });
+            }
+        }
+        if trade.price <= Num::default() {
+            return Err(CandleError::NonPositivePrice { id: id(), price: trade.price });
+        }
+        if trade.amount < Num::default() {
+            return Err(CandleError::NegativeAmount { id: id(), amount: trade.amount });
+        }
+        match expected {
+            Some(e) if *self == Validation::Full && e != trade.instrument => Err(CandleError::InstrumentMismatch {
+                id: id(),
+                expected: Box::new(e.clone()),
+                found: Box::new(trade.instrument.clone()),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use crate::types::parse_num;
+use crate::{FormatError, Instrument, MarketType, Num, Side, SymbolRules, Trade, TradeParser};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+/// Биржа — источник сырых сообщений о сделках
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Exchange {
+    /// Потоки `trade` / `aggTrade` (в том числе combined), REST `trades`, `historicalTrades`, `aggTrades`
+    Binance,
+    /// v5: websocket `publicTrade.*`, REST `/v5/market/recent-trade`
+    Bybit,
+    /// v5: канал `trades` / `trades-all`, REST `/api/v5/market/trades`, `history-trades`
+    Okx,
+    /// Exchange API: websocket `match` / `last_match`, REST `/products/{id}/trades`
+    Coinbase,
+}
+
+impl Exchange {
+    /// Имя в `Instrument::exchange`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Exchange::Binance => "binance",
+            Exchange::Bybit => "bybit",
+            Exchange::Okx => "okx",
+            Exchange::Coinbase => "coinbase",
+        }
+    }
+}
+
+/// Стоимость одного контракта: деривативы OKX, инверсные Bybit и COIN-M Binance передают
+/// количество в контрактах, а `Trade::amount` — в базовом активе
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContractValue {
+    /// Контракт — столько базового актива (OKX `ctVal` у `BTC-USDT-SWAP` — 0.01 BTC)
+    Base(Num),
+    /// Контракт — столько котируемого актива, пересчёт по цене сделки (инверсные: OKX
+    /// `BTC-USD-SWAP` — 100 USD, Bybit inverse — 1 USD)
+    Quote(Num),
+}
+
+impl ContractValue {
+    /// Количество в базовом активе для `contracts` контрактов по цене `price`
+    pub fn base_amount(&self, contracts: Num, price: Num) -> Result<Num, String> {
+        match *self {
+            ContractValue::Base(v) => Ok(contracts * v),
+            ContractValue::Quote(v) if price > Num::default() => Ok(contracts * v / price),
+            ContractValue::Quote(_) => Err(format!("bad price {} for inverse contract", price)),
+        }
+    }
+}
+
+/// Декодер сырых JSON-сообщений биржи в `Trade`.
+///
+/// Сторона — агрессора (taker): `isBuyerMaker = true` у Binance — продажа; у Coinbase `side` —
+/// сторона maker-ордера, поэтому инвертируется. Служебные сообщения (подтверждения подписки,
+/// heartbeat, другие каналы) дают пустой список, ответы с ошибкой (OKX `code` != 0) — ошибку.
+///
+/// Количество в контрактах переводится в базовый актив по `contracts`. Для деривативов OKX
+/// стоимость контракта обязательна, инверсные Bybit (категория `inverse`) по умолчанию — 1 USD
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeDecoder {
+    pub exchange: Exchange,
+    /// Тип рынка, если его не видно в сообщении (Binance, websocket Bybit, Coinbase)
+    pub market_type: MarketType,
+    /// Символ биржи для ответов REST без символа (Binance, Coinbase), например `BTCUSDT`, `BTC-USD`
+    pub symbol: Option<String>,
+    /// Разбор символов; тип рынка по суффиксу (`-SWAP`, дата экспирации) важнее `market_type`
+    pub symbols: SymbolRules,
+    /// Стоимость контракта по символу биржи (`BTC-USDT-SWAP`, `BTCUSD_PERP`)
+    pub contracts: HashMap<String, ContractValue>,
+}
+
+impl ExchangeDecoder {
+    /// Спот, символ — из сообщений
+    pub fn new(exchange: Exchange) -> Self {
+        Self { exchange, market_type: MarketType::Spot, symbol: None, symbols: SymbolRules::default(), contracts: HashMap::new() }
+    }
+
+    /// Трейды одного сообщения (websocket или ответ REST)
+    pub fn decode(&self, json: &str) -> Result<Vec<Trade>, FormatError> {
+        let value: Value = serde_json::from_str(json).map_err(|e| FormatError::new(0, e.to_string()))?;
+        let result = match self.exchange {
+            Exchange::Binance => self.binance(value),
+            Exchange::Bybit => self.bybit(value),
+            Exchange::Okx => self.okx(value),
+            Exchange::Coinbase => self.coinbase(value),
+        };
+        result.map_err(|message| FormatError::new(0, message))
+    }
+
+    fn binance(&self, value: Value) -> Result<Vec<Trade>, String> {
+        let value = match value {
+            // Combined stream: {"stream": "btcusdt@trade", "data": {...}}
+            Value::Object(mut obj) if obj.contains_key("stream") => obj.remove("data").unwrap_or_default(),
+            value => value,
+        };
+        let rows: Vec<BinanceTrade> = match value {
+            Value::Array(_) => from_value(value)?,
+            Value::Object(ref obj) if matches!(obj.get("e").and_then(Value::as_str), Some("trade" | "aggTrade")) => vec![from_value(value)?],
+            _ => return Ok(Vec::new()),
+        };
+        rows.into_iter()
+            .map(|t| {
+                let symbol = t.symbol.or_else(|| self.symbol.clone()).ok_or("no symbol in message or decoder")?;
+                Ok(Trade {
+                    instrument: self.instrument(&symbol, &self.market_type)?,
+                    id: t.id.or(t.agg_id).ok_or("no trade id")?.to_string(),
+                    price: t.price,
+                    amount: self.base_amount(&symbol, t.qty, t.price, None)?,
+                    side: if t.is_buyer_maker { Side::Sell } else { Side::Buy },
+                    timestamp: millis(t.time)?,
+                })
+            })
+            .collect()
+    }
+
+    fn bybit(&self, value: Value) -> Result<Vec<Trade>, String> {
+        let mut inverse = false;
+        let (rows, market_type): (Vec<BybitTrade>, MarketType) = if value.get("topic").and_then(Value::as_str).is_some_and(|t| t.starts_with("publicTrade.")) {
+            (from_value(value["data"].clone())?, self.market_type.clone())
+        } else if let Some(result) = value.get("result").filter(|r| r.get("list").is_some()) {
+            let category = result.get("category").and_then(Value::as_str);
+            inverse = category == Some("inverse");
+            let market_type = match category {
+                Some("spot") => MarketType::Spot,
+                Some("linear" | "inverse") => MarketType::Futures,
+                Some(_) => MarketType::Unknown,
+                None => self.market_type.clone(),
+            };
+            (from_value(result["list"].clone())?, market_type)
+        } else {
+            return Ok(Vec::new());
+        };
+        rows.into_iter()
+            .map(|t| {
+                Ok(Trade {
+                    instrument: self.instrument(&t.symbol, &market_type)?,
+                    id: t.id,
+                    price: t.price,
+                    amount: self.base_amount(&t.symbol, t.size, t.price, inverse.then_some(ContractValue::Quote(crate::num(1.0))))?,
+                    side: parse_side(&t.side)?,
+                    timestamp: millis(t.time)?,
+                })
+            })
+            .collect()
+    }
+
+    fn okx(&self, value: Value) -> Result<Vec<Trade>, String> {
+        let channel = value.pointer("/arg/channel").and_then(Value::as_str);
+        let code = value.get("code").and_then(Value::as_str);
+        if code.is_some_and(|c| c != "0") || value.get("event").and_then(Value::as_str) == Some("error") {
+            let msg = value.get("msg").and_then(Value::as_str).unwrap_or_default();
+            return Err(format!("okx error {}: {}", code.unwrap_or_default(), msg));
+        }
+        if value.get("event").is_some() || !(code.is_some() || matches!(channel, Some("trades" | "trades-all"))) {
+            return Ok(Vec::new());
+        }
+        let rows: Vec<OkxTrade> = from_value(value.get("data").cloned().unwrap_or_default())?;
+        rows.into_iter()
+            .map(|t| {
+                let instrument = self.instrument(&t.inst_id, &self.market_type)?;
+                // У деривативов sz — в контрактах
+                let amount = match (self.contracts.get(&t.inst_id), &instrument.market_type) {
+                    (None, MarketType::Spot) => t.sz,
+                    (None, _) => return Err(format!("no contract value for {}", t.inst_id)),
+                    (Some(c), _) => c.base_amount(t.sz, t.px)?,
+                };
+                Ok(Trade {
+                    instrument,
+                    id: t.trade_id,
+                    price: t.px,
+                    amount,
+                    side: parse_side(&t.side)?,
+                    timestamp: millis(t.ts)?,
+                })
+            })
+            .collect()
+    }
+
+    fn coinbase(&self, value: Value) -> Result<Vec<Trade>, String> {
+        let rows: Vec<CoinbaseTrade> = match value {
+            Value::Array(_) => from_value(value)?,
+            Value::Object(ref obj) if matches!(obj.get("type").and_then(Value::as_str), Some("match" | "last_match")) => vec![from_value(value)?],
+            _ => return Ok(Vec::new()),
+        };
+        rows.into_iter()
+            .map(|t| {
+                let product = t.product_id.or_else(|| self.symbol.clone()).ok_or("no product_id in message or decoder")?;
+                // side — сторона maker-ордера
+                let side = match parse_side(&t.side)? {
+                    Side::Buy => Side::Sell,
+                    Side::Sell => Side::Buy,
+                    Side::Unknown => Side::Unknown,
+                };
+                Ok(Trade {
+                    instrument: self.instrument(&product, &self.market_type)?,
+                    id: t.trade_id.to_string(),
+                    price: t.price,
+                    amount: t.size,
+                    side,
+                    timestamp: t.time,
+                })
+            })
+            .collect()
+    }
+
+    /// Количество в базовом активе: по `contracts`, иначе по `implied`, иначе как есть
+    fn base_amount(&self, symbol: &str, qty: Num, price: Num, implied: Option<ContractValue>) -> Result<Num, String> {
+        match self.contracts.get(symbol).copied().or(implied) {
+            Some(c) => c.base_amount(qty, price),
+            None => Ok(qty),
+        }
+    }
+
+    /// Инструмент по символу; `market_type` — если суффикса нет
+    fn instrument(&self, symbol: &str, market_type: &MarketType) -> Result<Instrument, String> {
+        let (pair, suffix_type) = self.symbols.split(symbol).map_err(|e| e.to_string())?;
+        Ok(Instrument { pair, exchange: self.exchange.name().to_string(), market_type: suffix_type.unwrap_or_else(|| market_type.clone()) })
+    }
+}
+
+/// Сообщение на строку (как пишет коллектор); трейды всех сообщений подряд. Ошибка сообщения —
+/// с номером строки
+impl TradeParser for ExchangeDecoder {
+    fn parse<'r>(&'r self, reader: Box<dyn Read + 'r>) -> Box<dyn Iterator<Item = Result<Trade, FormatError>> + 'r> {
+        let lines = BufReader::new(reader).lines().enumerate();
+        Box::new(lines.flat_map(move |(i, line)| {
+            let at_line = |e: FormatError| FormatError::new(i + 1, e.message);
+            let decoded = line.map_err(FormatError::from).and_then(|line| match line.trim() {
+                "" => Ok(Vec::new()),
+                line => self.decode(line),
+            });
+            match decoded {
+                Ok(trades) => trades.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(at_line(e))],
+            }
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct BinanceTrade {
+    #[serde(rename = "s")]
+    symbol: Option<String>,
+    #[serde(rename = "id", alias = "t")]
+    id: Option<u64>,
+    /// Id агрегированной сделки (`aggTrade`); в старом потоке `trade` — id ордера продавца
+    #[serde(rename = "a")]
+    agg_id: Option<u64>,
+    #[serde(rename = "price", alias = "p", deserialize_with = "de_num")]
+    price: Num,
+    #[serde(rename = "qty", alias = "q", deserialize_with = "de_num")]
+    qty: Num,
+    #[serde(rename = "time", alias = "T", deserialize_with = "de_i64")]
+    time: i64,
+    #[serde(rename = "isBuyerMaker", alias = "m")]
+    is_buyer_maker: bool,
+}
+
+#[derive(Deserialize)]
+struct BybitTrade {
+    #[serde(rename = "execId", alias = "i")]
+    id: String,
+    #[serde(rename = "symbol", alias = "s")]
+    symbol: String,
+    #[serde(rename = "price", alias = "p", deserialize_with = "de_num")]
+    price: Num,
+    #[serde(rename = "size", alias = "v", deserialize_with = "de_num")]
+    size: Num,
+    #[serde(rename = "side", alias = "S")]
+    side: String,
+    #[serde(rename = "time", alias = "T", deserialize_with = "de_i64")]
+    time: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxTrade {
+    inst_id: String,
+    trade_id: String,
+    #[serde(deserialize_with = "de_num")]
+    px: Num,
+    #[serde(deserialize_with = "de_num")]
+    sz: Num,
+    side: String,
+    #[serde(deserialize_with = "de_i64")]
+    ts: i64,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseTrade {
+    product_id: Option<String>,
+    #[serde(deserialize_with = "de_i64")]
+    trade_id: i64,
+    #[serde(deserialize_with = "de_num")]
+    price: Num,
+    #[serde(deserialize_with = "de_num")]
+    size: Num,
+    side: String,
+    time: DateTime<Utc>,
+}
+
+/// Число или строка с числом (биржи передают цены строками, чтобы не терять точность)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumOrStr {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+fn de_num<'de, D: Deserializer<'de>>(d: D) -> Result<Num, D::Error> {
+    match NumOrStr::deserialize(d)? {
+        NumOrStr::Str(s) => parse_num(&s).ok_or_else(|| serde::de::Error::custom(format!("bad number {:?}", s))),
+        NumOrStr::Int(v) => Ok(crate::num(v as f64)),
+        NumOrStr::Float(v) => crate::try_num(v).ok_or_else(|| serde::de::Error::custom(format!("bad number {}", v))),
+    }
+}
+
+fn de_i64<'de, D: Deserializer<'de>>(d: D) -> Result<i64, D::Error> {
+    match NumOrStr::deserialize(d)? {
+        NumOrStr::Int(v) => Ok(v),
+        NumOrStr::Str(s) => s.parse().map_err(|_| serde::de::Error::custom(format!("bad integer {:?}", s))),
+        NumOrStr::Float(v) => Err(serde::de::Error::custom(format!("bad integer {}", v))),
+    }
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+fn millis(ms: i64) -> Result<DateTime<Utc>, String> {
+    DateTime::from_timestamp_millis(ms).ok_or_else(|| format!("timestamp {} out of range", ms))
+}
+
+fn parse_side(s: &str) -> Result<Side, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "buy" => Ok(Side::Buy),
+        "sell" => Ok(Side::Sell),
+        _ => Err(format!("bad side {:?}", s)),
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{Candle, Side, Trade};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Ошибка разбора трейдов или записи свечей; `line` — номер строки входа с 1 (0 — вне строк,
+/// например ошибка ввода-вывода или записи)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl FormatError {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Self { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => f.write_str(&self.message),
+            line => write!(f, "line {}: {}", line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<std::io::Error> for FormatError {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(0, e.to_string())
+    }
+}
+
+/// Потоковый разбор трейдов: трейды читаются из `reader` по мере обхода итератора. Ошибка в одной
+/// записи не останавливает разбор, если формат может продолжить со следующей
+pub trait TradeParser {
+    fn parse<'r>(&'r self, reader: Box<dyn Read + 'r>) -> Box<dyn Iterator<Item = Result<Trade, FormatError>> + 'r>;
+}
+
+/// Инкрементальная запись свечей: `writer` оборачивает поток вывода
+pub trait CandleSerializer {
+    fn writer<'w>(&'w self, out: Box<dyn Write + 'w>) -> Box<dyn CandleWriter + 'w>;
+}
+
+/// Открытый поток записи свечей
+pub trait CandleWriter {
+    fn write(&mut self, candle: &Candle) -> Result<(), FormatError>;
+
+    /// Дописывает буферы в поток; после `finish` писать нельзя
+    fn finish(&mut self) -> Result<(), FormatError>;
+}
+
+/// JSON Lines — эталонный формат: объект `Trade` / `Candle` (serde) на строку. Пустые строки
+/// пропускаются
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonLines;
+
+impl TradeParser for JsonLines {
+    fn parse<'r>(&'r self, reader: Box<dyn Read + 'r>) -> Box<dyn Iterator<Item = Result<Trade, FormatError>> + 'r> {
+        let lines = BufReader::new(reader).lines().enumerate();
+        Box::new(lines.filter_map(|(i, line)| {
+            let line = match line {
+                Ok(line) if line.trim().is_empty() => return None,
+                Ok(line) => line,
+                Err(e) => return Some(Err(FormatError::new(i + 1, e.to_string()))),
+            };
+            Some(serde_json::from_str(&line).map_err(|e| FormatError::new(i + 1, e.to_string())))
+        }))
+    }
+}
+
+impl CandleSerializer for JsonLines {
+    fn writer<'w>(&'w self, out: Box<dyn Write + 'w>) -> Box<dyn CandleWriter + 'w> {
+        Box::new(JsonLinesWriter { out })
+    }
+}
+
+struct JsonLinesWriter<'w> {
+    out: Box<dyn Write + 'w>,
+}
+
+impl CandleWriter for JsonLinesWriter<'_> {
+    fn write(&mut self, candle: &Candle) -> Result<(), FormatError> {
+        serde_json::to_writer(&mut self.out, candle).map_err(|e| FormatError::new(0, e.to_string()))?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), FormatError> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Форматы по имени — для выбора в рантайме (из конфига, аргумента CLI). `new` регистрирует
+/// встроенные: `jsonl` и, с feature `csv`, `csv` с настройками по умолчанию
+pub struct FormatRegistry {
+    parsers: HashMap<String, Box<dyn TradeParser + Send + Sync>>,
+    serializers: HashMap<String, Box<dyn CandleSerializer + Send + Sync>>,
+}
+
+impl FormatRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register_parser("jsonl", JsonLines);
+        registry.register_serializer("jsonl", JsonLines);
+        #[cfg(feature = "csv")]
+        {
+            registry.register_parser("csv", crate::CsvTradeParser::default());
+            registry.register_serializer("csv", crate::CsvCandleSerializer::default());
+        }
+        registry
+    }
+
+    /// Без встроенных форматов
+    pub fn empty() -> Self {
+        Self { parsers: HashMap::new(), serializers: HashMap::new() }
+    }
+
+    /// Регистрирует формат трейдов, заменяя одноимённый
+    pub fn register_parser(&mut self, name: impl Into<String>, parser: impl TradeParser + Send + Sync + 'static) {
+        self.parsers.insert(name.into(), Box::new(parser));
+    }
+
+    /// Регистрирует формат свечей, заменяя одноимённый
+    pub fn register_serializer(&mut self, name: impl Into<String>, serializer: impl CandleSerializer + Send + Sync + 'static) {
+        self.serializers.insert(name.into(), Box::new(serializer));
+    }
+
+    pub fn parser(&self, name: &str) -> Option<&(dyn TradeParser + Send + Sync)> {
+        self.parsers.get(name).map(|p| p.as_ref())
+    }
+
+    pub fn serializer(&self, name: &str) -> Option<&(dyn CandleSerializer + Send + Sync)> {
+        self.serializers.get(name).map(|s| s.as_ref())
+    }
+
+    /// Имена форматов трейдов по алфавиту
+    pub fn parser_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.parsers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Имена форматов свечей по алфавиту
+    pub fn serializer_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.serializers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Время в текстовых форматах: целое число секунд (допускается дробная часть), милли-, микро-
+/// или наносекунд от Unix epoch, либо RFC 3339
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    Seconds,
+    #[default]
+    Millis,
+    Micros,
+    Nanos,
+    Rfc3339,
+}
+
+impl TimestampFormat {
+    pub fn parse(&self, s: &str) -> Result<DateTime<Utc>, String> {
+        let s = s.trim();
+        let bad = || format!("bad timestamp {:?}", s);
+        if *self == TimestampFormat::Rfc3339 {
+            return DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc)).map_err(|e| format!("bad timestamp {:?}: {}", s, e));
+        }
+        if let (TimestampFormat::Seconds, Some((secs, frac))) = (self, s.split_once('.')) {
+            // Дробная часть — до наносекунд, без потерь точности f64
+            if frac.is_empty() || frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(bad());
+            }
+            let nanos: i64 = format!("{:0<9}", frac).parse().map_err(|_| bad())?;
+            // Знак относится ко всему числу: -1.5 — полторы секунды до эпохи
+            let nanos = if secs.starts_with('-') { -nanos } else { nanos };
+            let secs: i64 = secs.parse().map_err(|_| bad())?;
+            return DateTime::from_timestamp(secs, 0).and_then(|t| t.checked_add_signed(Duration::nanoseconds(nanos))).ok_or_else(bad);
+        }
+        let v: i64 = s.parse().map_err(|_| bad())?;
+        match self {
+            TimestampFormat::Seconds => DateTime::from_timestamp(v, 0),
+            TimestampFormat::Millis => DateTime::from_timestamp_millis(v),
+            TimestampFormat::Micros => DateTime::from_timestamp_micros(v),
+            TimestampFormat::Nanos => Some(DateTime::from_timestamp_nanos(v)),
+            TimestampFormat::Rfc3339 => unreachable!(),
+        }
+        .ok_or_else(bad)
+    }
+
+    /// Доли, не представимые в единице формата, отбрасываются
+    pub fn format(&self, ts: DateTime<Utc>) -> Result<String, String> {
+        Ok(match self {
+            TimestampFormat::Seconds => match ts.timestamp_subsec_nanos() {
+                0 => ts.timestamp().to_string(),
+                // До эпохи дробная часть отсчитывается от нуля, как при разборе
+                nanos if ts.timestamp() < 0 => format!("-{}.{}", -(ts.timestamp() + 1), frac_digits(1_000_000_000 - nanos)),
+                nanos => format!("{}.{}", ts.timestamp(), frac_digits(nanos)),
+            },
+            TimestampFormat::Millis => ts.timestamp_millis().to_string(),
+            TimestampFormat::Micros => ts.timestamp_micros().to_string(),
+            TimestampFormat::Nanos => ts.timestamp_nanos_opt().ok_or_else(|| format!("timestamp {} out of nanosecond range", ts))?.to_string(),
+            TimestampFormat::Rfc3339 => ts.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        })
+    }
+}
+
+/// Наносекунды как дробная часть секунды, без хвостовых нулей
+fn frac_digits(nanos: u32) -> String {
+    format!("{:09}", nanos).trim_end_matches('0').to_string()
+}
+
+/// Кодировка стороны сделки. Пустое значение — `Side::Unknown`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SideEncoding {
+    /// `buy` / `sell` (регистр не важен)
+    #[default]
+    Words,
+    /// `b` / `s`
+    Letters,
+    /// is_buyer_maker: `true` — продажа (агрессор — продавец), `false` — покупка
+    BuyerMaker,
+}
+
+impl SideEncoding {
+    pub fn parse(&self, s: &str) -> Result<Side, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(Side::Unknown);
+        }
+        let side = match (self, s.to_ascii_lowercase().as_str()) {
+            (SideEncoding::Words, "buy") | (SideEncoding::Letters, "b") | (SideEncoding::BuyerMaker, "false" | "0") => Side::Buy,
+            (SideEncoding::Words, "sell") | (SideEncoding::Letters, "s") | (SideEncoding::BuyerMaker, "true" | "1") => Side::Sell,
+            (SideEncoding::Words, "unknown") => Side::Unknown,
+            _ => return Err(format!("bad side {:?}", s)),
+        };
+        Ok(side)
+    }
+
+    pub fn format(&self, side: Side) -> &'static str {
+        match (self, side) {
+            (_, Side::Unknown) => "",
+            (SideEncoding::Words, Side::Buy) => "buy",
+            (SideEncoding::Words, Side::Sell) => "sell",
+            (SideEncoding::Letters, Side::Buy) => "b",
+            (SideEncoding::Letters, Side::Sell) => "s",
+            (SideEncoding::BuyerMaker, Side::Buy) => "false",
+            (SideEncoding::BuyerMaker, Side::Sell) => "true",
+        }
+    }
+}
+
+/// Фиксированные колонки свечи в табличных форматах (кастомные метрики идут следом)
+#[cfg(any(feature = "arrow", feature = "csv"))]
+pub(crate) const CANDLE_COLUMNS: [&str; 16] = [
+    "exchange", "base_id", "quote_id", "market_type", "interval", "timestamp",
+    "open", "high", "low", "close", "volume", "trade_count", "volume_usdt",
+    "incomplete", "revision", "synthetic",
+];
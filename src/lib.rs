@@ -3,93 +3,248 @@ pub fn add(left: u64, right: u64) -> u64 {
 }
 
 #[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+mod tests;
 
 mod types;
+mod symbol;
+mod builder;
+mod bars;
+mod alignment;
+mod calendar;
+mod error;
+mod columnar;
+mod format;
+mod exchange;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "csv")]
+mod csv;
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "proto")]
+mod proto;
 
 pub use types::*;
-use chrono::{DateTime, Utc, Timelike};
+pub use symbol::*;
+pub use builder::*;
+pub use bars::*;
+pub use alignment::*;
+pub use calendar::*;
+pub use error::*;
+pub use columnar::*;
+pub use format::*;
+pub use exchange::*;
+#[cfg(feature = "arrow")]
+pub use arrow::*;
+#[cfg(feature = "csv")]
+pub use csv::*;
+#[cfg(feature = "parquet")]
+pub use parquet::*;
+#[cfg(feature = "proto")]
+pub use proto::*;
+use chrono::{DateTime, Datelike, Duration, DurationRound, Months, NaiveDate, NaiveTime, Utc, Timelike};
 use std::collections::HashMap;
+use std::sync::Arc;
 
+#[derive(Default)]
 pub struct CandleGenerator {
     pub config: CandleConfig,
 }
 
-impl Default for CandleGenerator {
-    fn default() -> Self {
-        Self { config: CandleConfig::default() }
-    }
-}
-
 impl CandleGenerator {
     pub fn aggregate<'a, I>(&self, trades: I, timeframe: Timeframe) -> Vec<Candle>
     where
         I: Iterator<Item = &'a Trade>,
     {
+        self.aggregate_with_stats(trades, timeframe).0
+    }
+
+    /// `aggregate` со счётчиками поздних трейдов (см. `CandleConfig::late_policy`)
+    pub fn aggregate_with_stats<'a, I>(&self, trades: I, timeframe: Timeframe) -> (Vec<Candle>, LateStats)
+    where
+        I: Iterator<Item = &'a Trade>,
+    {
+        let mut builder = self.builder(timeframe);
         let mut candles = Vec::new();
-        let mut current: Option<Candle> = None;
+        // Один буфер на весь проход: закрытые свечи переносятся, а не копируются
+        let mut out = Vec::new();
         for trade in trades {
-            let ts = truncate_to_tf(trade.timestamp, &timeframe);
-            match &mut current {
-                Some(c) if c.timestamp == ts => {
-                    c.high = c.high.max(trade.price);
-                    c.low = c.low.min(trade.price);
-                    c.close = trade.price;
-                    c.volume += trade.amount;
-                    c.trade_count += 1;
-                    // USDT volume
-                    if let Some(vu) = calc_volume_usdt(trade, &self.config.volume_in_usdt) {
-                        c.volume_usdt = Some(c.volume_usdt.unwrap_or(0.0) + vu);
-                    }
-                    // Кастомные метрики
-                    for m in &self.config.custom_metrics {
-                        m.update(trade, c);
-                    }
-                }
-                Some(c) => {
-                    candles.push(c.clone());
-                    current = Some(new_candle(trade, timeframe.clone(), ts, &self.config));
-                }
+            builder.push_ref(&trade.view(), &mut out);
+            self.collect_emitted(&mut candles, &mut out);
+        }
+        out.extend(builder.flush());
+        self.collect_emitted(&mut candles, &mut out);
+        (candles, builder.late_stats().clone())
+    }
+
+    /// `aggregate`, прерывающийся на первом некорректном трейде. Проверяет по `CandleConfig::validation`:
+    /// с `Validation::Off` (по умолчанию) ошибок не бывает
+    pub fn try_aggregate<'a, I>(&self, trades: I, timeframe: Timeframe) -> Result<Vec<Candle>, CandleError>
+    where
+        I: Iterator<Item = &'a Trade>,
+    {
+        let mut builder = self.builder(timeframe);
+        let mut candles = Vec::new();
+        let mut out = Vec::new();
+        for trade in trades {
+            builder.try_push_ref(&trade.view(), &mut out)?;
+            self.collect_emitted(&mut candles, &mut out);
+        }
+        out.extend(builder.flush());
+        self.collect_emitted(&mut candles, &mut out);
+        Ok(candles)
+    }
+
+    /// Переносит выданные билдером свечи из `out` в результат
+    fn collect_emitted(&self, candles: &mut Vec<Candle>, out: &mut Vec<Candle>) {
+        if !matches!(self.config.late_policy, LatePolicy::Merge(_)) {
+            candles.append(out);
+            return;
+        }
+        // Корректировка заменяет ранее выданную свечу, поздний интервал встаёт на своё место
+        for c in out.drain(..) {
+            let pos = candles.partition_point(|x| x.timestamp < c.timestamp);
+            match candles.get_mut(pos) {
+                Some(x) if x.timestamp == c.timestamp => *x = c,
+                _ => candles.insert(pos, c),
+            }
+        }
+    }
+
+    /// Раскладывает свечи по инструментам, копируя `Instrument` один раз на инструмент.
+    /// Корректировки (`LatePolicy::Merge`) заменяют свечу так же, как в `aggregate`
+    fn group_by_instrument(&self, candles: Vec<Candle>) -> HashMap<Instrument, Vec<Candle>> {
+        let mut result: HashMap<Instrument, Vec<Candle>> = HashMap::new();
+        for c in candles {
+            match result.get_mut(&*c.instrument) {
+                Some(v) => v.push(c),
                 None => {
-                    current = Some(new_candle(trade, timeframe.clone(), ts, &self.config));
+                    result.insert((*c.instrument).clone(), vec![c]);
                 }
             }
         }
-        if let Some(c) = current {
-            candles.push(c);
+        for emitted in result.values_mut() {
+            let mut out = std::mem::take(emitted);
+            self.collect_emitted(emitted, &mut out);
         }
-        candles
+        result
+    }
+
+    /// Собирает свечи старшего таймфрейма из отсортированных младших.
+    /// Крайние неполные бакеты тоже выдаются (см. `CandleConfig::mark_incomplete`)
+    pub fn rollup(&self, lower: &[Candle], timeframe: &Timeframe) -> Vec<Candle> {
+        aggregate_from_lower(lower, timeframe, &self.config)
+    }
+
+    /// Инкрементальный билдер для живого потока трейдов с тем же конфигом
+    pub fn builder(&self, timeframe: Timeframe) -> CandleBuilder<'_> {
+        CandleBuilder::new(&self.config, timeframe)
+    }
+
+    /// Агрегирует смешанный поток трейдов отдельно по каждому `Instrument` (pair, exchange, market_type)
+    pub fn aggregate_by_instrument<'a, I>(&self, trades: I, timeframe: Timeframe) -> HashMap<Instrument, Vec<Candle>>
+    where
+        I: Iterator<Item = &'a Trade>,
+    {
+        let mut builder = self.instrument_builder(timeframe);
+        let mut closed = Vec::new();
+        for trade in trades {
+            builder.push_ref(&trade.view(), &mut closed);
+        }
+        closed.extend(builder.flush());
+        self.group_by_instrument(closed)
+    }
+
+    /// `aggregate_by_instrument`, прерывающийся на первом некорректном трейде
+    pub fn try_aggregate_by_instrument<'a, I>(&self, trades: I, timeframe: Timeframe) -> Result<HashMap<Instrument, Vec<Candle>>, CandleError>
+    where
+        I: Iterator<Item = &'a Trade>,
+    {
+        let mut builder = self.instrument_builder(timeframe);
+        let mut closed = Vec::new();
+        for trade in trades {
+            builder.try_push_ref(&trade.view(), &mut closed)?;
+        }
+        closed.extend(builder.flush());
+        Ok(self.group_by_instrument(closed))
+    }
+
+    /// Потоковый вариант `aggregate_by_instrument`
+    pub fn instrument_builder(&self, timeframe: Timeframe) -> InstrumentCandleBuilder<'_> {
+        InstrumentCandleBuilder::new(&self.config, timeframe)
+    }
+
+    /// Бары по потоку трейдов: каждые N трейдов, N единиц объёма или N USDT.
+    /// `split` — делить трейд, переполняющий порог, между барами.
+    /// Паника при некорректном пороге (`BarKind::is_valid`); `try_aggregate_bars` возвращает ошибку
+    pub fn aggregate_bars<'a, I>(&self, trades: I, kind: BarKind, split: bool) -> Vec<Candle>
+    where
+        I: Iterator<Item = &'a Trade>,
+    {
+        let mut builder = self.bar_builder(kind, split).unwrap_or_else(|e| panic!("{}", e));
+        let mut bars = Vec::new();
+        for trade in trades {
+            builder.push_ref(&trade.view(), &mut bars);
+        }
+        bars.extend(builder.flush());
+        bars
+    }
+
+    /// `aggregate_bars`, прерывающийся на первом некорректном трейде
+    pub fn try_aggregate_bars<'a, I>(&self, trades: I, kind: BarKind, split: bool) -> Result<Vec<Candle>, CandleError>
+    where
+        I: Iterator<Item = &'a Trade>,
+    {
+        let mut builder = self.bar_builder(kind, split)?;
+        let mut bars = Vec::new();
+        for trade in trades {
+            builder.try_push_ref(&trade.view(), &mut bars)?;
+        }
+        bars.extend(builder.flush());
+        Ok(bars)
+    }
+
+    /// Потоковый вариант `aggregate_bars`; ошибка — для некорректного порога
+    pub fn bar_builder(&self, kind: BarKind, split: bool) -> Result<BarBuilder<'_>, CandleError> {
+        BarBuilder::new(&self.config, kind, split)
     }
 
     /// Строит цепочку агрегации: m1→m5→m15→m30→h1→h4→d1
     pub fn aggregate_chain<'a, I>(&self, trades: I) -> HashMap<Timeframe, Vec<Candle>>
     where
         I: Iterator<Item = &'a Trade> + Clone,
+    {
+        let tf_order = [Timeframe::m1, Timeframe::m5, Timeframe::m15, Timeframe::m30, Timeframe::h1, Timeframe::h4, Timeframe::d1];
+        self.aggregate_chain_for(trades, &tf_order)
+    }
+
+    /// Цепочка по заданным таймфреймам (по возрастанию, каждый кратен предыдущему),
+    /// например `[s1, s15, m1]` или `[d1, w1]`. Первый агрегируется из трейдов, остальные — из предыдущего
+    pub fn aggregate_chain_for<'a, I>(&self, trades: I, timeframes: &[Timeframe]) -> HashMap<Timeframe, Vec<Candle>>
+    where
+        I: Iterator<Item = &'a Trade>,
     {
         let mut result = HashMap::new();
-        let tf_order = vec![Timeframe::m1, Timeframe::m5, Timeframe::m15, Timeframe::m30, Timeframe::h1, Timeframe::h4, Timeframe::d1];
-        let mut prev = self.aggregate(trades.clone(), Timeframe::m1);
-        result.insert(Timeframe::m1, prev.clone());
-        for tf in tf_order.into_iter().skip(1) {
-            let higher = aggregate_from_lower(&prev, &tf);
-            result.insert(tf.clone(), higher.clone());
-            prev = higher;
+        let Some((first, rest)) = timeframes.split_first() else {
+            return result;
+        };
+        let mut prev_tf = first;
+        let mut prev = self.aggregate(trades, first.clone());
+        for tf in rest {
+            let higher = self.rollup(&prev, tf);
+            result.insert(prev_tf.clone(), std::mem::replace(&mut prev, higher));
+            prev_tf = tf;
         }
+        result.insert(prev_tf.clone(), prev);
         result
     }
 }
 
-fn new_candle(trade: &Trade, tf: Timeframe, ts: DateTime<Utc>, config: &CandleConfig) -> Candle {
+pub(crate) fn new_candle(trade: &TradeRef, instrument: Arc<Instrument>, tf: Timeframe, ts: DateTime<Utc>, config: &CandleConfig) -> Candle {
     let mut c = Candle {
-        instrument: trade.instrument.clone(),
+        instrument,
         interval: tf,
         timestamp: ts,
         open: trade.price,
@@ -100,6 +255,9 @@ fn new_candle(trade: &Trade, tf: Timeframe, ts: DateTime<Utc>, config: &CandleCo
         trade_count: 1,
         volume_usdt: calc_volume_usdt(trade, &config.volume_in_usdt),
         custom: HashMap::new(),
+        incomplete: false,
+        revision: 0,
+        synthetic: false,
     };
     for m in &config.custom_metrics {
         m.update(trade, &mut c);
@@ -107,91 +265,151 @@ fn new_candle(trade: &Trade, tf: Timeframe, ts: DateTime<Utc>, config: &CandleCo
     c
 }
 
-fn calc_volume_usdt(trade: &Trade, src: &UsdtVolumeSource) -> Option<f64> {
+/// Пустой интервал: OHLC = close предыдущей свечи, нулевой объём
+pub(crate) fn synthetic_candle(prev: &Candle, ts: DateTime<Utc>) -> Candle {
+    Candle {
+        instrument: prev.instrument.clone(),
+        interval: prev.interval.clone(),
+        timestamp: ts,
+        open: prev.close,
+        high: prev.close,
+        low: prev.close,
+        close: prev.close,
+        volume: Num::default(),
+        trade_count: 0,
+        volume_usdt: prev.volume_usdt.map(|_| Num::default()),
+        custom: HashMap::new(),
+        incomplete: false,
+        revision: 0,
+        synthetic: true,
+    }
+}
+
+/// Синтетические свечи за пустые интервалы после `prev` до `until`; интервалы целиком вне
+/// торговой сессии (перерывы, выходные, праздники) пропускаются
+pub(crate) fn gap_candles(prev: &Candle, until: DateTime<Utc>, config: &CandleConfig) -> Vec<Candle> {
+    let tf = &prev.interval;
+    let mut filler = Vec::new();
+    let mut ts = config.alignment.next(prev.timestamp, tf);
+    while ts < until {
+        let next = config.alignment.next(ts, tf);
+        if config.calendar.is_open_between(ts, next) {
+            filler.push(synthetic_candle(prev, ts));
+        }
+        ts = next;
+    }
+    filler
+}
+
+/// Начало следующего интервала
+pub(crate) fn next_bucket(ts: DateTime<Utc>, tf: &Timeframe) -> DateTime<Utc> {
+    match tf {
+        Timeframe::M1 => ts + Months::new(1),
+        _ => ts + tf.duration(),
+    }
+}
+
+pub(crate) fn update_candle(c: &mut Candle, trade: &TradeRef, config: &CandleConfig) {
+    c.high = c.high.max(trade.price);
+    c.low = c.low.min(trade.price);
+    c.close = trade.price;
+    c.volume += trade.amount;
+    c.trade_count += 1;
+    // USDT volume
+    if let Some(vu) = calc_volume_usdt(trade, &config.volume_in_usdt) {
+        c.volume_usdt = Some(c.volume_usdt.unwrap_or_default() + vu);
+    }
+    // Кастомные метрики
+    for m in &config.custom_metrics {
+        m.update(trade, c);
+    }
+}
+
+pub(crate) fn calc_volume_usdt(trade: &TradeRef, src: &UsdtVolumeSource) -> Option<Num> {
     let quote = &trade.instrument.pair.quote_id;
     if quote == "USDT" {
         Some(trade.price * trade.amount)
     } else {
         match src {
-            UsdtVolumeSource::Fixed(rate) => Some(trade.price * trade.amount * rate),
-            UsdtVolumeSource::Callback(cb) => cb(&trade.instrument.pair, trade.timestamp).map(|r| trade.price * trade.amount * r),
+            UsdtVolumeSource::Fixed(rate) => Some(trade.price * trade.amount * num(*rate)),
+            UsdtVolumeSource::Callback(cb) => cb(&trade.instrument.pair, trade.timestamp).and_then(try_num).map(|r| trade.price * trade.amount * r),
             UsdtVolumeSource::None => None,
         }
     }
 }
 
-fn truncate_to_tf(ts: DateTime<Utc>, tf: &Timeframe) -> DateTime<Utc> {
+pub(crate) fn truncate_to_tf(ts: DateTime<Utc>, tf: &Timeframe) -> DateTime<Utc> {
+    let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
     match tf {
-        Timeframe::m1 => ts.date_naive().and_hms_opt(ts.hour(), ts.minute(), 0).unwrap().and_utc(),
-        Timeframe::m5 => {
-            let m = ts.minute() / 5 * 5;
-            ts.date_naive().and_hms_opt(ts.hour(), m, 0).unwrap().and_utc()
-        },
-        Timeframe::m15 => {
-            let m = ts.minute() / 15 * 15;
-            ts.date_naive().and_hms_opt(ts.hour(), m, 0).unwrap().and_utc()
+        // Внутридневные таймфреймы делят сутки нацело
+        Timeframe::s1 | Timeframe::s5 | Timeframe::s15 | Timeframe::s30
+        | Timeframe::m1 | Timeframe::m5 | Timeframe::m15 | Timeframe::m30
+        | Timeframe::h1 | Timeframe::h4 | Timeframe::d1 => {
+            let step = tf.duration().num_seconds() as u32;
+            let secs = ts.num_seconds_from_midnight() / step * step;
+            midnight(ts.date_naive()) + Duration::seconds(secs as i64)
         },
-        Timeframe::m30 => {
-            let m = ts.minute() / 30 * 30;
-            ts.date_naive().and_hms_opt(ts.hour(), m, 0).unwrap().and_utc()
-        },
-        Timeframe::h1 => ts.date_naive().and_hms_opt(ts.hour(), 0, 0).unwrap().and_utc(),
-        Timeframe::h4 => {
-            let h = ts.hour() / 4 * 4;
-            ts.date_naive().and_hms_opt(h, 0, 0).unwrap().and_utc()
-        },
-        Timeframe::d1 => ts.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc(),
-        _ => ts,
+        Timeframe::w1 => midnight(ts.date_naive() - Duration::days(ts.weekday().num_days_from_monday() as i64)),
+        Timeframe::M1 => midnight(ts.date_naive() - Duration::days(ts.day0() as i64)),
+        // Отсчёт от Unix epoch
+        Timeframe::Custom(w) => ts.duration_trunc(w.duration()).unwrap_or(ts),
+        // Бары не привязаны к сетке времени
+        Timeframe::Bar(_) => ts,
     }
 }
 
-fn aggregate_from_lower(lower: &[Candle], tf: &Timeframe) -> Vec<Candle> {
-    let count = match tf {
-        Timeframe::m5 => 5,
-        Timeframe::m15 => 3,
-        Timeframe::m30 => 2,
-        Timeframe::h1 => 2,
-        Timeframe::h4 => 4,
-        Timeframe::d1 => 6,
-        _ => 1,
-    };
-    let mut result = Vec::new();
-    let mut i = 0;
-    while i + count <= lower.len() {
-        let slice = &lower[i..i+count];
-        let open = slice.first().unwrap().open;
-        let close = slice.last().unwrap().close;
-        let high = slice.iter().map(|c| c.high).fold(f64::MIN, f64::max);
-        let low = slice.iter().map(|c| c.low).fold(f64::MAX, f64::min);
-        let volume = slice.iter().map(|c| c.volume).sum();
-        let trade_count = slice.iter().map(|c| c.trade_count).sum();
-        let volume_usdt = if slice.iter().all(|c| c.volume_usdt.is_some()) {
-            Some(slice.iter().map(|c| c.volume_usdt.unwrap()).sum())
-        } else {
-            None
-        };
-        let mut candle = Candle {
-            instrument: slice[0].instrument.clone(),
-            interval: tf.clone(),
-            timestamp: truncate_to_tf(slice[0].timestamp, tf),
-            open, high, low, close, volume, trade_count, volume_usdt,
-            custom: HashMap::new(),
-        };
-        // Кастомные метрики (агрегация по цепочке)
-        i += count;
+fn aggregate_from_lower(lower: &[Candle], tf: &Timeframe, config: &CandleConfig) -> Vec<Candle> {
+    // Группируем по интервалу старшего таймфрейма, а не по количеству свечей:
+    // пропущенная младшая свеча не сдвигает последующие бакеты
+    let mut result: Vec<Candle> = Vec::new();
+    let align = &config.alignment;
+    for slice in lower.chunk_by(|a, b| align.truncate(a.timestamp, tf) == align.truncate(b.timestamp, tf)) {
+        let candle = rollup_bucket(slice, tf, config);
+        if let Some(prev) = result.last().filter(|_| config.fill_gaps) {
+            let filler = gap_candles(prev, candle.timestamp, config);
+            result.extend(filler);
+        }
         result.push(candle);
     }
     result
 }
 
-pub struct CandleAggregator {
-    pub config: CandleConfig,
+fn rollup_bucket(slice: &[Candle], tf: &Timeframe, config: &CandleConfig) -> Candle {
+    let open = slice.first().unwrap().open;
+    let close = slice.last().unwrap().close;
+    let high = slice.iter().map(|c| c.high).reduce(Num::max).unwrap();
+    let low = slice.iter().map(|c| c.low).reduce(Num::min).unwrap();
+    let volume = slice.iter().map(|c| c.volume).sum();
+    let trade_count = slice.iter().map(|c| c.trade_count).sum();
+    let volume_usdt = if slice.iter().all(|c| c.volume_usdt.is_some()) {
+        Some(slice.iter().map(|c| c.volume_usdt.unwrap()).sum())
+    } else {
+        None
+    };
+    let bucket = config.alignment.truncate(slice[0].timestamp, tf);
+    let expected = (config.alignment.next(bucket, tf) - bucket).num_seconds() / slice[0].interval.duration().num_seconds().max(1);
+    let incomplete = config.mark_incomplete
+        && ((slice.len() as i64) < expected || slice.iter().any(|c| c.incomplete));
+    let mut candle = Candle {
+        instrument: slice[0].instrument.clone(),
+        interval: tf.clone(),
+        timestamp: bucket,
+        open, high, low, close, volume, trade_count, volume_usdt,
+        custom: HashMap::new(),
+        incomplete,
+        revision: 0,
+        synthetic: slice.iter().all(|c| c.synthetic),
+    };
+    // Кастомные метрики (агрегация по цепочке)
+    for m in &config.custom_metrics {
+        m.aggregate(slice, &mut candle);
+    }
+    candle
 }
 
-impl Default for CandleAggregator {
-    fn default() -> Self {
-        Self { config: CandleConfig::default() }
-    }
+#[derive(Default)]
+pub struct CandleAggregator {
+    pub config: CandleConfig,
 }
 
 impl CandleAggregator {
@@ -216,7 +434,7 @@ impl CandleAggregator {
                 Some(c) => {
                     candles.push(c.clone());
                     current = Some(Candle {
-                        instrument: trade.instrument.clone(),
+                        instrument: Arc::new(trade.instrument.clone()),
                         interval: timeframe.clone(),
                         timestamp: ts,
                         open: trade.price,
@@ -227,11 +445,14 @@ impl CandleAggregator {
                         trade_count: 1,
                         volume_usdt: None, // через config
                         custom: HashMap::new(),
+                        incomplete: false,
+                        revision: 0,
+                        synthetic: false,
                     });
                 }
                 None => {
                     current = Some(Candle {
-                        instrument: trade.instrument.clone(),
+                        instrument: Arc::new(trade.instrument.clone()),
                         interval: timeframe.clone(),
                         timestamp: ts,
                         open: trade.price,
@@ -242,6 +463,9 @@ impl CandleAggregator {
                         trade_count: 1,
                         volume_usdt: None, // через config
                         custom: HashMap::new(),
+                        incomplete: false,
+                        revision: 0,
+                        synthetic: false,
                     });
                 }
             }
@@ -258,6 +482,20 @@ pub struct CandleConfig {
     pub basic_ohlcv: bool,
     pub volume_in_usdt: UsdtVolumeSource,
     pub custom_metrics: Vec<Box<dyn CandleMetric>>,
+    /// Помечать свечи старших таймфреймов, собранные из неполного набора младших
+    pub mark_incomplete: bool,
+    /// Что делать с трейдами, пришедшими после закрытия их интервала
+    pub late_policy: LatePolicy,
+    /// Выдавать синтетические свечи (`Candle::synthetic`) за интервалы без трейдов
+    pub fill_gaps: bool,
+    /// Часовой пояс и начало торговой сессии для h4/d1/w1/M1 (по умолчанию — UTC, полночь)
+    pub alignment: Alignment,
+    /// Торговые сессии: вне них свечи не открываются и пропуски не заполняются (по умолчанию 24/7)
+    pub calendar: Box<dyn SessionCalendar>,
+    /// Проверка входных трейдов (NaN, цена ≤ 0, отрицательный объём, чужой инструмент); по умолчанию выключена
+    pub validation: Validation,
+    /// Отбрасывать повторно присланные трейды (тот же `Trade.id` того же инструмента)
+    pub dedup: Dedup,
 }
 
 impl Default for CandleConfig {
@@ -266,11 +504,138 @@ impl Default for CandleConfig {
             basic_ohlcv: true,
             volume_in_usdt: UsdtVolumeSource::None,
             custom_metrics: vec![],
+            mark_incomplete: false,
+            late_policy: LatePolicy::default(),
+            fill_gaps: false,
+            alignment: Alignment::default(),
+            calendar: Box::new(AlwaysOpen),
+            validation: Validation::default(),
+            dedup: Dedup::default(),
+        }
+    }
+}
+
+/// Обработка поздних (out-of-order) трейдов, чей интервал раньше текущей свечи
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatePolicy {
+    /// Поздний трейд закрывает текущую свечу и открывает новую с более ранним timestamp
+    #[default]
+    Reopen,
+    /// Поздний трейд отбрасывается
+    Drop,
+    /// Поздний трейд вливается в уже выданную свечу, и она выдаётся повторно с `revision + 1`.
+    /// Свечи старше текущей больше чем на `Duration` не хранятся — такие трейды отбрасываются
+    Merge(Duration),
+    /// Свечи удерживаются, пока watermark (максимальный timestamp − `Duration`) не пройдёт их конец.
+    /// Трейды в уже выданные интервалы отбрасываются
+    Buffer(Duration),
+}
+
+/// Дедупликация трейдов по `Trade.id` (в пределах инструмента), например после переподключения
+/// к websocket с повтором истории
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dedup {
+    #[default]
+    Off,
+    /// Помнить последние N id
+    Window(usize),
+    /// Помнить id трейдов каждой открытой (или удерживаемой `LatePolicy`) свечи
+    PerCandle,
+}
+
+/// Счётчики поздних и отброшенных трейдов
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LateStats {
+    /// Всего трейдов с интервалом раньше текущей свечи
+    pub late: u64,
+    pub reopened: u64,
+    pub dropped: u64,
+    pub merged: u64,
+    pub buffered: u64,
+    /// Трейды вне торговой сессии (`CandleConfig::calendar`), отброшенные
+    pub outside_session: u64,
+    /// Некорректные трейды (`CandleConfig::validation`), отброшенные
+    pub invalid: u64,
+    /// Повторные трейды (`CandleConfig::dedup`), отброшенные
+    pub duplicates: u64,
+}
+
+impl std::ops::Add<&LateStats> for LateStats {
+    type Output = LateStats;
+
+    fn add(self, rhs: &LateStats) -> LateStats {
+        LateStats {
+            late: self.late + rhs.late,
+            reopened: self.reopened + rhs.reopened,
+            dropped: self.dropped + rhs.dropped,
+            merged: self.merged + rhs.merged,
+            buffered: self.buffered + rhs.buffered,
+            outside_session: self.outside_session + rhs.outside_session,
+            invalid: self.invalid + rhs.invalid,
+            duplicates: self.duplicates + rhs.duplicates,
         }
     }
 }
 
-pub trait CandleMetric {
-    fn update(&self, trade: &Trade, candle: &mut Candle);
+/// Ограничение метрик: с feature `parallel` — `Send + Sync` (метрики конфига делятся между потоками
+/// `par_aggregate`), без неё — никаких, метрика может держать `Rc`/`RefCell`
+#[cfg(feature = "parallel")]
+pub trait MetricBound: Send + Sync {}
+#[cfg(feature = "parallel")]
+impl<T: Send + Sync + ?Sized> MetricBound for T {}
+#[cfg(not(feature = "parallel"))]
+pub trait MetricBound {}
+#[cfg(not(feature = "parallel"))]
+impl<T: ?Sized> MetricBound for T {}
+
+pub trait CandleMetric: MetricBound {
+    fn update(&self, trade: &TradeRef, candle: &mut Candle);
+    /// Сводит значения метрики из младших свечей `src` в старшую `dst` (цепочка агрегации).
+    /// Для типовых случаев см. `MergeRule`
     fn aggregate(&self, src: &[Candle], dst: &mut Candle);
 }
+
+/// Встроенные правила слияния значения из `Candle::custom` при агрегации по цепочке.
+/// Свечи без ключа пропускаются; если ключа нет ни в одной, `dst` не меняется
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeRule {
+    Sum,
+    First,
+    Last,
+    Max,
+    Min,
+    /// Среднее, взвешенное по `volume` (VWAP и подобные)
+    VolumeWeightedMean,
+}
+
+impl MergeRule {
+    pub fn merge(&self, key: &str, src: &[Candle]) -> Option<f64> {
+        let mut values = src.iter().filter_map(|c| c.custom.get(key).map(|v| (*v, num_to_f64(c.volume)))).peekable();
+        values.peek()?;
+        Some(match self {
+            MergeRule::Sum => values.map(|(v, _)| v).sum(),
+            MergeRule::First => values.next().unwrap().0,
+            MergeRule::Last => values.last().unwrap().0,
+            MergeRule::Max => values.map(|(v, _)| v).fold(f64::MIN, f64::max),
+            MergeRule::Min => values.map(|(v, _)| v).fold(f64::MAX, f64::min),
+            MergeRule::VolumeWeightedMean => {
+                let (mut acc, mut weight, mut plain, mut n) = (0.0, 0.0, 0.0, 0);
+                for (v, w) in values {
+                    acc += v * w;
+                    weight += w;
+                    plain += v;
+                    n += 1;
+                }
+                // Нулевой объём — обычное среднее
+                if weight > 0.0 { acc / weight } else { plain / n as f64 }
+            }
+        })
+    }
+
+    /// Записывает результат `merge` в `dst.custom[key]`
+    pub fn apply(&self, key: &str, src: &[Candle], dst: &mut Candle) {
+        if let Some(v) = self.merge(key, src) {
+            dst.custom.insert(key.to_string(), v);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{gap_candles, Candle, CandleGenerator, Dedup, LateStats, Timeframe, Trade, Validation};
+use rayon::prelude::*;
+
+impl CandleGenerator {
+    /// `aggregate` отсортированного по времени среза на пуле потоков rayon: срез режется на границах
+    /// свечей, куски агрегируются параллельно, результаты склеиваются (с `fill_gaps` — вместе с
+    /// пропусками на стыках). Каждая свеча целиком собирается в одном куске, поэтому результат,
+    /// включая кастомные метрики и счётчики, совпадает с `aggregate`.
+    ///
+    /// Неотсортированный срез и `Dedup::Window` (окно тянется через границы кусков) агрегируются
+    /// последовательно
+    pub fn par_aggregate(&self, trades: &[Trade], timeframe: Timeframe) -> Vec<Candle> {
+        self.par_aggregate_with_stats(trades, timeframe).0
+    }
+
+    /// `par_aggregate` со счётчиками (см. `aggregate_with_stats`)
+    pub fn par_aggregate_with_stats(&self, trades: &[Trade], timeframe: Timeframe) -> (Vec<Candle>, LateStats) {
+        let sorted = trades.par_windows(2).all(|w| w[0].timestamp <= w[1].timestamp);
+        if !sorted || matches!(self.config.dedup, Dedup::Window(_)) {
+            return self.aggregate_with_stats(trades.iter(), timeframe);
+        }
+        // Последовательный билдер сверяет инструмент с первым корректным трейдом всего потока
+        let expected = match self.config.validation {
+            Validation::Full => trades.iter().find(|t| Validation::Values.check(&t.view(), None).is_ok()).map(|t| t.instrument.clone()),
+            _ => None,
+        };
+        let parts: Vec<(Vec<Candle>, LateStats)> = self
+            .split_at_candles(trades, &timeframe)
+            .into_par_iter()
+            .map(|chunk| {
+                let mut builder = self.builder(timeframe.clone());
+                if let Some(instrument) = &expected {
+                    builder.expect_instrument(instrument.clone());
+                }
+                let mut candles = Vec::new();
+                for trade in chunk {
+                    builder.push_ref(&trade.view(), &mut candles);
+                }
+                candles.extend(builder.flush());
+                (candles, builder.late_stats().clone())
+            })
+            .collect();
+
+        let mut candles: Vec<Candle> = Vec::with_capacity(parts.iter().map(|(c, _)| c.len()).sum());
+        let mut stats = LateStats::default();
+        for (part, part_stats) in parts {
+            if let (Some(prev), Some(next)) = (candles.last().filter(|_| self.config.fill_gaps), part.first()) {
+                let filler = gap_candles(prev, next.timestamp, &self.config);
+                candles.extend(filler);
+            }
+            candles.extend(part);
+            stats = stats + &part_stats;
+        }
+        (candles, stats)
+    }
+
+    /// Режет срез примерно на 4 куска на поток, сдвигая границы к началу следующей свечи
+    fn split_at_candles<'t>(&self, trades: &'t [Trade], timeframe: &Timeframe) -> Vec<&'t [Trade]> {
+        let parts = rayon::current_num_threads() * 4;
+        let bucket = |t: &Trade| self.config.alignment.truncate(t.timestamp, timeframe);
+        let mut chunks = Vec::with_capacity(parts);
+        let mut start = 0;
+        for k in 1..parts {
+            let mut end = (trades.len() * k / parts).max(start);
+            if end == 0 || end >= trades.len() {
+                continue;
+            }
+            // Срез отсортирован, значит и бакеты идут по возрастанию
+            let b = bucket(&trades[end - 1]);
+            end += trades[end..].partition_point(|t| bucket(t) == b);
+            if end > start {
+                chunks.push(&trades[start..end]);
+                start = end;
+            }
+        }
+        if start < trades.len() {
+            chunks.push(&trades[start..]);
+        }
+        chunks
+    }
+}
@@ -0,0 +1,179 @@
+use crate::arrow::{datetime_from, string_column, timestamp_column, timestamp_unit};
+use crate::{candles_from_batch, candles_to_batch, trades_from_batch, Candle, Instrument, InstrumentMapping, MarketType, Trade, TradeColumnMapping};
+pub use ::parquet::basic::{Compression, ZstdLevel};
+use ::parquet::arrow::arrow_reader::{ArrowPredicateFn, ParquetRecordBatchReaderBuilder, RowFilter};
+use ::parquet::arrow::{ArrowWriter, ProjectionMask};
+use ::parquet::errors::ParquetError;
+use ::parquet::file::metadata::RowGroupMetaData;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::statistics::Statistics;
+use arrow_array::{BooleanArray, RecordBatch};
+use arrow_schema::{ArrowError, TimeUnit};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::path::Path;
+
+/// Параметры записи свечей
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParquetWriteOptions {
+    /// Максимум строк в row group
+    pub row_group_size: usize,
+    pub compression: Compression,
+}
+
+impl Default for ParquetWriteOptions {
+    fn default() -> Self {
+        Self { row_group_size: 1024 * 1024, compression: Compression::ZSTD(ZstdLevel::default()) }
+    }
+}
+
+/// Отбор трейдов при чтении: время в `[from, to)` и инструмент. Row group'ы, которые по статистике
+/// не могут содержать подходящих трейдов, не читаются; остальные фильтруются построчно до разбора
+/// в `Trade`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TradeFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub instrument: Option<Instrument>,
+}
+
+/// Все трейды файла (см. `TradeColumnMapping`)
+pub fn read_trades(path: impl AsRef<Path>, mapping: &TradeColumnMapping) -> Result<Vec<Trade>, ParquetError> {
+    read_trades_filtered(path, mapping, &TradeFilter::default())
+}
+
+/// Трейды файла, подходящие под `filter`
+pub fn read_trades_filtered(path: impl AsRef<Path>, mapping: &TradeColumnMapping, filter: &TradeFilter) -> Result<Vec<Trade>, ParquetError> {
+    let mut builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    // Колонки инструмента с ожидаемыми значениями и колонка типа рынка
+    let (columns, market_column) = match (&mapping.instrument, &filter.instrument) {
+        // Инструмент задан для всего файла — сравниваем один раз
+        (InstrumentMapping::Fixed(fixed), Some(wanted)) if fixed != wanted => return Ok(Vec::new()),
+        (InstrumentMapping::Fixed(_), _) | (_, None) => (Vec::new(), None),
+        (InstrumentMapping::Columns { exchange, base, quote, market_type }, Some(wanted)) => (
+            vec![
+                (exchange.clone(), wanted.exchange.clone()),
+                (base.clone(), wanted.pair.base_id.clone()),
+                (quote.clone(), wanted.pair.quote_id.clone()),
+            ],
+            market_type.clone(),
+        ),
+    };
+    let ranged = filter.from.is_some() || filter.to.is_some();
+    if ranged || !columns.is_empty() {
+        let arrow_schema = builder.schema().clone();
+        let field = |name: &str| arrow_schema.index_of(name).map_err(ParquetError::from);
+        let ts_index = field(&mapping.timestamp)?;
+        let unit = timestamp_unit(&mapping.timestamp, arrow_schema.field(ts_index).data_type())?;
+        let string_indices = columns.iter().map(|(name, _)| field(name)).collect::<Result<Vec<_>, _>>()?;
+
+        // Статистика — по листьям parquet: при вложенных колонках (struct, list) их больше, чем полей
+        // arrow, и индексы не совпадают. Колонка без своего листа верхнего уровня не отсекает row group'ы
+        let parquet_schema = builder.parquet_schema();
+        let leaf = |name: &str| parquet_schema.columns().iter().position(|c| matches!(c.path().parts(), [part] if part == name));
+        let ts_leaf = leaf(&mapping.timestamp);
+        let string_leaves: Vec<Option<usize>> = columns.iter().map(|(name, _)| leaf(name)).collect();
+        let metadata = builder.metadata().clone();
+        let groups: Vec<usize> = (0..metadata.num_row_groups())
+            .filter(|&i| {
+                let group = metadata.row_group(i);
+                (!ranged || ts_leaf.is_none_or(|leaf| time_may_match(group, leaf, unit, filter)))
+                    && columns.iter().zip(&string_leaves).all(|((_, value), leaf)| leaf.is_none_or(|leaf| string_may_match(group, leaf, value)))
+            })
+            .collect();
+
+        let mut indices = string_indices;
+        if ranged {
+            indices.push(ts_index);
+        }
+        if let Some(name) = &market_column {
+            indices.push(field(name)?);
+        }
+        let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+        let (ts_name, filter) = (ranged.then(|| mapping.timestamp.clone()), filter.clone());
+        let predicate = ArrowPredicateFn::new(mask, move |batch: RecordBatch| {
+            row_mask(&batch, ts_name.as_deref(), &columns, market_column.as_deref(), &filter)
+        });
+        builder = builder.with_row_groups(groups).with_row_filter(RowFilter::new(vec![Box::new(predicate)]));
+    }
+
+    let mut trades = Vec::new();
+    for batch in builder.build()? {
+        trades.extend(trades_from_batch(&batch?, mapping)?);
+    }
+    Ok(trades)
+}
+
+/// Какие строки батча (только колонки фильтра) подходят
+fn row_mask(
+    batch: &RecordBatch,
+    ts_name: Option<&str>,
+    columns: &[(String, String)],
+    market_column: Option<&str>,
+    filter: &TradeFilter,
+) -> Result<BooleanArray, ArrowError> {
+    let mut keep = vec![true; batch.num_rows()];
+    if let Some(name) = ts_name {
+        let timestamps = timestamp_column(batch, name)?;
+        for (row, k) in keep.iter_mut().enumerate() {
+            *k = timestamps.get(row)?.is_some_and(|ts| filter.from.is_none_or(|from| ts >= from) && filter.to.is_none_or(|to| ts < to));
+        }
+    }
+    for (name, value) in columns {
+        for (k, v) in keep.iter_mut().zip(string_column(batch, name)?.array.iter()) {
+            *k = *k && v == Some(value.as_str());
+        }
+    }
+    if let (Some(name), Some(wanted)) = (market_column, &filter.instrument) {
+        for (k, v) in keep.iter_mut().zip(string_column(batch, name)?.array.iter()) {
+            *k = *k && v.is_some_and(|v| MarketType::from(v) == wanted.market_type);
+        }
+    }
+    Ok(BooleanArray::from(keep))
+}
+
+/// Может ли row group содержать время из `[from, to)` (без статистики — может)
+fn time_may_match(group: &RowGroupMetaData, leaf: usize, unit: TimeUnit, filter: &TradeFilter) -> bool {
+    let (min, max) = match group.column(leaf).statistics() {
+        Some(Statistics::Int64(s)) => (s.min_opt().copied(), s.max_opt().copied()),
+        Some(Statistics::Int32(s)) => (s.min_opt().map(|v| *v as i64), s.max_opt().map(|v| *v as i64)),
+        _ => (None, None),
+    };
+    let (min, max) = (min.and_then(|v| datetime_from(v, unit)), max.and_then(|v| datetime_from(v, unit)));
+    filter.from.zip(max).is_none_or(|(from, max)| max >= from) && filter.to.zip(min).is_none_or(|(to, min)| min < to)
+}
+
+/// Может ли строковая колонка row group содержать `value`
+fn string_may_match(group: &RowGroupMetaData, leaf: usize, value: &str) -> bool {
+    let Some(Statistics::ByteArray(s)) = group.column(leaf).statistics() else {
+        return true;
+    };
+    let (min, max) = (s.min_opt().and_then(|v| v.as_utf8().ok()), s.max_opt().and_then(|v| v.as_utf8().ok()));
+    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+}
+
+/// Свечи в файл со схемой `candle_schema` и параметрами по умолчанию
+pub fn write_candles(path: impl AsRef<Path>, candles: &[Candle]) -> Result<(), ParquetError> {
+    write_candles_with_options(path, candles, &ParquetWriteOptions::default())
+}
+
+pub fn write_candles_with_options(path: impl AsRef<Path>, candles: &[Candle], options: &ParquetWriteOptions) -> Result<(), ParquetError> {
+    let batch = candles_to_batch(candles)?;
+    let props = WriterProperties::builder()
+        .set_max_row_group_size(options.row_group_size.max(1))
+        .set_compression(options.compression)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Свечи, записанные `write_candles`
+pub fn read_candles(path: impl AsRef<Path>) -> Result<Vec<Candle>, ParquetError> {
+    let mut candles = Vec::new();
+    for batch in ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()? {
+        candles.extend(candles_from_batch(&batch?)?);
+    }
+    Ok(candles)
+}
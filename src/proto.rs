@@ -0,0 +1,320 @@
+use crate::types::parse_num;
+use crate::{BarKind, Candle, Instrument, MarketType, Num, Pair, Side, Timeframe, Trade};
+use chrono::{DateTime, Duration, Utc};
+use std::fmt;
+use std::sync::Arc;
+
+/// Типы схемы `proto/candle_generator.proto` (пакет `candle_generator.v1`). Файл сгенерирован
+/// prost-build 0.14 (`tools/protogen`); после правки схемы — перегенерировать, руками не менять.
+/// `-- --check` у того же инструмента сверяет файл со схемой
+#[allow(clippy::all)]
+pub mod pb {
+    include!("proto/candle_generator.v1.rs");
+}
+
+/// Сообщение protobuf не переводится в тип крейта: нет обязательного поля, незнакомое значение
+/// enum, неразборное число или время вне диапазона
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtoError(pub String);
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid protobuf message: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+impl From<prost::UnknownEnumValue> for ProtoError {
+    fn from(e: prost::UnknownEnumValue) -> Self {
+        ProtoError(e.to_string())
+    }
+}
+
+/// `From<T>` через `From<&T>`
+macro_rules! from_owned {
+    ($($t:ty => $pb:ty),* $(,)?) => {$(
+        impl From<$t> for $pb {
+            fn from(v: $t) -> Self {
+                Self::from(&v)
+            }
+        }
+    )*};
+}
+
+from_owned!(
+    Pair => pb::TradingPair,
+    MarketType => pb::MarketType,
+    Instrument => pb::Instrument,
+    Trade => pb::Trade,
+    Timeframe => pb::Timeframe,
+    Candle => pb::Candle,
+);
+
+fn required<T>(field: Option<T>, name: &str) -> Result<T, ProtoError> {
+    field.ok_or_else(|| ProtoError(format!("missing {}", name)))
+}
+
+/// Числа — десятичной строкой: `Display` у `f64` и `Decimal` разбирается обратно в то же значение.
+/// NaN и бесконечности — ошибка
+fn num_from(s: &str, name: &str) -> Result<Num, ProtoError> {
+    parse_num(s).ok_or_else(|| ProtoError(format!("bad {} {:?}", name, s)))
+}
+
+fn timestamp_to(ts: &DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp { seconds: ts.timestamp(), nanos: ts.timestamp_subsec_nanos() as i32 }
+}
+
+fn timestamp_from(ts: Option<prost_types::Timestamp>) -> Result<DateTime<Utc>, ProtoError> {
+    let ts = required(ts, "timestamp")?;
+    u32::try_from(ts.nanos)
+        .ok()
+        .and_then(|nanos| DateTime::from_timestamp(ts.seconds, nanos))
+        .ok_or_else(|| ProtoError(format!("timestamp {}.{:09} out of range", ts.seconds, ts.nanos)))
+}
+
+impl From<&Pair> for pb::TradingPair {
+    fn from(p: &Pair) -> Self {
+        Self { base_id: p.base_id.clone(), quote_id: p.quote_id.clone() }
+    }
+}
+
+impl From<pb::TradingPair> for Pair {
+    fn from(p: pb::TradingPair) -> Self {
+        Self { base_id: p.base_id, quote_id: p.quote_id }
+    }
+}
+
+/// `Unknown` — `MARKET_TYPE_UNSPECIFIED`
+impl From<&MarketType> for pb::MarketType {
+    fn from(m: &MarketType) -> Self {
+        match m {
+            MarketType::Spot => pb::MarketType::Spot,
+            MarketType::Futures => pb::MarketType::Futures,
+            MarketType::Margin => pb::MarketType::Margin,
+            MarketType::Unknown => pb::MarketType::Unspecified,
+        }
+    }
+}
+
+impl From<pb::MarketType> for MarketType {
+    fn from(m: pb::MarketType) -> Self {
+        match m {
+            pb::MarketType::Spot => MarketType::Spot,
+            pb::MarketType::Futures => MarketType::Futures,
+            pb::MarketType::Margin => MarketType::Margin,
+            pb::MarketType::Unspecified => MarketType::Unknown,
+        }
+    }
+}
+
+impl From<Side> for pb::Side {
+    fn from(s: Side) -> Self {
+        match s {
+            Side::Buy => pb::Side::Buy,
+            Side::Sell => pb::Side::Sell,
+            Side::Unknown => pb::Side::Unspecified,
+        }
+    }
+}
+
+impl From<pb::Side> for Side {
+    fn from(s: pb::Side) -> Self {
+        match s {
+            pb::Side::Buy => Side::Buy,
+            pb::Side::Sell => Side::Sell,
+            pb::Side::Unspecified => Side::Unknown,
+        }
+    }
+}
+
+impl From<&Instrument> for pb::Instrument {
+    fn from(i: &Instrument) -> Self {
+        Self {
+            pair: Some((&i.pair).into()),
+            exchange: i.exchange.clone(),
+            market_type: pb::MarketType::from(&i.market_type) as i32,
+        }
+    }
+}
+
+impl TryFrom<pb::Instrument> for Instrument {
+    type Error = ProtoError;
+
+    fn try_from(i: pb::Instrument) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pair: required(i.pair, "instrument.pair")?.into(),
+            exchange: i.exchange,
+            market_type: pb::MarketType::try_from(i.market_type)?.into(),
+        })
+    }
+}
+
+impl From<&Trade> for pb::Trade {
+    fn from(t: &Trade) -> Self {
+        Self {
+            instrument: Some((&t.instrument).into()),
+            id: t.id.clone(),
+            price: t.price.to_string(),
+            amount: t.amount.to_string(),
+            side: pb::Side::from(t.side) as i32,
+            timestamp: Some(timestamp_to(&t.timestamp)),
+        }
+    }
+}
+
+impl TryFrom<pb::Trade> for Trade {
+    type Error = ProtoError;
+
+    fn try_from(t: pb::Trade) -> Result<Self, Self::Error> {
+        Ok(Self {
+            instrument: required(t.instrument, "instrument")?.try_into()?,
+            id: t.id,
+            price: num_from(&t.price, "price")?,
+            amount: num_from(&t.amount, "amount")?,
+            side: pb::Side::try_from(t.side)?.into(),
+            timestamp: timestamp_from(t.timestamp)?,
+        })
+    }
+}
+
+const INTERVALS: [(Timeframe, pb::Interval); 13] = [
+    (Timeframe::s1, pb::Interval::S1),
+    (Timeframe::s5, pb::Interval::S5),
+    (Timeframe::s15, pb::Interval::S15),
+    (Timeframe::s30, pb::Interval::S30),
+    (Timeframe::m1, pb::Interval::M1),
+    (Timeframe::m5, pb::Interval::M5),
+    (Timeframe::m15, pb::Interval::M15),
+    (Timeframe::m30, pb::Interval::M30),
+    (Timeframe::h1, pb::Interval::H1),
+    (Timeframe::h4, pb::Interval::H4),
+    (Timeframe::d1, pb::Interval::D1),
+    (Timeframe::w1, pb::Interval::W1),
+    (Timeframe::M1, pb::Interval::Month1),
+];
+
+impl From<&BarKind> for pb::BarKind {
+    fn from(kind: &BarKind) -> Self {
+        use pb::bar_kind::Kind;
+        let adaptive = |initial: u64, span: u32| pb::AdaptiveBar { initial, span };
+        let kind = match *kind {
+            BarKind::Tick(n) => Kind::Tick(n),
+            BarKind::Volume(v) => Kind::Volume(v),
+            BarKind::Dollar(v) => Kind::Dollar(v),
+            BarKind::Range(v) => Kind::Range(v),
+            BarKind::Renko { brick, reversal } => Kind::Renko(pb::RenkoBar { brick, reversal }),
+            BarKind::TickImbalance { initial, span } => Kind::TickImbalance(adaptive(initial, span)),
+            BarKind::VolumeImbalance { initial, span } => Kind::VolumeImbalance(adaptive(initial, span)),
+            BarKind::TickRun { initial, span } => Kind::TickRun(adaptive(initial, span)),
+            BarKind::VolumeRun { initial, span } => Kind::VolumeRun(adaptive(initial, span)),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+/// Порог проверяется `BarKind::is_valid`: нулевой или нечисловой — ошибка
+impl TryFrom<pb::BarKind> for BarKind {
+    type Error = ProtoError;
+
+    fn try_from(kind: pb::BarKind) -> Result<Self, Self::Error> {
+        use pb::bar_kind::Kind;
+        let kind = match required(kind.kind, "bar kind")? {
+            Kind::Tick(n) => BarKind::Tick(n),
+            Kind::Volume(v) => BarKind::Volume(v),
+            Kind::Dollar(v) => BarKind::Dollar(v),
+            Kind::Range(v) => BarKind::Range(v),
+            Kind::Renko(r) => BarKind::Renko { brick: r.brick, reversal: r.reversal },
+            Kind::TickImbalance(a) => BarKind::TickImbalance { initial: a.initial, span: a.span },
+            Kind::VolumeImbalance(a) => BarKind::VolumeImbalance { initial: a.initial, span: a.span },
+            Kind::TickRun(a) => BarKind::TickRun { initial: a.initial, span: a.span },
+            Kind::VolumeRun(a) => BarKind::VolumeRun { initial: a.initial, span: a.span },
+        };
+        if !kind.is_valid() {
+            return Err(ProtoError(format!("invalid bar threshold {}", kind)));
+        }
+        Ok(kind)
+    }
+}
+
+/// Стандартные — `Interval`, `Custom` — `google.protobuf.Duration`, бары — `BarKind`
+impl From<&Timeframe> for pb::Timeframe {
+    fn from(tf: &Timeframe) -> Self {
+        use pb::timeframe::Kind;
+        let kind = match tf {
+            Timeframe::Custom(w) => {
+                let d = w.duration();
+                Kind::Custom(prost_types::Duration { seconds: d.num_seconds(), nanos: d.subsec_nanos() })
+            }
+            Timeframe::Bar(kind) => Kind::Bar(kind.into()),
+            tf => Kind::Interval(INTERVALS.iter().find(|(t, _)| t == tf).map_or(pb::Interval::Unspecified, |(_, i)| *i) as i32),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<pb::Timeframe> for Timeframe {
+    type Error = ProtoError;
+
+    fn try_from(tf: pb::Timeframe) -> Result<Self, Self::Error> {
+        use pb::timeframe::Kind;
+        match required(tf.kind, "timeframe")? {
+            Kind::Interval(i) => {
+                let interval = pb::Interval::try_from(i)?;
+                let found = INTERVALS.iter().find(|(_, pb)| *pb == interval).map(|(tf, _)| tf.clone());
+                found.ok_or_else(|| ProtoError(format!("unspecified interval {}", i)))
+            }
+            // Ширина стандартного таймфрейма даёт стандартный (как `Timeframe::from_duration`)
+            Kind::Custom(d) => Duration::try_seconds(d.seconds)
+                .and_then(|secs| secs.checked_add(&Duration::nanoseconds(d.nanos.into())))
+                .and_then(Timeframe::from_duration)
+                .ok_or_else(|| ProtoError(format!("bad timeframe width {}s {}ns", d.seconds, d.nanos))),
+            Kind::Bar(kind) => Ok(Timeframe::Bar(kind.try_into()?)),
+        }
+    }
+}
+
+impl From<&Candle> for pb::Candle {
+    fn from(c: &Candle) -> Self {
+        Self {
+            instrument: Some(c.instrument.as_ref().into()),
+            interval: Some((&c.interval).into()),
+            timestamp: Some(timestamp_to(&c.timestamp)),
+            open: c.open.to_string(),
+            high: c.high.to_string(),
+            low: c.low.to_string(),
+            close: c.close.to_string(),
+            volume: c.volume.to_string(),
+            trade_count: c.trade_count,
+            volume_usdt: c.volume_usdt.map(|v| v.to_string()),
+            custom: c.custom.clone(),
+            incomplete: c.incomplete,
+            revision: c.revision,
+            synthetic: c.synthetic,
+        }
+    }
+}
+
+/// Инструмент каждой свечи — свой `Arc`; общий для потока свечей можно восстановить по равенству
+impl TryFrom<pb::Candle> for Candle {
+    type Error = ProtoError;
+
+    fn try_from(c: pb::Candle) -> Result<Self, Self::Error> {
+        Ok(Self {
+            instrument: Arc::new(required(c.instrument, "instrument")?.try_into()?),
+            interval: required(c.interval, "interval")?.try_into()?,
+            timestamp: timestamp_from(c.timestamp)?,
+            open: num_from(&c.open, "open")?,
+            high: num_from(&c.high, "high")?,
+            low: num_from(&c.low, "low")?,
+            close: num_from(&c.close, "close")?,
+            volume: num_from(&c.volume, "volume")?,
+            trade_count: c.trade_count,
+            volume_usdt: c.volume_usdt.as_deref().map(|v| num_from(v, "volume_usdt")).transpose()?,
+            custom: c.custom,
+            incomplete: c.incomplete,
+            revision: c.revision,
+            synthetic: c.synthetic,
+        })
+    }
+}
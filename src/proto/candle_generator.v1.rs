@@ -0,0 +1,256 @@
+// This file is @generated by prost-build.
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct TradingPair {
+    /// Например, "BTC"
+    #[prost(string, tag = "1")]
+    pub base_id: ::prost::alloc::string::String,
+    /// Например, "USDT"
+    #[prost(string, tag = "2")]
+    pub quote_id: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Instrument {
+    #[prost(message, optional, tag = "1")]
+    pub pair: ::core::option::Option<TradingPair>,
+    /// Например, "binance"
+    #[prost(string, tag = "2")]
+    pub exchange: ::prost::alloc::string::String,
+    #[prost(enumeration = "MarketType", tag = "3")]
+    pub market_type: i32,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct Trade {
+    #[prost(message, optional, tag = "1")]
+    pub instrument: ::core::option::Option<Instrument>,
+    #[prost(string, tag = "2")]
+    pub id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub price: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub amount: ::prost::alloc::string::String,
+    #[prost(enumeration = "Side", tag = "5")]
+    pub side: i32,
+    #[prost(message, optional, tag = "6")]
+    pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+}
+/// Renko: кирпич `brick`, разворот на `reversal` кирпичей
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RenkoBar {
+    #[prost(double, tag = "1")]
+    pub brick: f64,
+    #[prost(uint32, tag = "2")]
+    pub reversal: u32,
+}
+/// Imbalance и run bars: первый бар — `initial` трейдов, EWMA с периодом `span`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct AdaptiveBar {
+    #[prost(uint64, tag = "1")]
+    pub initial: u64,
+    #[prost(uint32, tag = "2")]
+    pub span: u32,
+}
+/// Правило закрытия бара (`BarKind`)
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct BarKind {
+    #[prost(oneof = "bar_kind::Kind", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    pub kind: ::core::option::Option<bar_kind::Kind>,
+}
+/// Nested message and enum types in `BarKind`.
+pub mod bar_kind {
+    #[derive(Clone, Copy, PartialEq, ::prost::Oneof)]
+    pub enum Kind {
+        #[prost(uint64, tag = "1")]
+        Tick(u64),
+        #[prost(double, tag = "2")]
+        Volume(f64),
+        #[prost(double, tag = "3")]
+        Dollar(f64),
+        #[prost(double, tag = "4")]
+        Range(f64),
+        #[prost(message, tag = "5")]
+        Renko(super::RenkoBar),
+        #[prost(message, tag = "6")]
+        TickImbalance(super::AdaptiveBar),
+        #[prost(message, tag = "7")]
+        VolumeImbalance(super::AdaptiveBar),
+        #[prost(message, tag = "8")]
+        TickRun(super::AdaptiveBar),
+        #[prost(message, tag = "9")]
+        VolumeRun(super::AdaptiveBar),
+    }
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct Timeframe {
+    #[prost(oneof = "timeframe::Kind", tags = "1, 2, 3")]
+    pub kind: ::core::option::Option<timeframe::Kind>,
+}
+/// Nested message and enum types in `Timeframe`.
+pub mod timeframe {
+    #[derive(Clone, Copy, PartialEq, ::prost::Oneof)]
+    pub enum Kind {
+        #[prost(enumeration = "super::Interval", tag = "1")]
+        Interval(i32),
+        /// Произвольная фиксированная ширина (`Timeframe::Custom`)
+        #[prost(message, tag = "2")]
+        Custom(::prost_types::Duration),
+        #[prost(message, tag = "3")]
+        Bar(super::BarKind),
+    }
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Candle {
+    #[prost(message, optional, tag = "1")]
+    pub instrument: ::core::option::Option<Instrument>,
+    #[prost(message, optional, tag = "2")]
+    pub interval: ::core::option::Option<Timeframe>,
+    #[prost(message, optional, tag = "3")]
+    pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    #[prost(string, tag = "4")]
+    pub open: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub high: ::prost::alloc::string::String,
+    #[prost(string, tag = "6")]
+    pub low: ::prost::alloc::string::String,
+    #[prost(string, tag = "7")]
+    pub close: ::prost::alloc::string::String,
+    #[prost(string, tag = "8")]
+    pub volume: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "9")]
+    pub trade_count: u64,
+    /// Не задан — курс в USDT неизвестен
+    #[prost(string, optional, tag = "10")]
+    pub volume_usdt: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(map = "string, double", tag = "11")]
+    pub custom: ::std::collections::HashMap<::prost::alloc::string::String, f64>,
+    #[prost(bool, tag = "12")]
+    pub incomplete: bool,
+    #[prost(uint32, tag = "13")]
+    pub revision: u32,
+    #[prost(bool, tag = "14")]
+    pub synthetic: bool,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum MarketType {
+    Unspecified = 0,
+    Spot = 1,
+    Futures = 2,
+    Margin = 3,
+}
+impl MarketType {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "MARKET_TYPE_UNSPECIFIED",
+            Self::Spot => "SPOT",
+            Self::Futures => "FUTURES",
+            Self::Margin => "MARGIN",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "MARKET_TYPE_UNSPECIFIED" => Some(Self::Unspecified),
+            "SPOT" => Some(Self::Spot),
+            "FUTURES" => Some(Self::Futures),
+            "MARGIN" => Some(Self::Margin),
+            _ => None,
+        }
+    }
+}
+/// Сторона агрессора (taker)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Side {
+    Unspecified = 0,
+    Buy = 1,
+    Sell = 2,
+}
+impl Side {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "SIDE_UNSPECIFIED",
+            Self::Buy => "BUY",
+            Self::Sell => "SELL",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "SIDE_UNSPECIFIED" => Some(Self::Unspecified),
+            "BUY" => Some(Self::Buy),
+            "SELL" => Some(Self::Sell),
+            _ => None,
+        }
+    }
+}
+/// Стандартные интервалы: коды как в `Timeframe` ("m1", "M1" — календарный месяц)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Interval {
+    Unspecified = 0,
+    S1 = 1,
+    S5 = 2,
+    S15 = 3,
+    S30 = 4,
+    M1 = 5,
+    M5 = 6,
+    M15 = 7,
+    M30 = 8,
+    H1 = 9,
+    H4 = 10,
+    D1 = 11,
+    W1 = 12,
+    Month1 = 13,
+}
+impl Interval {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "INTERVAL_UNSPECIFIED",
+            Self::S1 => "S1",
+            Self::S5 => "S5",
+            Self::S15 => "S15",
+            Self::S30 => "S30",
+            Self::M1 => "M1",
+            Self::M5 => "M5",
+            Self::M15 => "M15",
+            Self::M30 => "M30",
+            Self::H1 => "H1",
+            Self::H4 => "H4",
+            Self::D1 => "D1",
+            Self::W1 => "W1",
+            Self::Month1 => "MONTH1",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "INTERVAL_UNSPECIFIED" => Some(Self::Unspecified),
+            "S1" => Some(Self::S1),
+            "S5" => Some(Self::S5),
+            "S15" => Some(Self::S15),
+            "S30" => Some(Self::S30),
+            "M1" => Some(Self::M1),
+            "M5" => Some(Self::M5),
+            "M15" => Some(Self::M15),
+            "M30" => Some(Self::M30),
+            "H1" => Some(Self::H1),
+            "H4" => Some(Self::H4),
+            "D1" => Some(Self::D1),
+            "W1" => Some(Self::W1),
+            "MONTH1" => Some(Self::Month1),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{Instrument, MarketType, Pair};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Ошибка разбора символа или инструмента
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSymbolError(pub String);
+
+impl fmt::Display for ParseSymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid symbol: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSymbolError {}
+
+/// Правила разбора биржевых символов.
+///
+/// Понимает символы с разделителем (`BTC/USDT`, `BTC-USDT`, `btc_usdt`) и слитные (`BTCUSDT` —
+/// по самому длинному известному котируемому активу в конце). Суффиксы деривативов задают тип
+/// рынка: `-SWAP`, `PERP` (`-PERP`, `_PERP`), `.P`, расчётный актив ccxt (`BTC/USDT:USDT`), дата
+/// экспирации (`BTC-USD-240628`, `BTCUSDT_240628`) — фьючерсы; опционы (`BTC-USD-240628-30000-C`) —
+/// `MarketType::Unknown`. Бессрочные без котируемого актива (`BTC-PERP`, `BTCPERP`) получают
+/// `perp_quote`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRules {
+    /// Котируемые активы для слитных символов
+    pub quotes: Vec<String>,
+    /// Биржевой тикер → канонический (`XBT` → `BTC`), ключи в верхнем регистре
+    pub aliases: HashMap<String, String>,
+    /// Котируемый актив бессрочных, если в символе только база; `None` — такие символы ошибочны
+    pub perp_quote: Option<String>,
+}
+
+impl Default for SymbolRules {
+    fn default() -> Self {
+        let quotes = [
+            "USDT", "USDC", "FDUSD", "BUSD", "TUSD", "DAI", "USD", "EUR", "GBP", "JPY", "TRY", "BRL",
+            "BTC", "ETH", "BNB",
+        ];
+        let aliases = [("XBT", "BTC"), ("XDG", "DOGE")];
+        Self {
+            quotes: quotes.iter().map(|q| q.to_string()).collect(),
+            aliases: aliases.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            perp_quote: Some("USD".to_string()),
+        }
+    }
+}
+
+impl SymbolRules {
+    /// Пара и тип рынка по суффиксу (`None` — суффикса нет, тип рынка из символа не виден)
+    pub fn split(&self, symbol: &str) -> Result<(Pair, Option<MarketType>), ParseSymbolError> {
+        let err = || ParseSymbolError(symbol.to_string());
+        let upper = symbol.trim().to_ascii_uppercase();
+        let mut market = None;
+        let mut s = upper.as_str();
+        if let Some((head, _settle)) = s.split_once(':') {
+            s = head;
+            market = Some(MarketType::Futures);
+        }
+        let mut perp = false;
+        if let Some((head, suffix)) = ["-SWAP", "-PERP", "_PERP", ".P", "PERP"].iter().find_map(|suffix| Some((s.strip_suffix(suffix)?, suffix))) {
+            s = head;
+            perp = suffix.ends_with("PERP");
+            market = Some(MarketType::Futures);
+        }
+
+        let mut parts: Vec<&str> = s.split(['/', '-', '_']).collect();
+        let is_date = |p: &str| p.len() == 6 && p.bytes().all(|b| b.is_ascii_digit());
+        let is_number = |p: &str| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+        match parts[..] {
+            [.., date, strike, "C" | "P"] if is_date(date) && is_number(strike) => {
+                parts.truncate(parts.len() - 3);
+                market = Some(MarketType::Unknown);
+            }
+            [_, .., date] if is_date(date) => {
+                parts.pop();
+                market = Some(MarketType::Futures);
+            }
+            _ => {}
+        }
+
+        let (base, quote) = match parts[..] {
+            [base, quote] if !base.is_empty() && !quote.is_empty() => (base, quote),
+            [joined] => match (self.split_joined(joined), &self.perp_quote) {
+                (Some(pair), _) => pair,
+                (None, Some(quote)) if perp && !joined.is_empty() => (joined, quote.as_str()),
+                _ => return Err(err()),
+            },
+            _ => return Err(err()),
+        };
+        Ok((Pair { base_id: self.canonical(base), quote_id: self.canonical(quote) }, market))
+    }
+
+    /// `BTCUSDT` → (`BTC`, `USDT`); псевдонимы котируемых активов тоже узнаются (`ETHXBT`). Из
+    /// подходящих котируемых активов выбирается тот, после которого база — известный актив
+    /// (`XBTUSD` — `XBT` + `USD`, а не `XB` + `TUSD`), затем самый длинный
+    fn split_joined<'s>(&self, joined: &'s str) -> Option<(&'s str, &'s str)> {
+        let known = |asset: &str| self.quotes.iter().any(|q| q == asset) || self.aliases.contains_key(asset);
+        let aliased = self.aliases.iter().filter(|(_, canonical)| self.quotes.contains(canonical)).map(|(alias, _)| alias);
+        self.quotes
+            .iter()
+            .chain(aliased)
+            .filter(|q| joined.len() > q.len() && joined.ends_with(q.as_str()))
+            .map(|q| joined.split_at(joined.len() - q.len()))
+            .max_by_key(|(base, quote)| (known(base), quote.len()))
+    }
+
+    fn canonical(&self, asset: &str) -> String {
+        self.aliases.get(asset).cloned().unwrap_or_else(|| asset.to_string())
+    }
+}
+
+/// Правила по умолчанию для `Pair::parse` и `Instrument::from_exchange_symbol`
+fn default_rules() -> &'static SymbolRules {
+    static RULES: OnceLock<SymbolRules> = OnceLock::new();
+    RULES.get_or_init(SymbolRules::default)
+}
+
+impl Pair {
+    /// Пара из биржевого символа по правилам по умолчанию (`BTCUSDT`, `BTC-USDT-SWAP`, `XBTUSD`)
+    pub fn parse(symbol: &str) -> Result<Pair, ParseSymbolError> {
+        Pair::parse_with(symbol, default_rules())
+    }
+
+    pub fn parse_with(symbol: &str, rules: &SymbolRules) -> Result<Pair, ParseSymbolError> {
+        rules.split(symbol).map(|(pair, _)| pair)
+    }
+}
+
+impl Instrument {
+    /// Инструмент из символа биржи; тип рынка — по суффиксу символа, без суффикса — спот
+    pub fn from_exchange_symbol(exchange: &str, symbol: &str) -> Result<Instrument, ParseSymbolError> {
+        Instrument::from_exchange_symbol_with(exchange, symbol, default_rules())
+    }
+
+    pub fn from_exchange_symbol_with(exchange: &str, symbol: &str, rules: &SymbolRules) -> Result<Instrument, ParseSymbolError> {
+        let (pair, market_type) = rules.split(symbol)?;
+        Ok(Instrument { pair, exchange: exchange.to_ascii_lowercase(), market_type: market_type.unwrap_or(MarketType::Spot) })
+    }
+}
+
+/// `BTC/USDT`
+impl fmt::Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.base_id, self.quote_id)
+    }
+}
+
+impl FromStr for Pair {
+    type Err = ParseSymbolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pair::parse(s)
+    }
+}
+
+/// `BTC/USDT/binance/spot`
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.pair, self.exchange, self.market_type)
+    }
+}
+
+/// Обратно к `Display`: `BTC/USDT/binance/spot`
+impl FromStr for Instrument {
+    type Err = ParseSymbolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split('/').collect::<Vec<_>>()[..] {
+            [base, quote, exchange, market_type] if [base, quote, exchange].iter().all(|p| !p.is_empty()) => Ok(Instrument {
+                pair: Pair { base_id: base.to_string(), quote_id: quote.to_string() },
+                exchange: exchange.to_string(),
+                market_type: MarketType::from(market_type),
+            }),
+            _ => Err(ParseSymbolError(s.to_string())),
+        }
+    }
+}
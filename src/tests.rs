@@ -1,6 +1,7 @@
 use super::*;
 use chrono::{TimeZone, Utc};
 use rand::seq::SliceRandom;
+use std::sync::Arc;
 
 fn sample_instrument() -> Instrument {
     Instrument {
@@ -14,33 +15,53 @@ fn sample_trade(ts: i64, price: f64, amount: f64, side: Side) -> Trade {
     Trade {
         instrument: sample_instrument(),
         id: format!("{}", ts),
-        price,
-        amount,
+        price: num(price),
+        amount: num(amount),
+        side,
+        timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
+    }
+}
+
+// Пара с quote != USDT: объём в USDT считается через UsdtVolumeSource
+fn sample_cross_trade(ts: i64, price: f64, amount: f64, side: Side) -> Trade {
+    Trade {
+        instrument: Instrument {
+            pair: Pair { base_id: "ETH".to_string(), quote_id: "BTC".to_string() },
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+        },
+        id: format!("{}", ts),
+        price: num(price),
+        amount: num(amount),
         side,
         timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
     }
 }
 
+#[test]
+fn it_works() {
+    let result = add(2, 2);
+    assert_eq!(result, 4);
+}
+
 #[test]
 fn test_create_generator() {
-    let instrument = sample_instrument();
     let gen = CandleGenerator::default();
-    assert_eq!(gen.config.basic_ohlcv, true);
+    assert!(gen.config.basic_ohlcv);
 }
 
 #[test]
 fn test_add_and_reset() {
-    let instrument = sample_instrument();
-    let mut gen = CandleGenerator::default();
+    let gen = CandleGenerator::default();
     let trade = sample_trade(1_700_000_000_000, 42000.0, 0.1, Side::Buy);
-    let trades = vec![trade];
+    let trades = [trade];
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     assert_eq!(candles.len(), 1);
 }
 
 #[test]
 fn test_basic_ohlcv_aggregation() {
-    let trades = vec![
+    let trades = [
         sample_trade(1_700_000_000_000, 42000.0, 0.1, Side::Buy),
         sample_trade(1_700_000_000_000 + 10_000, 42100.0, 0.2, Side::Sell),
     ];
@@ -48,14 +69,37 @@ fn test_basic_ohlcv_aggregation() {
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     assert_eq!(candles.len(), 1);
     let c = &candles[0];
-    assert_eq!(c.open, 42000.0);
-    assert_eq!(c.high, 42100.0);
-    assert_eq!(c.low, 42000.0);
-    assert_eq!(c.close, 42100.0);
-    assert_eq!(c.volume, 0.3);
+    assert_eq!(c.open, num(42000.0));
+    assert_eq!(c.high, num(42100.0));
+    assert_eq!(c.low, num(42000.0));
+    assert_eq!(c.close, num(42100.0));
+    // f64: 0.1 + 0.2 != 0.3 (точно — с feature `decimal`, см. test_decimal_exact_volume)
+    assert!((num_to_f64(c.volume) - 0.3).abs() < 1e-12);
     assert_eq!(c.trade_count, 2);
 }
 
+#[cfg(feature = "decimal")]
+#[test]
+fn test_decimal_exact_volume() {
+    let t0 = 1_700_000_000_000;
+    let trades: Vec<_> = (0..1000).map(|i| sample_trade(t0 + i * 1_000, 0.1, 0.1, Side::Buy)).collect();
+    let gen = CandleGenerator::default();
+    let chain = gen.aggregate_chain_for(trades.iter(), &[Timeframe::m1, Timeframe::h1]);
+    assert_eq!(chain[&Timeframe::h1][0].volume, num(100.0));
+    assert_eq!(chain[&Timeframe::h1][0].volume_usdt, Some(num(10.0)));
+    // Decimal сериализуется строкой, без потери точности
+    let json = serde_json::to_value(&chain[&Timeframe::m1][0]).unwrap();
+    assert_eq!(json["v"], "4.0");
+}
+
+#[test]
+fn test_try_num_rejects_non_finite() {
+    assert_eq!(try_num(0.5), Some(num(0.5)));
+    for x in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        assert_eq!(try_num(x), None);
+    }
+}
+
 #[test]
 fn test_aggregation_chain() {
     let t0 = 1_700_000_000_000;
@@ -65,55 +109,84 @@ fn test_aggregation_chain() {
     let gen = CandleGenerator::default();
     let chain = gen.aggregate_chain(trades.iter());
     assert_eq!(chain[&Timeframe::m1].len(), 10);
-    assert_eq!(chain[&Timeframe::m5].len(), 2);
-    assert_eq!(chain[&Timeframe::m15].len(), 0); // 10 минут — меньше 15
+    // 22:13..22:22 → бакеты m5 22:10 (2 свечи), 22:15 (5), 22:20 (3)
+    assert_eq!(chain[&Timeframe::m5].len(), 3);
+    assert_eq!(chain[&Timeframe::m5][1].trade_count, 5);
+    assert_eq!(chain[&Timeframe::m15].len(), 2); // частичные бакеты 22:00 и 22:15
+    assert_eq!(chain[&Timeframe::d1].len(), 1);
+    assert_eq!(chain[&Timeframe::d1][0].volume, num(10.0));
+}
+
+#[test]
+fn test_rollup_by_timestamp_with_gaps() {
+    let t0 = 1_700_000_000_000 - 200_000; // 22:10:00
+    // пропущена минута 22:11: следующие бакеты не должны сдвигаться
+    let trades: Vec<_> = [0, 2, 3, 4, 5, 6]
+        .iter()
+        .map(|&m| sample_trade(t0 + m * 60_000, 100.0 + m as f64, 1.0, Side::Buy))
+        .collect();
+    let config = CandleConfig { mark_incomplete: true, ..Default::default() };
+    let gen = CandleGenerator { config };
+    let m1 = gen.aggregate(trades.iter(), Timeframe::m1);
+    let m5 = gen.rollup(&m1, &Timeframe::m5);
+    assert_eq!(m5.len(), 2);
+    assert_eq!(m5[0].timestamp, m1[0].timestamp);
+    assert_eq!(m5[0].trade_count, 4);
+    assert_eq!(m5[0].close, num(104.0));
+    assert!(m5[0].incomplete);
+    assert_eq!(m5[1].open, num(105.0));
+    assert!(m5[1].incomplete);
+
+    let gen = CandleGenerator::default();
+    assert!(gen.rollup(&m1, &Timeframe::m5).iter().all(|c| !c.incomplete));
 }
 
 #[test]
 fn test_usdt_volume_fixed() {
-    let mut config = CandleConfig::default();
-    config.volume_in_usdt = UsdtVolumeSource::Fixed(2.0); // удваиваем объём
+    // Удваиваем объём
+    let config = CandleConfig { volume_in_usdt: UsdtVolumeSource::Fixed(2.0), ..Default::default() };
     let gen = CandleGenerator { config };
-    let trades = vec![sample_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy)];
+    let trades = [sample_cross_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy)];
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
-    assert_eq!(candles[0].volume_usdt, Some(200.0));
+    assert_eq!(candles[0].volume_usdt, Some(num(200.0)));
 }
 
 #[test]
 fn test_usdt_volume_callback() {
-    let mut config = CandleConfig::default();
-    config.volume_in_usdt = UsdtVolumeSource::Callback(Box::new(|_pair, _ts| Some(10.0)));
+    let config = CandleConfig { volume_in_usdt: UsdtVolumeSource::Callback(Box::new(|_pair, _ts| Some(10.0))), ..Default::default() };
     let gen = CandleGenerator { config };
-    let trades = vec![sample_trade(1_700_000_000_000, 5.0, 2.0, Side::Buy)];
+    let trades = [sample_cross_trade(1_700_000_000_000, 5.0, 2.0, Side::Buy)];
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
-    assert_eq!(candles[0].volume_usdt, Some(100.0));
+    assert_eq!(candles[0].volume_usdt, Some(num(100.0)));
 }
 
 #[test]
 fn test_usdt_volume_none() {
-    let mut config = CandleConfig::default();
-    config.volume_in_usdt = UsdtVolumeSource::None;
+    let config = CandleConfig { volume_in_usdt: UsdtVolumeSource::None, ..Default::default() };
     let gen = CandleGenerator { config };
-    let trades = vec![sample_trade(1_700_000_000_000, 5.0, 2.0, Side::Buy)];
+    let trades = [sample_cross_trade(1_700_000_000_000, 5.0, 2.0, Side::Buy)];
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     assert_eq!(candles[0].volume_usdt, None);
 }
 
 struct BuySellVolume;
 impl CandleMetric for BuySellVolume {
-    fn update(&self, trade: &Trade, candle: &mut Candle) {
+    fn update(&self, trade: &TradeRef, candle: &mut Candle) {
         let buy = *candle.custom.get("buy_volume").unwrap_or(&0.0);
         let sell = *candle.custom.get("sell_volume").unwrap_or(&0.0);
         let (mut buy, mut sell) = (buy, sell);
         match trade.side {
-            Side::Buy => buy += trade.amount,
-            Side::Sell => sell += trade.amount,
+            Side::Buy => buy += num_to_f64(trade.amount),
+            Side::Sell => sell += num_to_f64(trade.amount),
             _ => {}
         }
         candle.custom.insert("buy_volume".to_string(), buy);
         candle.custom.insert("sell_volume".to_string(), sell);
     }
-    fn aggregate(&self, _src: &[Candle], _dst: &mut Candle) {}
+    fn aggregate(&self, src: &[Candle], dst: &mut Candle) {
+        MergeRule::Sum.apply("buy_volume", src, dst);
+        MergeRule::Sum.apply("sell_volume", src, dst);
+    }
 }
 
 #[test]
@@ -121,7 +194,7 @@ fn test_buy_sell_volume_metric() {
     let mut config = CandleConfig::default();
     config.custom_metrics.push(Box::new(BuySellVolume));
     let gen = CandleGenerator { config };
-    let trades = vec![
+    let trades = [
         sample_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy),
         sample_trade(1_700_000_000_000 + 10_000, 101.0, 2.0, Side::Sell),
     ];
@@ -136,15 +209,15 @@ fn test_gaps_and_empty_candles() {
     // Трейды только в 00:00 и 00:05
     let t0 = 1_700_000_000_000;
     let t5 = t0 + 5 * 60_000;
-    let trades = vec![
+    let trades = [
         sample_trade(t0, 100.0, 1.0, Side::Buy),
         sample_trade(t5, 110.0, 2.0, Side::Sell),
     ];
     let gen = CandleGenerator::default();
     let m1 = gen.aggregate(trades.iter(), Timeframe::m1);
     assert_eq!(m1.len(), 2); // Только свечи с трейдами
-    assert_eq!(m1[0].open, 100.0);
-    assert_eq!(m1[1].open, 110.0);
+    assert_eq!(m1[0].open, num(100.0));
+    assert_eq!(m1[1].open, num(110.0));
 }
 
 #[test]
@@ -160,17 +233,19 @@ fn test_bulk_ingestion_sorted() {
 
 struct VWAPMetric;
 impl CandleMetric for VWAPMetric {
-    fn update(&self, trade: &Trade, candle: &mut Candle) {
+    fn update(&self, trade: &TradeRef, candle: &mut Candle) {
         let vwap = candle.custom.get("vwap").cloned().unwrap_or(0.0);
-        let total_volume = candle.volume;
+        let (price, amount, total_volume) = (num_to_f64(trade.price), num_to_f64(trade.amount), num_to_f64(candle.volume));
         let new_vwap = if total_volume > 0.0 {
-            (vwap * (total_volume - trade.amount) + trade.price * trade.amount) / total_volume
+            (vwap * (total_volume - amount) + price * amount) / total_volume
         } else {
-            trade.price
+            price
         };
         candle.custom.insert("vwap".to_string(), new_vwap);
     }
-    fn aggregate(&self, _src: &[Candle], _dst: &mut Candle) {}
+    fn aggregate(&self, src: &[Candle], dst: &mut Candle) {
+        MergeRule::VolumeWeightedMean.apply("vwap", src, dst);
+    }
 }
 
 #[test]
@@ -178,7 +253,7 @@ fn test_custom_vwap_metric() {
     let mut config = CandleConfig::default();
     config.custom_metrics.push(Box::new(VWAPMetric));
     let gen = CandleGenerator { config };
-    let trades = vec![
+    let trades = [
         sample_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy),
         sample_trade(1_700_000_000_000 + 10_000, 110.0, 2.0, Side::Buy),
     ];
@@ -188,21 +263,67 @@ fn test_custom_vwap_metric() {
     assert!((*vwap - 106.6666).abs() < 0.01);
 }
 
+// Без feature `parallel` метрика не обязана быть `Send + Sync`
+#[cfg(not(feature = "parallel"))]
+#[test]
+fn test_non_sync_metric() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Counter(Rc<RefCell<u64>>);
+    impl CandleMetric for Counter {
+        fn update(&self, _trade: &TradeRef, _candle: &mut Candle) {
+            *self.0.borrow_mut() += 1;
+        }
+        fn aggregate(&self, _src: &[Candle], _dst: &mut Candle) {}
+    }
+
+    let seen = Rc::new(RefCell::new(0));
+    let config = CandleConfig { custom_metrics: vec![Box::new(Counter(seen.clone()))], ..Default::default() };
+    let gen = CandleGenerator { config };
+    let trades = [sample_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy), sample_trade(1_700_000_070_000, 101.0, 1.0, Side::Sell)];
+    assert_eq!(gen.aggregate(trades.iter(), Timeframe::m1).len(), 2);
+    assert_eq!(*seen.borrow(), 2);
+}
+
 #[test]
 fn test_out_of_order_trades() {
     let t0 = 1_700_000_000_000;
-    let trades = vec![
+    let trades = [
         sample_trade(t0, 100.0, 1.0, Side::Buy),
         sample_trade(t0 + 60_000, 110.0, 2.0, Side::Sell),
         sample_trade(t0 + 10_000, 120.0, 0.5, Side::Buy), // out-of-order
     ];
-    let gen = CandleGenerator::default();
+    let config = CandleConfig { late_policy: LatePolicy::Merge(chrono::Duration::hours(1)), ..Default::default() };
+    let gen = CandleGenerator { config };
     let mut candles = gen.aggregate(trades.iter(), Timeframe::m1);
     candles.sort_by_key(|c| c.timestamp);
     assert_eq!(candles.len(), 2);
     let c0 = &candles[0];
-    assert_eq!(c0.high, 120.0);
-    assert_eq!(c0.volume, 1.5);
+    assert_eq!(c0.high, num(120.0));
+    assert_eq!(c0.volume, num(1.5));
+}
+
+#[test]
+fn test_merge_by_instrument_replaces_candle() {
+    let t0 = 1_700_000_000_000;
+    let trades = [
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_cross_trade(t0, 0.05, 1.0, Side::Buy),
+        sample_trade(t0 + 60_000, 110.0, 2.0, Side::Sell),
+        sample_trade(t0 + 10_000, 120.0, 1.0, Side::Buy), // корректировка закрытой свечи
+    ];
+    let config = CandleConfig { late_policy: LatePolicy::Merge(chrono::Duration::hours(1)), ..Default::default() };
+    let gen = CandleGenerator { config };
+    let expected = gen.aggregate(trades.iter().filter(|t| t.instrument == sample_instrument()), Timeframe::m1);
+    assert_eq!(expected.len(), 2);
+    let by_instrument = gen.aggregate_by_instrument(trades.iter(), Timeframe::m1);
+    assert_eq!(by_instrument[&sample_instrument()], expected);
+    assert_eq!(by_instrument[&sample_instrument()][0].volume, num(2.0));
+    assert_eq!(by_instrument[&sample_instrument()][0].revision, 1);
+    assert_eq!(by_instrument[&sample_cross_trade(t0, 0.05, 1.0, Side::Buy).instrument].len(), 1);
+    let tried = gen.try_aggregate_by_instrument(trades.iter(), Timeframe::m1).unwrap();
+    assert_eq!(tried, by_instrument);
 }
 
 #[test]
@@ -215,7 +336,7 @@ fn test_bulk_ingestion_unsorted() {
     trades.shuffle(&mut rng);
     let gen = CandleGenerator::default();
     let mut m1_unsorted = gen.aggregate(trades.iter(), Timeframe::m1);
-    let trades_sorted: Vec<_> = trades.iter().cloned().collect();
+    let trades_sorted: Vec<_> = trades.to_vec();
     let mut m1_sorted = gen.aggregate(trades_sorted.iter(), Timeframe::m1);
     m1_unsorted.sort_by_key(|c| c.timestamp);
     m1_sorted.sort_by_key(|c| c.timestamp);
@@ -225,48 +346,1567 @@ fn test_bulk_ingestion_unsorted() {
 #[test]
 fn test_bulk_ingestion_duplicates() {
     let t0 = 1_700_000_000_000;
-    let mut trades = vec![
+    let mut trades = [
         sample_trade(t0, 100.0, 1.0, Side::Buy),
         sample_trade(t0, 100.0, 1.0, Side::Buy), // дубликат
         sample_trade(t0 + 60_000, 110.0, 2.0, Side::Sell),
     ];
     let mut rng = rand::thread_rng();
     trades.shuffle(&mut rng);
-    let gen = CandleGenerator::default();
+    let config = CandleConfig { late_policy: LatePolicy::Merge(chrono::Duration::hours(1)), ..Default::default() };
+    let gen = CandleGenerator { config };
     let mut candles = gen.aggregate(trades.iter(), Timeframe::m1);
     candles.sort_by_key(|c| c.timestamp);
     assert_eq!(candles.len(), 2);
-    assert_eq!(candles[0].volume, 2.0); // оба трейда в одной свече
+    assert_eq!(candles[0].volume, num(2.0)); // оба трейда в одной свече
+}
+
+#[test]
+fn test_dedup_trades() {
+    let t0 = 1_700_000_000_000;
+    let mut trades = vec![
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 60_000, 110.0, 2.0, Side::Sell),
+    ];
+    // Переподключение: повтор истории, включая трейд уже закрытой свечи
+    trades.extend(trades.clone());
+    trades.push(sample_trade(t0 + 61_000, 111.0, 1.0, Side::Buy));
+    for dedup in [Dedup::Window(100), Dedup::PerCandle] {
+        let config = CandleConfig {
+            late_policy: LatePolicy::Merge(chrono::Duration::hours(1)),
+            dedup,
+            ..Default::default()
+        };
+        let gen = CandleGenerator { config };
+        let (candles, stats) = gen.aggregate_with_stats(trades.iter(), Timeframe::m1);
+        assert_eq!(candles.iter().map(|c| c.volume).collect::<Vec<_>>(), vec![num(1.0), num(3.0)], "{:?}", dedup);
+        assert_eq!(stats.duplicates, 2);
+    }
+
+    // Окно помнит только последние N id
+    let config = CandleConfig { dedup: Dedup::Window(1), ..Default::default() };
+    let gen = CandleGenerator { config };
+    let ids = |ts: &[i64]| ts.iter().map(|t| sample_trade(*t, 100.0, 1.0, Side::Buy)).collect::<Vec<_>>();
+    let (candles, stats) = gen.aggregate_with_stats(ids(&[t0, t0 + 1, t0 + 1, t0, t0 + 2]).iter(), Timeframe::m1);
+    assert_eq!((candles[0].trade_count, stats.duplicates), (4, 1));
+
+    // Поток: ключ — id в пределах инструмента
+    let mut builder = gen.instrument_builder(Timeframe::m1);
+    let cross = Trade { id: format!("{}", t0), ..sample_cross_trade(t0, 0.05, 1.0, Side::Buy) };
+    for t in [sample_trade(t0, 100.0, 1.0, Side::Buy), cross.clone(), cross] {
+        builder.push(&t);
+    }
+    assert_eq!(builder.late_stats().duplicates, 1);
+    assert_eq!(builder.flush().len(), 2);
 }
 
 #[test]
 fn test_bulk_ingestion_same_timestamp() {
     let t0 = 1_700_000_000_000;
-    let trades = vec![
+    let trades = [
         sample_trade(t0, 100.0, 1.0, Side::Buy),
         sample_trade(t0, 110.0, 2.0, Side::Sell), // тот же timestamp
     ];
     let gen = CandleGenerator::default();
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     assert_eq!(candles.len(), 1);
-    assert_eq!(candles[0].open, 100.0);
-    assert_eq!(candles[0].close, 110.0);
-    assert_eq!(candles[0].high, 110.0);
-    assert_eq!(candles[0].low, 100.0);
-    assert_eq!(candles[0].volume, 3.0);
+    assert_eq!(candles[0].open, num(100.0));
+    assert_eq!(candles[0].close, num(110.0));
+    assert_eq!(candles[0].high, num(110.0));
+    assert_eq!(candles[0].low, num(100.0));
+    assert_eq!(candles[0].volume, num(3.0));
 }
 
 #[test]
 fn test_bulk_ingestion_boundary_trades() {
     let t0 = 1_700_000_000_000;
     let t1 = t0 + 60_000; // граница следующей свечи
-    let trades = vec![
+    let trades = [
         sample_trade(t0, 100.0, 1.0, Side::Buy),
         sample_trade(t1, 110.0, 2.0, Side::Sell), // ровно на границе
     ];
     let gen = CandleGenerator::default();
     let candles = gen.aggregate(trades.iter(), Timeframe::m1);
     assert_eq!(candles.len(), 2);
-    assert_eq!(candles[0].open, 100.0);
-    assert_eq!(candles[1].open, 110.0);
-} 
\ No newline at end of file
+    assert_eq!(candles[0].open, num(100.0));
+    assert_eq!(candles[1].open, num(110.0));
+} 
+#[test]
+fn test_builder_matches_aggregate() {
+    let t0 = 1_700_000_000_000;
+    let trades: Vec<_> = (0..50)
+        .map(|i| sample_trade(t0 + i * 7_000, 100.0 + (i % 7) as f64, 0.5, if i % 2 == 0 { Side::Buy } else { Side::Sell }))
+        .collect();
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    let gen = CandleGenerator { config };
+    let expected = gen.aggregate(trades.iter(), Timeframe::m1);
+
+    let mut builder = gen.builder(Timeframe::m1);
+    let mut candles: Vec<_> = trades.iter().flat_map(|t| builder.push(t)).collect();
+    candles.extend(builder.flush());
+    assert_eq!(candles, expected);
+    assert!(builder.current().is_none());
+}
+
+#[test]
+fn test_builder_emits_on_boundary() {
+    let t0 = 1_700_000_000_000;
+    let gen = CandleGenerator::default();
+    let mut builder = gen.builder(Timeframe::m1);
+    assert!(builder.push(&sample_trade(t0, 100.0, 1.0, Side::Buy)).is_empty());
+    assert!(builder.push(&sample_trade(t0 + 10_000, 105.0, 1.0, Side::Sell)).is_empty());
+    assert_eq!(builder.current().unwrap().close, num(105.0));
+    let closed = builder.push(&sample_trade(t0 + 60_000, 110.0, 2.0, Side::Buy)).remove(0);
+    assert_eq!(closed.trade_count, 2);
+    assert_eq!(closed.high, num(105.0));
+    assert_eq!(builder.current().unwrap().open, num(110.0));
+    assert_eq!(builder.flush()[0].volume, num(2.0));
+}
+
+#[test]
+fn test_chain_aggregates_custom_metrics() {
+    let t0 = 1_700_000_000_000 - 200_000; // 22:10:00
+    let trades = [
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 60_000, 110.0, 3.0, Side::Sell),
+        sample_trade(t0 + 120_000, 120.0, 1.0, Side::Buy),
+    ];
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    config.custom_metrics.push(Box::new(VWAPMetric));
+    let gen = CandleGenerator { config };
+    let chain = gen.aggregate_chain(trades.iter());
+    for tf in [Timeframe::m5, Timeframe::h1, Timeframe::d1] {
+        let c = &chain[&tf][0];
+        assert_eq!(c.custom["buy_volume"], 2.0);
+        assert_eq!(c.custom["sell_volume"], 3.0);
+        assert!((c.custom["vwap"] - 110.0).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_merge_rules() {
+    let t0 = 1_700_000_000_000;
+    let gen = CandleGenerator::default();
+    let trades = [
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 60_000, 100.0, 3.0, Side::Buy),
+        sample_trade(t0 + 120_000, 100.0, 0.0, Side::Buy),
+    ];
+    let mut src = gen.aggregate(trades.iter(), Timeframe::m1);
+    src[0].custom.insert("x".to_string(), 2.0);
+    src[1].custom.insert("x".to_string(), 6.0);
+    assert_eq!(MergeRule::Sum.merge("x", &src), Some(8.0));
+    assert_eq!(MergeRule::First.merge("x", &src), Some(2.0));
+    assert_eq!(MergeRule::Last.merge("x", &src), Some(6.0));
+    assert_eq!(MergeRule::Max.merge("x", &src), Some(6.0));
+    assert_eq!(MergeRule::Min.merge("x", &src), Some(2.0));
+    assert_eq!(MergeRule::VolumeWeightedMean.merge("x", &src), Some(5.0));
+    assert_eq!(MergeRule::VolumeWeightedMean.merge("x", &src[2..]), None);
+    src[2].custom.insert("x".to_string(), 4.0);
+    assert_eq!(MergeRule::VolumeWeightedMean.merge("x", &src[2..]), Some(4.0));
+}
+
+#[test]
+fn test_aggregate_by_instrument() {
+    let t0 = 1_700_000_000_000;
+    let eth = Instrument {
+        pair: Pair { base_id: "ETH".to_string(), quote_id: "USDT".to_string() },
+        exchange: "okx".to_string(),
+        market_type: MarketType::Spot,
+    };
+    let eth_trade = |ts: i64, price: f64| Trade { instrument: eth.clone(), ..sample_trade(ts, price, 1.0, Side::Sell) };
+    let trades = [
+        sample_trade(t0, 42000.0, 0.5, Side::Buy),
+        eth_trade(t0 + 1_000, 2000.0),
+        sample_trade(t0 + 2_000, 42100.0, 0.5, Side::Buy),
+        eth_trade(t0 + 3_000, 2010.0),
+        sample_trade(t0 + 60_000, 42200.0, 0.5, Side::Buy),
+    ];
+    let gen = CandleGenerator::default();
+    let by_inst = gen.aggregate_by_instrument(trades.iter(), Timeframe::m1);
+    assert_eq!(by_inst.len(), 2);
+    let btc = &by_inst[&sample_instrument()];
+    assert_eq!(btc.len(), 2);
+    assert_eq!(btc[0].high, num(42100.0));
+    assert_eq!(btc[0].volume, num(1.0));
+    let e = &by_inst[&eth];
+    assert_eq!(e.len(), 1);
+    assert_eq!((e[0].open, e[0].close, e[0].trade_count), (num(2000.0), num(2010.0), 2));
+
+    // Тот же инструмент на другом рынке — отдельная серия
+    let futures = Instrument { market_type: MarketType::Futures, ..sample_instrument() };
+    let mut builder = gen.instrument_builder(Timeframe::m1);
+    builder.push(&sample_trade(t0, 1.0, 1.0, Side::Buy));
+    builder.push(&Trade { instrument: futures.clone(), ..sample_trade(t0, 2.0, 1.0, Side::Buy) });
+    assert_eq!(builder.current(&futures).unwrap().open, num(2.0));
+    assert_eq!(builder.current(&sample_instrument()).unwrap().open, num(1.0));
+    assert_eq!(builder.flush().len(), 2);
+}
+
+fn late_trades() -> Vec<Trade> {
+    let t0 = 1_700_000_000_000 - 200_000; // 22:10:00
+    vec![
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 30_000, 101.0, 1.0, Side::Buy),
+        sample_trade(t0 + 65_000, 110.0, 2.0, Side::Sell),
+        sample_trade(t0 + 20_000, 120.0, 0.5, Side::Buy), // опоздал на 45 секунд
+        sample_trade(t0 + 125_000, 130.0, 1.0, Side::Buy),
+        sample_trade(t0 + 5_000, 90.0, 1.0, Side::Sell), // опоздал на 2 минуты
+    ]
+}
+
+fn gen_with_policy(policy: LatePolicy) -> CandleGenerator {
+    let config = CandleConfig { late_policy: policy, ..Default::default() };
+    CandleGenerator { config }
+}
+
+#[test]
+fn test_late_policy_reopen_and_drop() {
+    let (candles, stats) = CandleGenerator::default().aggregate_with_stats(late_trades().iter(), Timeframe::m1);
+    assert_eq!(candles.len(), 5);
+    assert_eq!((stats.late, stats.reopened), (2, 2));
+
+    let (candles, stats) = gen_with_policy(LatePolicy::Drop).aggregate_with_stats(late_trades().iter(), Timeframe::m1);
+    assert_eq!(candles.len(), 3);
+    assert_eq!(candles[0].volume, num(2.0));
+    assert_eq!((stats.late, stats.dropped), (2, 2));
+}
+
+#[test]
+fn test_late_policy_merge() {
+    let gen = gen_with_policy(LatePolicy::Merge(chrono::Duration::minutes(1)));
+    let (candles, stats) = gen.aggregate_with_stats(late_trades().iter(), Timeframe::m1);
+    assert_eq!(candles.len(), 3);
+    let c0 = &candles[0];
+    assert_eq!((c0.open, c0.high, c0.close, c0.volume), (num(100.0), num(120.0), num(101.0), num(2.5)));
+    assert_eq!(c0.revision, 1);
+    assert_eq!((stats.late, stats.merged, stats.dropped), (2, 1, 1)); // 22:10 уже вне горизонта к 22:12
+
+    // Потоково корректировка выдаётся сразу за поздним трейдом
+    let mut builder = gen.builder(Timeframe::m1);
+    let trades = late_trades();
+    builder.push(&trades[0]);
+    builder.push(&trades[1]);
+    assert_eq!(builder.push(&trades[2])[0].revision, 0);
+    let corrected = builder.push(&trades[3]);
+    assert_eq!(corrected.len(), 1);
+    assert_eq!((corrected[0].revision, corrected[0].high), (1, num(120.0)));
+    assert_eq!(builder.current().unwrap().open, num(110.0));
+}
+
+#[test]
+fn test_late_policy_buffer() {
+    let gen = gen_with_policy(LatePolicy::Buffer(chrono::Duration::seconds(90)));
+    let trades = late_trades();
+    let mut builder = gen.builder(Timeframe::m1);
+    assert!(trades[..4].iter().all(|t| builder.push(t).is_empty()));
+    // watermark 22:10:35 → 22:10 всё ещё удерживается; 22:12:05 − 90s = 22:10:35
+    assert!(builder.push(&trades[4]).is_empty());
+    // 22:10:05 попадает в удерживаемую свечу без корректировок
+    assert!(builder.push(&trades[5]).is_empty());
+    let c0 = &builder.flush()[0];
+    assert_eq!((c0.open, c0.low, c0.close, c0.volume, c0.revision), (num(100.0), num(90.0), num(101.0), num(3.5), 0));
+    assert_eq!((builder.late_stats().late, builder.late_stats().buffered), (2, 2));
+
+    let gen = gen_with_policy(LatePolicy::Buffer(chrono::Duration::seconds(30)));
+    let (candles, stats) = gen.aggregate_with_stats(trades.iter(), Timeframe::m1);
+    assert_eq!(candles.len(), 3);
+    assert!(candles.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+    assert_eq!(candles[0].volume, num(2.5));
+    assert_eq!((stats.late, stats.buffered, stats.dropped), (2, 1, 1));
+}
+
+#[test]
+fn test_fill_gaps() {
+    // Трейды только в 22:13 и 22:18
+    let t0 = 1_700_000_000_000;
+    let trades = [
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 10_000, 105.0, 1.0, Side::Buy),
+        sample_trade(t0 + 5 * 60_000, 110.0, 2.0, Side::Sell),
+    ];
+    let config = CandleConfig { fill_gaps: true, ..Default::default() };
+    let gen = CandleGenerator { config };
+    let m1 = gen.aggregate(trades.iter(), Timeframe::m1);
+    assert_eq!(m1.len(), 6);
+    assert!(!m1[0].synthetic && !m1[5].synthetic);
+    for c in &m1[1..5] {
+        assert!(c.synthetic);
+        assert_eq!((c.open, c.high, c.low, c.close), (num(105.0), num(105.0), num(105.0), num(105.0)));
+        assert_eq!((c.volume, c.trade_count), (num(0.0), 0));
+        assert_eq!(c.volume_usdt, Some(num(0.0)));
+    }
+    assert!(m1.windows(2).all(|w| w[1].timestamp - w[0].timestamp == chrono::Duration::minutes(1)));
+
+    // Потоково синтетические свечи выдаются сразу за закрытой, как только пришёл трейд после пропуска
+    let mut builder = gen.builder(Timeframe::m1);
+    builder.push(&trades[0]);
+    builder.push(&trades[1]);
+    let out = builder.push(&trades[2]);
+    assert_eq!(out, m1[..5]);
+    assert_eq!(builder.flush(), m1[5..]);
+}
+
+#[test]
+fn test_fill_gaps_chain() {
+    let t0 = 1_700_000_000_000 - 200_000; // 22:10:00
+    let trades = [
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 40 * 60_000, 120.0, 1.0, Side::Buy), // 22:50
+    ];
+    let config = CandleConfig { fill_gaps: true, ..Default::default() };
+    let gen = CandleGenerator { config };
+    let chain = gen.aggregate_chain(trades.iter());
+    assert_eq!(chain[&Timeframe::m1].len(), 41);
+    let m5 = &chain[&Timeframe::m5];
+    assert_eq!(m5.len(), 9);
+    assert!(!m5[0].synthetic && m5[1].synthetic && !m5[8].synthetic);
+    assert_eq!(m5[4].close, num(100.0));
+    assert_eq!(chain[&Timeframe::m15].len(), 4);
+    assert_eq!(chain[&Timeframe::h1].len(), 1);
+    assert_eq!(chain[&Timeframe::h1][0].volume, num(2.0));
+
+    // rollup сам заполняет пропуски между старшими бакетами
+    let m1 = CandleGenerator::default().aggregate(trades.iter(), Timeframe::m1);
+    assert_eq!(gen.rollup(&m1, &Timeframe::m5).len(), 9);
+}
+
+#[test]
+fn test_fill_gaps_with_late_merge() {
+    let t0 = 1_700_000_000_000 - 200_000; // 22:10:00
+    let config = CandleConfig {
+        fill_gaps: true,
+        late_policy: LatePolicy::Merge(chrono::Duration::minutes(10)),
+        ..Default::default()
+    };
+    let gen = CandleGenerator { config };
+    let mut builder = gen.builder(Timeframe::m1);
+    builder.push(&sample_trade(t0, 100.0, 1.0, Side::Buy));
+    let out = builder.push(&sample_trade(t0 + 180_000, 110.0, 1.0, Side::Buy));
+    assert_eq!(out.len(), 3); // 22:10 и синтетические 22:11, 22:12
+    assert!(out[1].synthetic && out[2].synthetic);
+    // 22:11 уже выдана синтетикой — поздний трейд выдаётся корректировкой
+    let out = builder.push(&sample_trade(t0 + 60_000, 105.0, 1.0, Side::Buy));
+    assert_eq!((out[0].revision, out[0].synthetic, out[0].open), (1, false, num(105.0)));
+
+    let trades: Vec<_> = [0, 180_000, 60_000, 120_000].iter().map(|&d| sample_trade(t0 + d, 100.0, 1.0, Side::Buy)).collect();
+    let m1 = gen.aggregate(trades.iter(), Timeframe::m1);
+    assert_eq!(m1.len(), 4);
+    assert!(m1.iter().all(|c| !c.synthetic));
+}
+
+#[test]
+fn test_timeframe_codes() {
+    for (code, tf) in [
+        ("s1", Timeframe::s1),
+        ("s15", Timeframe::s15),
+        ("m1", Timeframe::m1),
+        ("h4", Timeframe::h4),
+        ("d1", Timeframe::d1),
+        ("w1", Timeframe::w1),
+        ("M1", Timeframe::M1),
+        ("m3", Timeframe::from_duration(chrono::Duration::minutes(3)).unwrap()),
+        ("h2", Timeframe::from_duration(chrono::Duration::hours(2)).unwrap()),
+    ] {
+        assert_eq!(code.parse::<Timeframe>().unwrap(), tf);
+        assert_eq!(tf.to_string(), code);
+    }
+    // Стандартная ширина разбирается в именованный вариант
+    assert_eq!("s60".parse::<Timeframe>().unwrap(), Timeframe::m1);
+    assert_eq!(Timeframe::from_duration(chrono::Duration::hours(4)), Some(Timeframe::h4));
+    assert_eq!(Timeframe::from_duration(chrono::Duration::seconds(90)).unwrap().to_string(), "s90");
+    assert_eq!(Timeframe::from_duration(chrono::Duration::milliseconds(1_500)).unwrap().to_string(), "ms1500");
+    // Код нестандартной ширины разбирается обратно в ту же ширину
+    for ms in [1, 250, 1_500, 90_000, 180_000, 7_200_000, 3 * 86_400_000, 14 * 86_400_000, 86_400_001] {
+        let tf = Timeframe::from_duration(chrono::Duration::milliseconds(ms)).unwrap();
+        assert!(matches!(tf, Timeframe::Custom(_)), "{}", ms);
+        assert_eq!(tf.to_string().parse::<Timeframe>().unwrap(), tf, "{}", tf);
+    }
+    // Нулевая, отрицательная и дробная ширина непредставимы; 7 дней — ISO-неделя
+    for bad in [chrono::Duration::zero(), chrono::Duration::seconds(-60), chrono::Duration::microseconds(1_500)] {
+        assert_eq!(Timeframe::from_duration(bad), None);
+    }
+    assert_eq!(Timeframe::from_duration(chrono::Duration::days(7)), Some(Timeframe::w1));
+    for bad in ["", "m", "x5", "m0", "1m", "m-1", "M2", "m1.5", "ms", "ms0", "ms-5"] {
+        assert!(bad.parse::<Timeframe>().is_err(), "{}", bad);
+    }
+    assert_eq!(serde_json::to_string(&Timeframe::m15).unwrap(), "\"m15\"");
+    assert_eq!(serde_json::from_str::<Timeframe>("\"m3\"").unwrap(), Timeframe::from_duration(chrono::Duration::minutes(3)).unwrap());
+}
+
+#[test]
+fn test_extended_timeframe_buckets() {
+    let ts = |s: &str| chrono::DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+    let t = ts("2024-02-29T13:47:38.250Z"); // четверг
+    assert_eq!(truncate_to_tf(t, &Timeframe::s1), ts("2024-02-29T13:47:38Z"));
+    assert_eq!(truncate_to_tf(t, &Timeframe::s15), ts("2024-02-29T13:47:30Z"));
+    assert_eq!(truncate_to_tf(t, &Timeframe::w1), ts("2024-02-26T00:00:00Z"));
+    assert_eq!(truncate_to_tf(t, &Timeframe::M1), ts("2024-02-01T00:00:00Z"));
+    assert_eq!(truncate_to_tf(t, &Timeframe::from_duration(chrono::Duration::minutes(7)).unwrap()), ts("2024-02-29T13:46:00Z")); // от epoch
+    assert_eq!(next_bucket(ts("2024-02-01T00:00:00Z"), &Timeframe::M1), ts("2024-03-01T00:00:00Z"));
+
+    let trades = [
+        Trade { timestamp: ts("2024-01-31T23:59:59Z"), ..sample_trade(0, 100.0, 1.0, Side::Buy) },
+        Trade { timestamp: ts("2024-02-01T00:00:00Z"), ..sample_trade(0, 110.0, 1.0, Side::Buy) },
+        Trade { timestamp: ts("2024-02-29T23:00:00Z"), ..sample_trade(0, 120.0, 1.0, Side::Buy) },
+    ];
+    let config = CandleConfig { mark_incomplete: true, ..Default::default() };
+    let gen = CandleGenerator { config };
+    let chain = gen.aggregate_chain_for(trades.iter(), &[Timeframe::d1, Timeframe::M1]);
+    let months = &chain[&Timeframe::M1];
+    assert_eq!(months.len(), 2);
+    assert_eq!(months[1].timestamp, ts("2024-02-01T00:00:00Z"));
+    assert_eq!((months[1].open, months[1].close, months[1].trade_count), (num(110.0), num(120.0), 2));
+    assert!(months[1].incomplete); // 2 дня из 29
+
+    let weeks = gen.aggregate(trades.iter(), Timeframe::w1);
+    assert_eq!(weeks.len(), 2);
+    assert_eq!(weeks[0].timestamp, ts("2024-01-29T00:00:00Z"));
+}
+
+#[test]
+fn test_tick_bars() {
+    let t0 = 1_700_000_000_000;
+    let trades: Vec<_> = (0..7).map(|i| sample_trade(t0 + i * 1_000, 100.0 + i as f64, 1.0, Side::Buy)).collect();
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    let gen = CandleGenerator { config };
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::Tick(3), false);
+    assert_eq!(bars.len(), 3);
+    assert_eq!((bars[0].open, bars[0].close, bars[0].trade_count), (num(100.0), num(102.0), 3));
+    assert_eq!(bars[1].timestamp, trades[3].timestamp);
+    assert_eq!(bars[1].interval, Timeframe::Bar(BarKind::Tick(3)));
+    assert_eq!(bars[1].custom["buy_volume"], 3.0);
+    assert!(!bars[1].incomplete && bars[2].incomplete);
+    assert_eq!(bars[2].trade_count, 1);
+}
+
+#[test]
+fn test_volume_bars_split() {
+    let t0 = 1_700_000_000_000;
+    let trades = [
+        sample_trade(t0, 100.0, 1.5, Side::Buy),
+        sample_trade(t0 + 1_000, 101.0, 4.0, Side::Sell), // переполняет порог 2.0
+        sample_trade(t0 + 2_000, 102.0, 0.5, Side::Buy),
+    ];
+    let gen = CandleGenerator::default();
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::Volume(2.0), true);
+    assert_eq!(bars.iter().map(|b| b.volume).collect::<Vec<_>>(), vec![num(2.0), num(2.0), num(2.0)]);
+    assert_eq!((bars[0].close, bars[0].trade_count), (num(101.0), 2));
+    assert_eq!((bars[1].open, bars[1].close, bars[1].trade_count), (num(101.0), num(101.0), 1));
+    assert_eq!((bars[2].open, bars[2].close), (num(101.0), num(102.0)));
+    assert!(!bars[2].incomplete);
+    assert_eq!(bars.iter().map(|b| b.volume_usdt.unwrap()).sum::<Num>(), num(150.0 + 404.0 + 51.0));
+
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::Volume(2.0), false);
+    assert_eq!(bars.iter().map(|b| b.volume).collect::<Vec<_>>(), vec![num(5.5), num(0.5)]);
+    assert!(bars[1].incomplete);
+}
+
+#[test]
+fn test_invalid_bar_thresholds() {
+    let trades = [sample_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy)];
+    let gen = CandleGenerator::default();
+    for kind in [BarKind::Tick(0), BarKind::Volume(0.0), BarKind::Dollar(0.0), BarKind::Volume(-1.0), BarKind::Volume(f64::INFINITY), BarKind::Dollar(f64::NAN)] {
+        assert!(gen.bar_builder(kind, true).is_err(), "{:?}", kind);
+        assert!(matches!(gen.try_aggregate_bars(trades.iter(), kind, true), Err(CandleError::InvalidBarKind(_))));
+    }
+    assert!(gen.bar_builder(BarKind::Volume(0.5), true).is_ok());
+}
+
+#[test]
+#[should_panic(expected = "invalid bar threshold")]
+fn test_aggregate_bars_panics_on_zero_threshold() {
+    let trades = [sample_trade(1_700_000_000_000, 100.0, 1.0, Side::Buy)];
+    CandleGenerator::default().aggregate_bars(trades.iter(), BarKind::Volume(0.0), true);
+}
+
+#[test]
+fn test_dollar_bars() {
+    let t0 = 1_700_000_000_000;
+    let trades: Vec<_> = (0..4).map(|i| sample_cross_trade(t0 + i * 1_000, 10.0, 1.0, Side::Buy)).collect();
+    // 50 USDT на трейд
+    let config = CandleConfig { volume_in_usdt: UsdtVolumeSource::Fixed(5.0), ..Default::default() };
+    let gen = CandleGenerator { config };
+    let mut builder = gen.bar_builder(BarKind::Dollar(100.0), true).unwrap();
+    assert!(builder.push(&trades[0]).is_empty());
+    assert_eq!(builder.progress(), 0.5);
+    let closed = builder.push(&trades[1]);
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].volume_usdt, Some(num(100.0)));
+    assert!(builder.current().is_none());
+
+    assert_eq!("usdt100".parse::<Timeframe>().unwrap(), Timeframe::Bar(BarKind::Dollar(100.0)));
+    assert_eq!(Timeframe::Bar(BarKind::Volume(2.5)).to_string(), "vol2.5");
+    assert_eq!(Timeframe::Bar(BarKind::Tick(500)).to_string(), "tick500");
+    assert!("tick0".parse::<Timeframe>().is_err());
+}
+
+#[test]
+fn test_range_bars() {
+    let t0 = 1_700_000_000_000;
+    let prices = [100.0, 103.0, 98.0, 99.0, 101.0, 106.0, 104.0];
+    let trades: Vec<_> = prices.iter().enumerate().map(|(i, &p)| sample_trade(t0 + i as i64 * 1_000, p, 1.0, Side::Buy)).collect();
+    let gen = CandleGenerator::default();
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::Range(5.0), false);
+    assert_eq!(bars.len(), 3);
+    assert_eq!((bars[0].high, bars[0].low, bars[0].trade_count), (num(103.0), num(98.0), 3));
+    assert_eq!((bars[1].open, bars[1].close, bars[1].trade_count), (num(99.0), num(106.0), 3));
+    assert!(bars[2].incomplete);
+}
+
+#[test]
+fn test_renko_bricks() {
+    let t0 = 1_700_000_000_000;
+    // 100 → 125 (2 кирпича вверх), 115 (мало для разворота), 95 (разворот на 2 кирпича)
+    let prices = [100.0, 104.0, 125.0, 115.0, 95.0];
+    let trades: Vec<_> = prices.iter().enumerate().map(|(i, &p)| sample_trade(t0 + i as i64 * 1_000, p, 1.0, Side::Buy)).collect();
+    let gen = CandleGenerator::default();
+    let kind = BarKind::Renko { brick: 10.0, reversal: 2 };
+    let bricks = gen.aggregate_bars(trades.iter(), kind, false);
+    let ohlc: Vec<_> = bricks.iter().filter(|b| !b.incomplete).map(|b| (b.open, b.close)).collect();
+    assert_eq!(ohlc, vec![(num(100.0), num(110.0)), (num(110.0), num(120.0)), (num(110.0), num(100.0))]);
+    assert_eq!(bricks[0].trade_count, 3);
+    assert_eq!((bricks[1].trade_count, bricks[1].volume), (0, num(0.0)));
+    assert_eq!(bricks[2].trade_count, 2);
+    assert_eq!((bricks[0].high, bricks[0].low), (num(110.0), num(100.0)));
+    assert_eq!(bricks[1].interval, Timeframe::Bar(kind));
+
+    // Разворот на 3 кирпича: от открытия последнего кирпича, без пропусков
+    let trades: Vec<_> = [100.0, 103.5, 99.0].iter().enumerate().map(|(i, &p)| sample_trade(t0 + i as i64 * 1_000, p, 1.0, Side::Buy)).collect();
+    let bricks = gen.aggregate_bars(trades.iter(), BarKind::Renko { brick: 1.0, reversal: 3 }, false);
+    let ohlc: Vec<_> = bricks.iter().filter(|b| !b.incomplete).map(|b| (b.open, b.close)).collect();
+    let expected = [(100.0, 101.0), (101.0, 102.0), (102.0, 103.0), (102.0, 101.0), (101.0, 100.0), (100.0, 99.0)];
+    assert_eq!(ohlc, expected.map(|(o, c)| (num(o), num(c))));
+
+    assert_eq!(kind.to_string(), "renko10r2");
+    assert_eq!("renko10r2".parse::<BarKind>().unwrap(), kind);
+    assert!("renko10".parse::<BarKind>().is_err());
+    assert!("range0".parse::<BarKind>().is_err());
+}
+
+#[test]
+fn test_renko_rejects_non_positive_brick() {
+    // Кирпич 0: up == anchor == down, и любой трейд выдавал бы кирпичи бесконечно
+    let t0 = 1_700_000_000_000;
+    let trades = [sample_trade(t0, 100.0, 1.0, Side::Buy), sample_trade(t0 + 1_000, 101.0, 1.0, Side::Buy)];
+    let gen = CandleGenerator::default();
+    for (brick, reversal) in [(0.0, 2), (-10.0, 2), (f64::NAN, 2), (10.0, 0)] {
+        let kind = BarKind::Renko { brick, reversal };
+        assert!(gen.bar_builder(kind, false).is_err(), "{:?}", kind);
+        assert!(gen.try_aggregate_bars(trades.iter(), kind, false).is_err());
+    }
+}
+
+#[test]
+fn test_heikin_ashi() {
+    let t0 = 1_700_000_000_000;
+    let trades = [
+        sample_trade(t0, 10.0, 1.0, Side::Buy),
+        sample_trade(t0 + 1_000, 14.0, 1.0, Side::Buy),
+        sample_trade(t0 + 2_000, 8.0, 1.0, Side::Buy),
+        sample_trade(t0 + 3_000, 12.0, 1.0, Side::Buy), // m1: O10 H14 L8 C12
+        sample_trade(t0 + 60_000, 12.0, 2.0, Side::Buy),
+        sample_trade(t0 + 61_000, 16.0, 1.0, Side::Buy), // m1: O12 H16 L12 C16
+    ];
+    let gen = CandleGenerator::default();
+    let m1 = gen.aggregate(trades.iter(), Timeframe::m1);
+    let ha = heikin_ashi(&m1);
+    assert_eq!((ha[0].open, ha[0].close, ha[0].high, ha[0].low), (num(11.0), num(11.0), num(14.0), num(8.0)));
+    assert_eq!((ha[1].open, ha[1].close, ha[1].high, ha[1].low), (num(11.0), num(14.0), num(16.0), num(11.0)));
+    assert_eq!(ha[1].volume, num(3.0));
+    assert_eq!(ha[1].timestamp, m1[1].timestamp);
+}
+
+fn side_trades(sides: &[Side], amounts: &[f64]) -> Vec<Trade> {
+    let t0 = 1_700_000_000_000;
+    sides
+        .iter()
+        .zip(amounts.iter().cycle())
+        .enumerate()
+        .map(|(i, (side, &a))| sample_trade(t0 + i as i64 * 1_000, 100.0, a, *side))
+        .collect()
+}
+
+#[test]
+fn test_tick_imbalance_bars() {
+    use Side::*;
+    // Разогрев: 4 трейда, E[b] = 0.5 → порог 4·0.5 = 2; span 1 — ожидания по последнему бару
+    let trades = side_trades(&[Buy, Buy, Buy, Sell, Buy, Buy, Sell, Sell, Buy, Sell, Buy, Buy], &[1.0]);
+    let gen = CandleGenerator::default();
+    let kind = BarKind::TickImbalance { initial: 4, span: 1 };
+    let bars = gen.aggregate_bars(trades.iter(), kind, false);
+    assert_eq!(bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(), vec![4, 2, 2, 4]);
+    assert!(!bars[3].incomplete);
+
+    let mut builder = gen.bar_builder(kind, false).unwrap();
+    assert_eq!(builder.expected_threshold(), None);
+    for t in &trades[..4] {
+        builder.push(t);
+    }
+    assert_eq!(builder.expected_threshold(), Some(2.0));
+    assert_eq!(kind.to_string(), "timb4e1");
+    assert_eq!("vrun100e20".parse::<BarKind>().unwrap(), BarKind::VolumeRun { initial: 100, span: 20 });
+}
+
+#[test]
+fn test_volume_imbalance_and_run_bars() {
+    use Side::*;
+    let gen = CandleGenerator::default();
+    // Разогрев: Σb·v = 3 − 1 = 2 за 2 трейда → порог 2·1 = 2
+    let trades = side_trades(&[Buy, Sell, Sell, Buy, Buy, Sell, Sell], &[3.0, 1.0]);
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::VolumeImbalance { initial: 2, span: 1 }, false);
+    // −3 ≥ 2 → порог 1·3; 1 + 3 ≥ 3 → порог 2·2; −1 − 3 ≥ 4
+    assert_eq!(bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(), vec![2, 1, 2, 2]);
+
+    // Run bars: разогрев B B B S → P[buy] = 0.75, порог 4·0.75 = 3 покупки
+    let trades = side_trades(&[Buy, Buy, Buy, Sell, Buy, Sell, Buy, Buy, Sell], &[1.0]);
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::TickRun { initial: 4, span: 1 }, false);
+    assert_eq!(bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(), vec![4, 4, 1]);
+    assert!(bars[2].incomplete);
+}
+
+#[test]
+fn test_imbalance_bars_balanced_warmup() {
+    use Side::*;
+    // Разогрев B S B S: E[b] = 0, но порог не нулевой — √4·E[v] = 2, а не закрытие на каждом трейде
+    let trades = side_trades(&[Buy, Sell, Buy, Sell, Buy, Sell, Buy, Buy], &[1.0]);
+    let gen = CandleGenerator::default();
+    let kind = BarKind::TickImbalance { initial: 4, span: 1 };
+    let mut builder = gen.bar_builder(kind, false).unwrap();
+    for t in &trades[..4] {
+        builder.push(t);
+    }
+    assert_eq!(builder.expected_threshold(), Some(2.0));
+    let bars = gen.aggregate_bars(trades.iter(), kind, false);
+    assert_eq!(bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(), vec![4, 4]);
+}
+
+#[test]
+fn test_imbalance_bars_tick_rule() {
+    // Side::Unknown: направление по изменению цены, без изменения — как у предыдущего
+    let t0 = 1_700_000_000_000;
+    let prices = [100.0, 101.0, 101.0, 102.0, 101.0, 100.0, 100.0, 99.0];
+    let trades: Vec<_> = prices.iter().enumerate().map(|(i, &p)| sample_trade(t0 + i as i64 * 1_000, p, 1.0, Side::Unknown)).collect();
+    let gen = CandleGenerator::default();
+    let bars = gen.aggregate_bars(trades.iter(), BarKind::TickImbalance { initial: 4, span: 1 }, false);
+    // Разогрев: 0, +1, +1, +1 → E[b] = 0.75, порог 3; далее −1, −1, −1 → |θ| = 3
+    assert_eq!(bars.iter().map(|b| b.trade_count).collect::<Vec<_>>(), vec![4, 3, 1]);
+}
+
+fn utc(s: &str) -> chrono::DateTime<Utc> {
+    chrono::DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+#[test]
+fn test_session_alignment_cme() {
+    // CME: торговый день начинается в 17:00 накануне по Чикаго; 10 марта 2024 — переход на летнее время
+    let cme = Alignment::new(chrono_tz::America::Chicago, chrono::Duration::hours(-7));
+    assert_eq!(cme.truncate(utc("2024-03-08T22:59:00Z"), &Timeframe::d1), utc("2024-03-07T23:00:00Z"));
+    assert_eq!(cme.truncate(utc("2024-03-08T23:00:00Z"), &Timeframe::d1), utc("2024-03-08T23:00:00Z"));
+    assert_eq!(cme.truncate(utc("2024-03-11T22:30:00Z"), &Timeframe::d1), utc("2024-03-11T22:00:00Z"));
+    // Торговый день с переходом длится 23 часа
+    assert_eq!(cme.next(utc("2024-03-09T23:00:00Z"), &Timeframe::d1), utc("2024-03-10T22:00:00Z"));
+    // h4 от 17:00: 17, 21, 01, 05, ...
+    assert_eq!(cme.truncate(utc("2024-03-08T03:30:00Z"), &Timeframe::h4), utc("2024-03-08T03:00:00Z"));
+    assert_eq!(cme.truncate(utc("2024-03-08T06:59:00Z"), &Timeframe::h4), utc("2024-03-08T03:00:00Z"));
+    // Неделя начинается с торгового дня понедельника — в воскресенье 17:00
+    assert_eq!(cme.truncate(utc("2024-03-13T15:00:00Z"), &Timeframe::w1), utc("2024-03-10T22:00:00Z"));
+    // Внутридневные таймфреймы — по UTC
+    assert_eq!(cme.truncate(utc("2024-03-08T23:07:10Z"), &Timeframe::m5), utc("2024-03-08T23:05:00Z"));
+
+    let trades = [
+        Trade { timestamp: utc("2024-03-08T22:00:00Z"), ..sample_trade(0, 100.0, 1.0, Side::Buy) },
+        Trade { timestamp: utc("2024-03-08T23:30:00Z"), ..sample_trade(0, 101.0, 1.0, Side::Buy) },
+        Trade { timestamp: utc("2024-03-11T21:00:00Z"), ..sample_trade(0, 102.0, 1.0, Side::Buy) },
+    ];
+    let config = CandleConfig {
+        alignment: cme,
+        fill_gaps: true,
+        ..Default::default()
+    };
+    let gen = CandleGenerator { config };
+    let d1 = gen.aggregate(trades.iter(), Timeframe::d1);
+    let starts: Vec<_> = d1.iter().map(|c| c.timestamp).collect();
+    assert_eq!(starts, vec![
+        utc("2024-03-07T23:00:00Z"),
+        utc("2024-03-08T23:00:00Z"),
+        utc("2024-03-09T23:00:00Z"),
+        utc("2024-03-10T22:00:00Z"),
+    ]);
+    assert!(d1[2].synthetic);
+    let chain = gen.aggregate_chain(trades.iter());
+    assert_eq!(chain[&Timeframe::d1].iter().map(|c| c.timestamp).collect::<Vec<_>>(), starts);
+}
+
+#[test]
+fn test_session_alignment_moex_and_dst_gap() {
+    let moex = Alignment::new(chrono_tz::Europe::Moscow, chrono::Duration::hours(10));
+    assert_eq!(moex.truncate(utc("2024-06-05T06:59:59Z"), &Timeframe::d1), utc("2024-06-04T07:00:00Z"));
+    assert_eq!(moex.truncate(utc("2024-06-05T07:00:00Z"), &Timeframe::d1), utc("2024-06-05T07:00:00Z"));
+    assert_eq!(moex.truncate(utc("2024-06-05T07:00:00Z"), &Timeframe::M1), utc("2024-06-01T07:00:00Z"));
+    assert_eq!(moex.next(utc("2024-06-01T07:00:00Z"), &Timeframe::M1), utc("2024-07-01T07:00:00Z"));
+
+    // 02:30 31 марта 2024 в Берлине не существует — бакет начинается в конце разрыва (03:00 CEST)
+    let berlin = Alignment::new(chrono_tz::Europe::Berlin, chrono::Duration::minutes(150));
+    assert_eq!(berlin.truncate(utc("2024-03-31T05:00:00Z"), &Timeframe::d1), utc("2024-03-31T01:00:00Z"));
+    assert_eq!(berlin.next(utc("2024-03-31T01:00:00Z"), &Timeframe::d1), utc("2024-04-01T00:30:00Z"));
+    // 27 октября 02:30 встречается дважды — берётся более раннее (CEST)
+    assert_eq!(berlin.truncate(utc("2024-10-27T05:00:00Z"), &Timeframe::d1), utc("2024-10-27T00:30:00Z"));
+    // Начало сессии не на границе 15 минут: 02:07 в разрыве — тоже конец разрыва, а не 03:07
+    let odd = Alignment::new(chrono_tz::America::New_York, chrono::Duration::minutes(127));
+    assert_eq!(odd.truncate(utc("2024-03-10T12:00:00Z"), &Timeframe::d1), utc("2024-03-10T07:00:00Z"));
+    assert_eq!(odd.next(utc("2024-03-09T07:07:00Z"), &Timeframe::d1), utc("2024-03-10T07:00:00Z"));
+}
+
+const MOEX_CALENDAR: &str = "\
+# Мосбиржа: клиринг 14:00–14:05
+timezone Europe/Moscow
+session mon-fri 10:00-14:00
+session mon-fri 14:05-18:50  # вечерняя сессия не учитывается
+holiday 2024-06-12
+";
+
+#[test]
+fn test_weekly_calendar() {
+    let path = std::env::temp_dir().join("candle_generator_moex_calendar.txt");
+    std::fs::write(&path, MOEX_CALENDAR).unwrap();
+    let moex = WeeklyCalendar::from_file(&path).unwrap();
+    assert_eq!(moex, WeeklyCalendar::parse(MOEX_CALENDAR).unwrap());
+    assert!(moex.is_open(utc("2024-06-05T07:00:00Z")));
+    assert!(!moex.is_open(utc("2024-06-05T11:02:00Z")));
+    assert!(moex.is_open(utc("2024-06-05T11:05:00Z")));
+    assert!(!moex.is_open(utc("2024-06-05T15:50:00Z")));
+    assert!(!moex.is_open(utc("2024-06-12T08:00:00Z")));
+    assert_eq!(moex.next_open(utc("2024-06-05T11:02:00Z")), Some(utc("2024-06-05T11:05:00Z")));
+    // Выходные и праздник
+    assert_eq!(moex.next_open(utc("2024-06-07T16:00:00Z")), Some(utc("2024-06-10T07:00:00Z")));
+    assert_eq!(moex.next_open(utc("2024-06-11T16:00:00Z")), Some(utc("2024-06-13T07:00:00Z")));
+    assert!(!moex.is_open_between(utc("2024-06-05T11:00:00Z"), utc("2024-06-05T11:05:00Z")));
+    assert!(moex.is_open_between(utc("2024-06-05T11:00:00Z"), utc("2024-06-05T11:06:00Z")));
+
+    // Сессия через полночь: пятничная заканчивается в субботу утром
+    let night = WeeklyCalendar::parse("session mon-fri 22:00-06:00").unwrap();
+    assert!(night.is_open(utc("2024-06-08T03:00:00Z")));
+    assert!(!night.is_open(utc("2024-06-08T07:00:00Z")));
+    assert!(!night.is_open(utc("2024-06-09T23:00:00Z")));
+
+    assert!(AlwaysOpen.is_open(utc("2024-06-08T07:00:00Z")));
+}
+
+#[test]
+fn test_weekly_calendar_parse_errors() {
+    let err = WeeklyCalendar::parse("# tz\ntimezone Mars/Base\n").unwrap_err();
+    assert_eq!(err.line, 2);
+    assert_eq!(WeeklyCalendar::parse("session mon-xyz 10:00-11:00").unwrap_err().line, 1);
+    assert_eq!(WeeklyCalendar::parse("\nsession mon 10:00-25:00").unwrap_err().line, 2);
+    assert_eq!(WeeklyCalendar::parse("holiday 2024-13-01").unwrap_err().line, 1);
+    let err = WeeklyCalendar::parse("timezone UTC\n\nlunch 13:00").unwrap_err();
+    assert_eq!(err.to_string(), "calendar line 3: unknown directive \"lunch 13:00\"");
+    assert_eq!(WeeklyCalendar::from_file("/nonexistent/calendar.txt").unwrap_err().line, 0);
+}
+
+#[test]
+fn test_calendar_skips_closed_time() {
+    let trades = [
+        Trade { timestamp: utc("2024-06-05T10:58:30Z"), ..sample_trade(0, 100.0, 1.0, Side::Buy) },
+        // Клиринг — трейд отбрасывается
+        Trade { timestamp: utc("2024-06-05T11:02:00Z"), ..sample_trade(0, 150.0, 1.0, Side::Buy) },
+        Trade { timestamp: utc("2024-06-05T11:06:10Z"), ..sample_trade(0, 101.0, 2.0, Side::Sell) },
+    ];
+    let config = CandleConfig {
+        calendar: Box::new(WeeklyCalendar::parse(MOEX_CALENDAR).unwrap()),
+        fill_gaps: true,
+        ..Default::default()
+    };
+    let gen = CandleGenerator { config };
+    let (m1, stats) = gen.aggregate_with_stats(trades.iter(), Timeframe::m1);
+    let starts: Vec<_> = m1.iter().map(|c| c.timestamp).collect();
+    assert_eq!(starts, vec![
+        utc("2024-06-05T10:58:00Z"),
+        utc("2024-06-05T10:59:00Z"),
+        utc("2024-06-05T11:05:00Z"),
+        utc("2024-06-05T11:06:00Z"),
+    ]);
+    assert!(m1[1].synthetic && m1[2].synthetic);
+    assert_eq!(m1[2].close, num(100.0));
+    assert_eq!(m1.iter().map(|c| c.high).reduce(Num::max), Some(num(101.0)));
+    assert_eq!(stats.outside_session, 1);
+
+    let mut builder = gen.builder(Timeframe::m1);
+    let mut streamed: Vec<Candle> = trades.iter().flat_map(|t| builder.push(t)).collect();
+    streamed.extend(builder.flush());
+    assert_eq!(streamed, m1);
+
+    // Старший таймфрейм: бакет 11:00–11:05 целиком в клиринге и не заполняется
+    let m5 = gen.rollup(&m1, &Timeframe::m5);
+    assert_eq!(m5.iter().map(|c| c.timestamp).collect::<Vec<_>>(), vec![utc("2024-06-05T10:55:00Z"), utc("2024-06-05T11:05:00Z")]);
+}
+
+#[test]
+fn test_try_aggregate_validation() {
+    let t0 = 1_700_000_000_000;
+    let good = sample_trade(t0, 100.0, 1.0, Side::Buy);
+    let gen = CandleGenerator { config: CandleConfig { validation: Validation::Full, ..Default::default() } };
+
+    let zero_price = sample_trade(t0 + 1_000, 0.0, 1.0, Side::Buy);
+    let err = gen.try_aggregate([good.clone(), zero_price].iter(), Timeframe::m1).unwrap_err();
+    assert!(matches!(err, CandleError::NonPositivePrice { .. }));
+    let negative = sample_trade(t0 + 2_000, 100.0, -1.0, Side::Sell);
+    let err = gen.try_aggregate([good.clone(), negative.clone()].iter(), Timeframe::m1).unwrap_err();
+    assert_eq!(err, CandleError::NegativeAmount { id: negative.id.clone(), amount: num(-1.0) });
+    #[cfg(not(feature = "decimal"))]
+    {
+        let nan = sample_trade(t0 + 3_000, f64::NAN, 1.0, Side::Buy);
+        let err = gen.try_aggregate([good.clone(), nan].iter(), Timeframe::m1).unwrap_err();
+        assert_eq!(err.to_string(), format!("trade {}: price is not finite", t0 + 3_000));
+    }
+
+    let other = sample_cross_trade(t0 + 4_000, 0.05, 1.0, Side::Buy);
+    let mixed = [good.clone(), other.clone()];
+    assert!(matches!(gen.try_aggregate(mixed.iter(), Timeframe::m1), Err(CandleError::InstrumentMismatch { .. })));
+    assert_eq!(gen.try_aggregate_by_instrument(mixed.iter(), Timeframe::m1).unwrap().len(), 2);
+
+    // Без try_: некорректные трейды отбрасываются и считаются
+    let trades = [good.clone(), negative.clone(), other.clone(), sample_trade(t0 + 5_000, 101.0, 2.0, Side::Buy)];
+    let (candles, stats) = gen.aggregate_with_stats(trades.iter(), Timeframe::m1);
+    assert_eq!((candles.len(), candles[0].volume, candles[0].close), (1, num(3.0), num(101.0)));
+    assert_eq!(stats.invalid, 2);
+    assert_eq!(gen.aggregate_bars(trades.iter(), BarKind::Tick(10), false)[0].trade_count, 2);
+    let mut bars = gen.bar_builder(BarKind::Tick(10), false).unwrap();
+    for t in &trades {
+        bars.push(t);
+    }
+    assert_eq!(bars.invalid(), 2);
+
+    // Уровни: Values пропускает чужой инструмент, Off — всё
+    let config = CandleConfig { validation: Validation::Values, ..Default::default() };
+    let gen = CandleGenerator { config };
+    assert!(gen.try_aggregate(mixed.iter(), Timeframe::m1).is_ok());
+    assert!(gen.try_aggregate([good.clone(), negative.clone()].iter(), Timeframe::m1).is_err());
+    // Off — по умолчанию: поведение `aggregate` без проверок не меняется
+    let gen = CandleGenerator::default();
+    assert_eq!(gen.config.validation, Validation::Off);
+    let candles = gen.try_aggregate([good, negative].iter(), Timeframe::m1).unwrap();
+    assert_eq!(candles[0].volume, num(0.0));
+    let (candles, stats) = gen.aggregate_with_stats(trades.iter(), Timeframe::m1);
+    assert_eq!((candles[0].trade_count, stats.invalid), (4, 0));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_par_aggregate_matches_sequential() {
+    let t0 = 1_700_000_000_000;
+    // Трейды с пропусками в несколько минут; сторона и цена меняются
+    let trades: Vec<_> = (0..20_000i64)
+        .map(|i| {
+            let ts = t0 + i * 700 + (i / 3000) * 600_000;
+            let side = if i % 3 == 0 { Side::Sell } else { Side::Buy };
+            sample_trade(ts, 100.0 + (i % 17) as f64, 0.5 + (i % 5) as f64, side)
+        })
+        .collect();
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    config.custom_metrics.push(Box::new(VWAPMetric));
+    config.fill_gaps = true;
+    config.dedup = Dedup::PerCandle;
+    config.validation = Validation::Full;
+    let gen = CandleGenerator { config };
+    for tf in [Timeframe::s5, Timeframe::m1, Timeframe::m15, Timeframe::d1] {
+        let (seq, seq_stats) = gen.aggregate_with_stats(trades.iter(), tf.clone());
+        let (par, par_stats) = gen.par_aggregate_with_stats(&trades, tf.clone());
+        assert_eq!(par, seq, "{}", tf);
+        assert_eq!(par_stats, seq_stats);
+    }
+
+    // Некорректные трейды и чужой инструмент считаются так же
+    let mut dirty = trades.clone();
+    dirty[5_000].amount = num(-1.0);
+    dirty[12_000] = Trade { timestamp: dirty[12_000].timestamp, ..sample_cross_trade(0, 0.05, 1.0, Side::Buy) };
+    let (par, par_stats) = gen.par_aggregate_with_stats(&dirty, Timeframe::m1);
+    let (seq, seq_stats) = gen.aggregate_with_stats(dirty.iter(), Timeframe::m1);
+    assert_eq!((par, par_stats.invalid), (seq, 2));
+    assert_eq!(seq_stats.invalid, 2);
+
+    // Неотсортированный срез — последовательная агрегация
+    let mut shuffled = trades[..500].to_vec();
+    shuffled.swap(10, 400);
+    assert_eq!(gen.par_aggregate(&shuffled, Timeframe::m1), gen.aggregate(shuffled.iter(), Timeframe::m1));
+}
+
+#[test]
+fn test_push_ref_shares_instrument() {
+    let t0 = 1_700_000_000_000;
+    let trades: Vec<_> = (0..5).map(|i| sample_trade(t0 + i * 60_000, 100.0 + i as f64, 1.0, Side::Buy)).collect();
+    let gen = CandleGenerator::default();
+    let mut builder = gen.builder(Timeframe::m1);
+    let mut out = Vec::new();
+    // Один инструмент на весь поток, id — из чужого буфера
+    let instrument = sample_instrument();
+    let ids = ["a", "b", "c", "d", "e"];
+    for (t, id) in trades.iter().zip(ids) {
+        builder.push_ref(&TradeRef { instrument: &instrument, id, ..t.view() }, &mut out);
+    }
+    out.extend(builder.flush());
+    assert_eq!(out, gen.aggregate(trades.iter(), Timeframe::m1));
+    assert!(out.windows(2).all(|w| Arc::ptr_eq(&w[0].instrument, &w[1].instrument)));
+    assert_eq!(trades[0].view().to_trade(), trades[0]);
+
+    let m5 = gen.rollup(&out, &Timeframe::m5);
+    assert!(Arc::ptr_eq(&m5[0].instrument, &out[0].instrument));
+}
+
+#[test]
+fn test_aggregate_columns_matches_aggregate() {
+    let t0 = 1_700_000_000_000;
+    let mut rng = rand::thread_rng();
+    let sides = [Side::Buy, Side::Sell, Side::Unknown];
+    let mut trades: Vec<_> = (0..3_000)
+        .map(|i| sample_cross_trade(t0 + i * 7_919, 0.05 + (i % 17) as f64 * 0.001, 0.5 + (i % 5) as f64, *sides.choose(&mut rng).unwrap()))
+        .collect();
+    // Поздний трейд и некорректная цена
+    trades.swap(1_000, 1_020);
+    trades[2_000].price = num(-1.0);
+
+    let config = CandleConfig {
+        volume_in_usdt: UsdtVolumeSource::Fixed(37_000.0),
+        custom_metrics: vec![Box::new(BuySellVolume)],
+        alignment: Alignment::new(chrono_tz::Europe::Moscow, chrono::Duration::hours(10)),
+        ..Default::default()
+    };
+    let gen = CandleGenerator { config };
+    // buy/sell-объём `aggregate_columns` считает сам, кастомные метрики ему не передаются
+    let columnar = CandleGenerator {
+        config: CandleConfig {
+            volume_in_usdt: UsdtVolumeSource::Fixed(37_000.0),
+            alignment: Alignment::new(chrono_tz::Europe::Moscow, chrono::Duration::hours(10)),
+            ..Default::default()
+        },
+    };
+    let timestamps: Vec<i64> = trades.iter().map(|t| t.timestamp.timestamp_millis()).collect();
+    let prices: Vec<Num> = trades.iter().map(|t| t.price).collect();
+    let amounts: Vec<Num> = trades.iter().map(|t| t.amount).collect();
+    let sides: Vec<Side> = trades.iter().map(|t| t.side).collect();
+    let columns = TradeColumns { instrument: &trades[0].instrument, timestamps: &timestamps, prices: &prices, amounts: &amounts, sides: &sides };
+
+    for tf in [Timeframe::m1, Timeframe::m15, Timeframe::h4, Timeframe::from_duration(chrono::Duration::minutes(3)).unwrap()] {
+        let candles = gen.aggregate(trades.iter(), tf.clone());
+        let out = columnar.aggregate_columns(&columns, &tf).unwrap();
+        assert_eq!(out.len(), candles.len(), "{}", tf);
+        for (i, c) in candles.iter().enumerate() {
+            assert_eq!(out.timestamps[i], c.timestamp.timestamp_millis());
+            assert_eq!((out.open[i], out.high[i], out.low[i], out.close[i]), (c.open, c.high, c.low, c.close));
+            assert_eq!((out.volume[i], out.trade_count[i], out.volume_usdt[i]), (c.volume, c.trade_count, c.volume_usdt));
+            assert!((num_to_f64(out.buy_volume[i]) - c.custom["buy_volume"]).abs() < 1e-9);
+            assert!((num_to_f64(out.sell_volume[i]) - c.custom["sell_volume"]).abs() < 1e-9);
+        }
+    }
+
+    let short = TradeColumns { amounts: &amounts[1..], ..columns };
+    assert!(matches!(
+        columnar.aggregate_columns(&short, &Timeframe::m1),
+        Err(CandleError::ColumnLength { column: "amounts", .. })
+    ));
+
+    assert_eq!(gen.aggregate_columns(&columns, &Timeframe::m1).unwrap_err(), CandleError::Unsupported { option: "custom_metrics" });
+    for (config, option) in [
+        (CandleConfig { late_policy: LatePolicy::Drop, ..Default::default() }, "late_policy"),
+        (CandleConfig { dedup: Dedup::Window(16), ..Default::default() }, "dedup"),
+        (CandleConfig { fill_gaps: true, ..Default::default() }, "fill_gaps"),
+    ] {
+        let gen = CandleGenerator { config };
+        assert_eq!(gen.aggregate_columns(&columns, &Timeframe::m1), Err(CandleError::Unsupported { option }));
+    }
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn test_arrow_round_trip() {
+    use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray};
+
+    let t0 = 1_700_000_000_000;
+    let batch = RecordBatch::try_from_iter([
+        ("ts", Arc::new(Int64Array::from(vec![t0, t0 + 1_000, t0 + 61_000, t0 + 62_000])) as ArrayRef),
+        ("px", Arc::new(Float64Array::from(vec![100.0, 101.5, 99.0, 100.5])) as ArrayRef),
+        ("qty", Arc::new(StringArray::from(vec!["1", "0.5", "2", "1.5"])) as ArrayRef),
+        ("is_buyer_maker", Arc::new(BooleanArray::from(vec![false, true, false, false])) as ArrayRef),
+    ])
+    .unwrap();
+    let mapping = TradeColumnMapping {
+        timestamp: "ts".to_string(),
+        price: "px".to_string(),
+        amount: "qty".to_string(),
+        side: Some("is_buyer_maker".to_string()),
+        id: None,
+        instrument: InstrumentMapping::Fixed(sample_instrument()),
+    };
+    let trades = trades_from_batch(&batch, &mapping).unwrap();
+    assert_eq!(trades[1], Trade { id: "1".to_string(), ..sample_trade(t0 + 1_000, 101.5, 0.5, Side::Sell) });
+    // NaN — ошибка и с `decimal`, где `num` дал бы 0
+    let columns = vec![batch.column(0).clone(), Arc::new(Float64Array::from(vec![100.0, f64::NAN, 99.0, 100.5])), batch.column(2).clone(), batch.column(3).clone()];
+    let bad = RecordBatch::try_new(batch.schema(), columns).unwrap();
+    let err = trades_from_batch(&bad, &mapping).unwrap_err();
+    assert!(err.to_string().contains("column px, row 1: NaN is not a finite number"), "{}", err);
+
+    // Кастомная метрика только у части свечей и volume_usdt = None у пары без курса
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    let gen = CandleGenerator { config };
+    let mut candles = gen.aggregate(trades.iter(), Timeframe::m1);
+    candles[0].custom.insert("vwap".to_string(), 100.5);
+    candles[1].volume_usdt = None;
+    let out = candles_to_batch(&candles).unwrap();
+    let schema = out.schema();
+    let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).skip(16).collect();
+    assert_eq!(names, ["buy_volume", "sell_volume", "vwap"]);
+    assert_eq!(out.column(12).null_count(), 1);
+    assert_eq!(candles_from_batch(&out).unwrap(), candles);
+    // Null в служебных колонках — ошибка, а не значение по умолчанию
+    let schema = Arc::new(arrow_schema::Schema::new(
+        out.schema().fields().iter().map(|f| f.as_ref().clone().with_nullable(true)).collect::<Vec<_>>(),
+    ));
+    for name in ["trade_count", "revision", "incomplete", "synthetic"] {
+        let i = schema.index_of(name).unwrap();
+        let mut columns = out.columns().to_vec();
+        columns[i] = arrow_array::new_null_array(columns[i].data_type(), out.num_rows());
+        let err = candles_from_batch(&RecordBatch::try_new(schema.clone(), columns).unwrap()).unwrap_err();
+        assert!(err.to_string().contains(&format!("column {}, row 0: unexpected null", name)), "{}", err);
+    }
+
+    // Трейды с колонками инструмента; null в обязательной колонке — ошибка со строкой
+    let cross = vec![sample_cross_trade(t0, 0.05, 1.0, Side::Buy)];
+    let batch = RecordBatch::try_from_iter([
+        ("timestamp", Arc::new(Int64Array::from(vec![t0, t0])) as ArrayRef),
+        ("price", Arc::new(Float64Array::from(vec![Some(0.05), None])) as ArrayRef),
+        ("amount", Arc::new(Float64Array::from(vec![1.0, 1.0])) as ArrayRef),
+        ("side", Arc::new(StringArray::from(vec!["BUY", "s"])) as ArrayRef),
+        ("id", Arc::new(StringArray::from(vec![cross[0].id.clone(), "x".to_string()])) as ArrayRef),
+        ("exchange", Arc::new(StringArray::from(vec!["binance", "binance"])) as ArrayRef),
+        ("base_id", Arc::new(StringArray::from(vec!["ETH", "ETH"])) as ArrayRef),
+        ("quote_id", Arc::new(StringArray::from(vec!["BTC", "BTC"])) as ArrayRef),
+        ("market_type", Arc::new(StringArray::from(vec!["Spot", "spot"])) as ArrayRef),
+    ])
+    .unwrap();
+    let err = trades_from_batch(&batch, &TradeColumnMapping::default()).unwrap_err();
+    assert!(err.to_string().contains("column price, row 1"), "{}", err);
+    assert_eq!(trades_from_batch(&batch.slice(0, 1), &TradeColumnMapping::default()).unwrap(), cross);
+
+    // Инструмент меняется посреди батча
+    let batch = RecordBatch::try_from_iter([
+        ("timestamp", Arc::new(Int64Array::from(vec![t0, t0, t0])) as ArrayRef),
+        ("price", Arc::new(Float64Array::from(vec![0.05, 0.05, 100.0])) as ArrayRef),
+        ("amount", Arc::new(Float64Array::from(vec![1.0, 1.0, 1.0])) as ArrayRef),
+        ("exchange", Arc::new(StringArray::from(vec!["binance", "binance", "binance"])) as ArrayRef),
+        ("base_id", Arc::new(StringArray::from(vec!["ETH", "ETH", "BTC"])) as ArrayRef),
+        ("quote_id", Arc::new(StringArray::from(vec!["BTC", "BTC", "USDT"])) as ArrayRef),
+    ])
+    .unwrap();
+    let mapping = TradeColumnMapping {
+        side: None,
+        id: None,
+        instrument: InstrumentMapping::Columns {
+            exchange: "exchange".to_string(),
+            base: "base_id".to_string(),
+            quote: "quote_id".to_string(),
+            market_type: None,
+        },
+        ..Default::default()
+    };
+    let pairs: Vec<_> = trades_from_batch(&batch, &mapping).unwrap().into_iter().map(|t| t.instrument.pair.to_string()).collect();
+    assert_eq!(pairs, ["ETH/BTC", "ETH/BTC", "BTC/USDT"]);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_parquet_round_trip() {
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+    use chrono::Duration;
+
+    let dir = std::env::temp_dir().join(format!("candle_generator_parquet_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let t0 = 1_700_000_000_000;
+    let mut trades: Vec<Trade> = (0..400)
+        .map(|i| {
+            let t = if i % 3 == 0 { sample_cross_trade } else { sample_trade };
+            t(t0 + i * 10_000, 100.0 + (i % 7) as f64, 0.5 + (i % 3) as f64, if i % 2 == 0 { Side::Buy } else { Side::Sell })
+        })
+        .collect();
+    trades[5].instrument.market_type = MarketType::Futures;
+
+    // Трейды в файл мелкими row group'ами
+    let column = |f: fn(&Trade) -> String| Arc::new(StringArray::from_iter_values(trades.iter().map(f))) as ArrayRef;
+    let batch = RecordBatch::try_from_iter([
+        ("timestamp", Arc::new(TimestampMillisecondArray::from_iter_values(trades.iter().map(|t| t.timestamp.timestamp_millis()))) as ArrayRef),
+        ("price", Arc::new(Float64Array::from_iter_values(trades.iter().map(|t| num_to_f64(t.price)))) as ArrayRef),
+        ("amount", Arc::new(Float64Array::from_iter_values(trades.iter().map(|t| num_to_f64(t.amount)))) as ArrayRef),
+        ("side", column(|t| format!("{:?}", t.side))),
+        ("id", column(|t| t.id.clone())),
+        ("exchange", column(|t| t.instrument.exchange.clone())),
+        ("base_id", column(|t| t.instrument.pair.base_id.clone())),
+        ("quote_id", column(|t| t.instrument.pair.quote_id.clone())),
+        ("market_type", column(|t| format!("{:?}", t.instrument.market_type))),
+    ])
+    .unwrap();
+    let path = dir.join("trades.parquet");
+    let props = ::parquet::file::properties::WriterProperties::builder().set_max_row_group_size(50).build();
+    let mut writer = ::parquet::arrow::ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), batch.schema(), Some(props)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let mapping = TradeColumnMapping::default();
+    assert_eq!(read_trades(&path, &mapping).unwrap(), trades);
+    let filter = TradeFilter {
+        from: Some(Utc.timestamp_millis_opt(t0 + 1_000_000).unwrap()),
+        to: Some(Utc.timestamp_millis_opt(t0 + 2_500_000).unwrap()),
+        instrument: Some(sample_instrument()),
+    };
+    let expected: Vec<Trade> = trades
+        .iter()
+        .filter(|t| t.timestamp >= filter.from.unwrap() && t.timestamp < filter.to.unwrap() && t.instrument == sample_instrument())
+        .cloned()
+        .collect();
+    assert_eq!(expected.len(), 100);
+    assert_eq!(read_trades_filtered(&path, &mapping, &filter).unwrap(), expected);
+    let futures = Instrument { market_type: MarketType::Futures, ..sample_instrument() };
+    let only_futures = TradeFilter { instrument: Some(futures), ..TradeFilter::default() };
+    assert_eq!(read_trades_filtered(&path, &mapping, &only_futures).unwrap(), vec![trades[5].clone()]);
+
+    // Свечи читаются обратно без изменений
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    config.fill_gaps = true;
+    let gen = CandleGenerator { config };
+    let candles = gen.aggregate(expected.iter(), Timeframe::from_duration(Duration::minutes(2)).unwrap());
+    let path = dir.join("candles.parquet");
+    let options = ParquetWriteOptions { row_group_size: 4, compression: Compression::SNAPPY };
+    write_candles_with_options(&path, &candles, &options).unwrap();
+    assert_eq!(read_candles(&path).unwrap(), candles);
+    write_candles(&path, &candles).unwrap();
+    assert_eq!(read_candles(&path).unwrap(), candles);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_parquet_filter_with_nested_column() {
+    use arrow_array::{ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray, StructArray, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field};
+
+    // Struct перед колонкой времени: у parquet два листа (meta.seq, meta.zero) на одно поле arrow
+    let t0 = 1_700_000_000_000;
+    let trades: Vec<Trade> = (0..200)
+        .map(|i| {
+            let t = if i % 2 == 0 { sample_cross_trade } else { sample_trade };
+            Trade { id: i.to_string(), ..t(t0 + i * 10_000, 100.0, 1.0, Side::Unknown) }
+        })
+        .collect();
+    let meta = StructArray::from(vec![
+        (Arc::new(Field::new("seq", DataType::Int64, false)), Arc::new(Int64Array::from_iter_values(0..200)) as ArrayRef),
+        (Arc::new(Field::new("zero", DataType::Int64, false)), Arc::new(Int64Array::from(vec![0; 200])) as ArrayRef),
+    ]);
+    let column = |f: fn(&Trade) -> String| Arc::new(StringArray::from_iter_values(trades.iter().map(f))) as ArrayRef;
+    let batch = RecordBatch::try_from_iter([
+        ("meta", Arc::new(meta) as ArrayRef),
+        ("timestamp", Arc::new(TimestampMillisecondArray::from_iter_values(trades.iter().map(|t| t.timestamp.timestamp_millis()))) as ArrayRef),
+        ("price", Arc::new(Float64Array::from_iter_values(trades.iter().map(|t| num_to_f64(t.price)))) as ArrayRef),
+        ("amount", Arc::new(Float64Array::from_iter_values(trades.iter().map(|t| num_to_f64(t.amount)))) as ArrayRef),
+        ("id", column(|t| t.id.clone())),
+        ("exchange", column(|t| t.instrument.exchange.clone())),
+        ("base_id", column(|t| t.instrument.pair.base_id.clone())),
+        ("quote_id", column(|t| t.instrument.pair.quote_id.clone())),
+    ])
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("candle_generator_nested_{}.parquet", std::process::id()));
+    let props = ::parquet::file::properties::WriterProperties::builder().set_max_row_group_size(50).build();
+    let mut writer = ::parquet::arrow::ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), batch.schema(), Some(props)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let mapping = TradeColumnMapping {
+        side: None,
+        instrument: InstrumentMapping::Columns {
+            exchange: "exchange".to_string(),
+            base: "base_id".to_string(),
+            quote: "quote_id".to_string(),
+            market_type: None,
+        },
+        ..Default::default()
+    };
+    let filter = TradeFilter {
+        from: Some(Utc.timestamp_millis_opt(t0 + 600_000).unwrap()),
+        to: Some(Utc.timestamp_millis_opt(t0 + 1_400_000).unwrap()),
+        instrument: Some(Instrument { market_type: MarketType::Unknown, ..sample_instrument() }),
+    };
+    let expected: Vec<Trade> = trades
+        .iter()
+        .filter(|t| t.timestamp >= filter.from.unwrap() && t.timestamp < filter.to.unwrap() && t.instrument.pair.quote_id == "USDT")
+        .map(|t| Trade { instrument: Instrument { market_type: MarketType::Unknown, ..t.instrument.clone() }, ..t.clone() })
+        .collect();
+    assert_eq!(expected.len(), 40);
+    assert_eq!(read_trades_filtered(&path, &mapping, &filter).unwrap(), expected);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_trade_parser() {
+    let t0 = 1_700_000_000_000;
+    // Заголовок в другом порядке, лишняя колонка, RFC 3339 и is_buyer_maker
+    let text = "\
+price,qty,time,is_buyer_maker,note
+42000.5,0.1,2023-11-14T22:13:20Z,false,x
+42001,0.2,2023-11-14T22:13:20.500+00:00,true,y
+";
+    let parser = CsvTradeParser {
+        columns: vec![
+            ("time".to_string(), TradeField::Timestamp),
+            ("price".to_string(), TradeField::Price),
+            ("qty".to_string(), TradeField::Amount),
+            ("is_buyer_maker".to_string(), TradeField::Side),
+        ],
+        timestamp: TimestampFormat::Rfc3339,
+        side: SideEncoding::BuyerMaker,
+        instrument: Some(sample_instrument()),
+        ..CsvTradeParser::default()
+    };
+    let trades: Vec<Trade> = parser.parse(Box::new(text.as_bytes())).collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        trades,
+        vec![
+            Trade { id: "2".to_string(), ..sample_trade(t0, 42000.5, 0.1, Side::Buy) },
+            Trade { id: "3".to_string(), ..sample_trade(t0 + 500, 42001.0, 0.2, Side::Sell) },
+        ]
+    );
+
+    // Без заголовка: по позиции, секунды с дробной частью, b/s
+    let text = "1700000000.25;binance;BTC;USDT;Spot;t1;100;1;b\n1700000001;binance;ETH;BTC;spot;t2;0.05;2;S\n";
+    let parser = CsvTradeParser { delimiter: b';', has_headers: false, timestamp: TimestampFormat::Seconds, side: SideEncoding::Letters, ..CsvTradeParser::default() };
+    let trades: Vec<Trade> = parser.parse(Box::new(text.as_bytes())).collect::<Result<_, _>>().unwrap();
+    assert_eq!(trades[0], Trade { id: "t1".to_string(), ..sample_trade(t0 + 250, 100.0, 1.0, Side::Buy) });
+    assert_eq!(trades[1], Trade { id: "t2".to_string(), ..sample_cross_trade(t0 + 1_000, 0.05, 2.0, Side::Sell) });
+
+    // Ошибки с номером строки; разбор продолжается со следующей строки
+    let text = "timestamp,exchange,base_id,quote_id,market_type,id,price,amount,side\n\
+                1700000000000,binance,BTC,USDT,spot,a,100,1,buy\n\
+                1700000000000,binance,BTC,USDT,spot,b,abc,1,buy\n\
+                1700000000000,binance,BTC,USDT,spot,c,100,1,long\n\
+                1700000000000,binance,BTC,USDT,spot,d,100,1,sell\n";
+    let parser = CsvTradeParser::default();
+    let results: Vec<_> = parser.parse(Box::new(text.as_bytes())).collect();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[1].as_ref().unwrap_err().to_string(), "line 3: bad price \"abc\"");
+    assert_eq!(results[2].as_ref().unwrap_err(), &FormatError::new(4, "bad side \"long\""));
+    assert_eq!(results[3].as_ref().unwrap().side, Side::Sell);
+
+    // NaN и бесконечности — не числа
+    let text = "timestamp,exchange,base_id,quote_id,market_type,id,price,amount,side\n\
+                1700000000000,binance,BTC,USDT,spot,a,NaN,1,buy\n\
+                1700000000000,binance,BTC,USDT,spot,b,100,inf,buy\n";
+    let results: Vec<_> = parser.parse(Box::new(text.as_bytes())).collect();
+    assert_eq!(results[0].as_ref().unwrap_err().to_string(), "line 2: bad price \"NaN\"");
+    assert_eq!(results[1].as_ref().unwrap_err().to_string(), "line 3: bad amount \"inf\"");
+
+    let parser = CsvTradeParser { columns: vec![("ts".to_string(), TradeField::Timestamp)], ..CsvTradeParser::default() };
+    let err = parser.parse(Box::new(text.as_bytes())).next().unwrap().unwrap_err();
+    assert_eq!(err, FormatError::new(1, "missing column \"ts\""));
+}
+
+#[test]
+fn test_timestamp_format_seconds_fraction() {
+    let f = TimestampFormat::Seconds;
+    let ms = |t: i64| Utc.timestamp_millis_opt(t).unwrap();
+    for (text, t) in [("1700000000.25", 1_700_000_000_250), ("-1.5", -1_500), ("-0.5", -500), ("-2", -2_000)] {
+        assert_eq!(f.parse(text).unwrap(), ms(t), "{}", text);
+        assert_eq!(f.format(ms(t)).unwrap(), text);
+    }
+    assert!(f.parse("-1.").is_err());
+    assert!(f.parse("1.-5").is_err());
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn test_csv_candle_serializer() {
+    let t0 = 1_700_000_040_000;
+    let trades = [sample_trade(t0, 100.0, 1.0, Side::Buy), sample_trade(t0 + 1_000, 101.5, 2.0, Side::Sell)];
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    let gen = CandleGenerator { config };
+    let candles = gen.aggregate(trades.iter(), Timeframe::m1);
+
+    let format = CsvCandleSerializer { timestamp: TimestampFormat::Rfc3339, custom: vec!["buy_volume".to_string(), "vwap".to_string()], ..CsvCandleSerializer::default() };
+    let mut out = Vec::new();
+    let mut writer = format.writer(Box::new(&mut out));
+    for c in &candles {
+        writer.write(c).unwrap();
+    }
+    writer.finish().unwrap();
+    drop(writer);
+    // Числа — в `Display` типа `Num` (у `Decimal` сохраняется масштаб: 303.0)
+    let c = &candles[0];
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!(
+            "exchange,base_id,quote_id,market_type,interval,timestamp,open,high,low,close,volume,trade_count,volume_usdt,incomplete,revision,synthetic,buy_volume,vwap\n\
+             binance,BTC,USDT,spot,m1,2023-11-14T22:14:00Z,{},{},{},{},{},2,{},false,0,false,1,\n",
+            c.open, c.high, c.low, c.close, c.volume, c.volume_usdt.unwrap()
+        )
+    );
+
+    // Пустой поток — только заголовок
+    let mut out = Vec::new();
+    CsvCandleSerializer::default().writer(Box::new(&mut out)).finish().unwrap();
+    assert!(String::from_utf8(out).unwrap().starts_with("exchange,"));
+}
+
+#[test]
+fn test_format_registry_json_lines() {
+    let t0 = 1_700_000_000_000;
+    let trades = vec![
+        sample_trade(t0, 100.0, 1.0, Side::Buy),
+        sample_trade(t0 + 1_000, 101.0, 0.5, Side::Sell),
+        sample_cross_trade(t0 + 61_000, 0.05, 2.0, Side::Unknown),
+    ];
+    let registry = FormatRegistry::new();
+    assert!(registry.parser_names().contains(&"jsonl"));
+    assert!(registry.parser("parquet").is_none());
+
+    // Трейды: строка на трейд, пустые строки пропускаются, ошибка — с номером строки
+    let mut text = String::new();
+    for t in &trades {
+        text += &serde_json::to_string(t).unwrap();
+        text += "\n\n";
+    }
+    text += "{\"id\": 1}\n";
+    let parser = registry.parser("jsonl").unwrap();
+    let results: Vec<_> = parser.parse(Box::new(text.as_bytes())).collect();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[..3].iter().map(|r| r.clone().unwrap()).collect::<Vec<_>>(), trades);
+    assert_eq!(results[3].as_ref().unwrap_err().line, 7);
+
+    // Свечи пишутся по одной и читаются обратно
+    let gen = CandleGenerator::default();
+    let candles = gen.aggregate(trades[..2].iter(), Timeframe::m1);
+    let mut out = Vec::new();
+    let mut writer = registry.serializer("jsonl").unwrap().writer(Box::new(&mut out));
+    for c in &candles {
+        writer.write(c).unwrap();
+    }
+    writer.finish().unwrap();
+    drop(writer);
+    let text = String::from_utf8(out).unwrap();
+    let back: Vec<Candle> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(back, candles);
+
+    // Свой формат под своим именем
+    let mut registry = FormatRegistry::empty();
+    registry.register_serializer("ndjson", JsonLines);
+    assert_eq!(registry.serializer_names(), ["ndjson"]);
+    assert!(registry.parser("jsonl").is_none());
+}
+
+fn exchange_trade(instrument: (&str, &str, &str, MarketType), id: &str, price: &str, amount: &str, side: Side, ts: i64) -> Trade {
+    let (exchange, base, quote, market_type) = instrument;
+    Trade {
+        instrument: Instrument {
+            pair: Pair { base_id: base.to_string(), quote_id: quote.to_string() },
+            exchange: exchange.to_string(),
+            market_type,
+        },
+        id: id.to_string(),
+        price: price.parse().unwrap(),
+        amount: amount.parse().unwrap(),
+        side,
+        timestamp: Utc.timestamp_millis_opt(ts).unwrap(),
+    }
+}
+
+#[test]
+fn test_exchange_decoders() {
+    let decode = |decoder: &ExchangeDecoder, fixture: &str| decoder.decode(fixture).unwrap();
+    let binance = ExchangeDecoder::new(Exchange::Binance);
+    let btc = ("binance", "BTC", "USDT", MarketType::Spot);
+    assert_eq!(
+        decode(&binance, include_str!("../tests/fixtures/exchanges/binance_trade.json")),
+        [exchange_trade(btc.clone(), "3272839281", "36500.01", "0.0015", Side::Sell, 1_700_000_000_120)]
+    );
+    assert_eq!(
+        decode(&binance, include_str!("../tests/fixtures/exchanges/binance_agg_trade_combined.json")),
+        [exchange_trade(("binance", "ETH", "BTC", MarketType::Spot), "482313922", "0.05432", "1.25", Side::Buy, 1_700_000_001_001)]
+    );
+    // В ответе REST нет символа — берётся из декодера
+    let historical = include_str!("../tests/fixtures/exchanges/binance_historical_trades.json");
+    assert!(binance.decode(historical).is_err());
+    let futures = ExchangeDecoder { market_type: MarketType::Futures, symbol: Some("BTCUSDT".to_string()), ..binance.clone() };
+    let trades = decode(&futures, historical);
+    assert_eq!(trades.len(), 2);
+    assert_eq!(trades[1], exchange_trade(("binance", "BTC", "USDT", MarketType::Futures), "3272839282", "36500.02", "0.01", Side::Buy, 1_700_000_000_350));
+    assert!(decode(&binance, r#"{"result":null,"id":1}"#).is_empty());
+
+    let bybit = ExchangeDecoder::new(Exchange::Bybit);
+    assert_eq!(
+        decode(&bybit, include_str!("../tests/fixtures/exchanges/bybit_public_trade.json")),
+        [exchange_trade(("bybit", "BTC", "USDT", MarketType::Spot), "2290000000069403311", "36501.5", "0.012", Side::Buy, 1_700_000_000_198)]
+    );
+    // Категория linear из ответа — фьючерсы
+    assert_eq!(
+        decode(&bybit, include_str!("../tests/fixtures/exchanges/bybit_recent_trade.json")),
+        [exchange_trade(("bybit", "ETH", "USDT", MarketType::Futures), "f2fd0d2b-1e4c-5b4e-9c5a-2f9bd1c1f3c2", "2050.35", "1.5", Side::Sell, 1_700_000_002_500)]
+    );
+    // Инверсные: size — контракты по 1 USD
+    assert_eq!(
+        decode(&bybit, include_str!("../tests/fixtures/exchanges/bybit_inverse_trade.json")),
+        [exchange_trade(("bybit", "BTC", "USD", MarketType::Futures), "8c9f1a62-3d0b-5c7e-a1f4-6e2b9d0c7a15", "40000", "0.025", Side::Buy, 1_700_000_005_000)]
+    );
+    assert!(decode(&bybit, r#"{"success":true,"ret_msg":"","op":"subscribe","conn_id":"x"}"#).is_empty());
+
+    let okx = ExchangeDecoder::new(Exchange::Okx);
+    assert_eq!(
+        decode(&okx, include_str!("../tests/fixtures/exchanges/okx_trades.json")),
+        [exchange_trade(("okx", "BTC", "USDT", MarketType::Spot), "468712395", "36499.9", "0.00264", Side::Sell, 1_700_000_003_100)]
+    );
+    // sz деривативов — в контрактах: без стоимости контракта ошибка, с ней — базовый актив
+    let history = include_str!("../tests/fixtures/exchanges/okx_history_trades.json");
+    assert_eq!(okx.decode(history).unwrap_err().message, "no contract value for BTC-USDT-SWAP");
+    let mut swap_decoder = okx.clone();
+    swap_decoder.contracts.insert("BTC-USDT-SWAP".to_string(), ContractValue::Base(num(0.01)));
+    let swap = decode(&swap_decoder, history);
+    assert_eq!(swap[0], exchange_trade(("okx", "BTC", "USDT", MarketType::Futures), "1087452991", "36510.2", "0.12", Side::Buy, 1_700_000_004_000));
+    assert_eq!((swap[1].side, swap[1].amount), (Side::Sell, num(0.03)));
+    let err = okx.decode(include_str!("../tests/fixtures/exchanges/okx_error.json")).unwrap_err();
+    assert_eq!(err.message, "okx error 51001: Instrument ID does not exist");
+    // Нечисла в строках — ошибка, как и в числах
+    let bad = r#"{"arg":{"channel":"trades","instId":"BTC-USDT"},"data":[{"instId":"BTC-USDT","tradeId":"1","px":"NaN","sz":"inf","side":"buy","ts":"1700000003100"}]}"#;
+    assert!(okx.decode(bad).unwrap_err().message.contains("bad number \"NaN\""));
+    assert!(okx.decode(&bad.replace("NaN", "1")).unwrap_err().message.contains("bad number \"inf\""));
+
+    // Coinbase: side — сторона maker-ордера, время RFC 3339 с микросекундами
+    let coinbase = ExchangeDecoder::new(Exchange::Coinbase);
+    let trades = decode(&coinbase, include_str!("../tests/fixtures/exchanges/coinbase_match.json"));
+    assert_eq!(trades[0].side, Side::Buy);
+    assert_eq!(trades[0].timestamp, utc("2023-11-14T22:13:25.123456Z"));
+    assert_eq!(trades[0].instrument, Instrument { pair: Pair { base_id: "BTC".to_string(), quote_id: "USD".to_string() }, exchange: "coinbase".to_string(), market_type: MarketType::Spot });
+    let rest = ExchangeDecoder { symbol: Some("BTC-USD".to_string()), ..coinbase.clone() };
+    let trades = decode(&rest, include_str!("../tests/fixtures/exchanges/coinbase_trades.json"));
+    assert_eq!((trades[0].id.as_str(), trades[0].side), ("572345124", Side::Sell));
+    assert!(decode(&coinbase, r#"{"type":"heartbeat","sequence":90,"last_trade_id":20,"product_id":"BTC-USD","time":"2014-11-07T08:19:28.464459Z"}"#).is_empty());
+
+    // Поток сообщений коллектора: служебные пропускаются, ошибка — с номером строки
+    let stream = include_str!("../tests/fixtures/exchanges/okx_stream.jsonl");
+    let results: Vec<_> = okx.parse(Box::new(stream.as_bytes())).collect();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().id, "468712395");
+    assert_eq!(results[1].as_ref().unwrap_err().line, 4);
+    assert_eq!(results[2].as_ref().unwrap().id, "468712397");
+}
+
+#[test]
+fn test_symbol_parsing() {
+    let pair = |base: &str, quote: &str| Pair { base_id: base.to_string(), quote_id: quote.to_string() };
+    assert_eq!(Pair::parse("BTCUSDT").unwrap(), pair("BTC", "USDT"));
+    assert_eq!(Pair::parse("ethbtc").unwrap(), pair("ETH", "BTC"));
+    assert_eq!(Pair::parse("BTCFDUSD").unwrap(), pair("BTC", "FDUSD"));
+    assert_eq!(Pair::parse("XBTUSD").unwrap(), pair("BTC", "USD"));
+    assert_eq!(Pair::parse("ETHXBT").unwrap(), pair("ETH", "BTC"));
+    assert_eq!("BTC/USDT".parse::<Pair>().unwrap(), pair("BTC", "USDT"));
+    assert!(Pair::parse("USDT").is_err());
+    assert!(Pair::parse("BTC-ETH-USDT").is_err());
+
+    let inst = |symbol: &str| Instrument::from_exchange_symbol("OKX", symbol).unwrap();
+    assert_eq!(inst("BTC-USDT").market_type, MarketType::Spot);
+    assert_eq!(inst("BTC-USDT-SWAP"), Instrument { pair: pair("BTC", "USDT"), exchange: "okx".to_string(), market_type: MarketType::Futures });
+    assert_eq!((inst("BTCUSD_PERP").pair, inst("BTCUSD_PERP").market_type), (pair("BTC", "USD"), MarketType::Futures));
+    assert_eq!(inst("BTCUSDT.P").market_type, MarketType::Futures);
+    // Только база: котируемый актив бессрочных из правил
+    assert_eq!(inst("BTC-PERP"), Instrument { pair: pair("BTC", "USD"), exchange: "okx".to_string(), market_type: MarketType::Futures });
+    assert_eq!((inst("ETHPERP").pair, inst("ETHPERP").market_type), (pair("ETH", "USD"), MarketType::Futures));
+    assert!(Pair::parse("BTC-SWAP").is_err());
+    assert!(Pair::parse("PERP").is_err());
+    let no_perp_quote = SymbolRules { perp_quote: None, ..SymbolRules::default() };
+    assert!(Pair::parse_with("BTC-PERP", &no_perp_quote).is_err());
+    let usdc = SymbolRules { perp_quote: Some("USDC".to_string()), ..SymbolRules::default() };
+    assert_eq!(Pair::parse_with("BTCPERP", &usdc).unwrap(), pair("BTC", "USDC"));
+    assert_eq!((inst("BTC/USDT:USDT").pair, inst("BTC/USDT:USDT").market_type), (pair("BTC", "USDT"), MarketType::Futures));
+    assert_eq!((inst("BTC-USD-240628").pair, inst("BTC-USD-240628").market_type), (pair("BTC", "USD"), MarketType::Futures));
+    assert_eq!(inst("BTCUSDT_240628").market_type, MarketType::Futures);
+    assert_eq!((inst("BTC-USD-240628-30000-C").pair, inst("BTC-USD-240628-30000-C").market_type), (pair("BTC", "USD"), MarketType::Unknown));
+
+    // Обратно — в форму `BTC/USDT/binance/spot`
+    let spot = Instrument::from_exchange_symbol("binance", "BTCUSDT").unwrap();
+    assert_eq!(spot.to_string(), "BTC/USDT/binance/spot");
+    assert_eq!("BTC/USDT/binance/spot".parse::<Instrument>().unwrap(), spot);
+    assert_eq!(inst("ETH-USDT-SWAP").to_string().parse::<Instrument>().unwrap(), inst("ETH-USDT-SWAP"));
+    assert!("BTC/USDT/binance".parse::<Instrument>().is_err());
+
+    // Свои котируемые активы и псевдонимы
+    let mut rules = SymbolRules::default();
+    assert_eq!(Pair::parse_with("SOLPYUSD", &rules).unwrap(), pair("SOLPY", "USD"));
+    rules.quotes.push("PYUSD".to_string());
+    rules.aliases.insert("WBTC".to_string(), "BTC".to_string());
+    assert_eq!(Pair::parse_with("SOLPYUSD", &rules).unwrap(), pair("SOL", "PYUSD"));
+    assert_eq!(Pair::parse_with("WBTC-USDT", &rules).unwrap(), pair("BTC", "USDT"));
+    let decoder = ExchangeDecoder { symbols: rules, symbol: Some("SOLPYUSD".to_string()), ..ExchangeDecoder::new(Exchange::Binance) };
+    let trades = decoder.decode(r#"[{"id":1,"price":"150.5","qty":"2","time":1700000000000,"isBuyerMaker":false}]"#).unwrap();
+    assert_eq!(trades[0].instrument.pair, pair("SOL", "PYUSD"));
+}
+
+#[cfg(feature = "proto")]
+#[test]
+fn test_proto_round_trip() {
+    use chrono::Duration;
+    use prost::Message;
+
+    let t0 = 1_700_000_000_000;
+    // Цена без точного десятичного представления в f64 и время с наносекундами
+    let mut trade = sample_trade(t0, 0.1 + 0.2, 1.5, Side::Sell);
+    trade.timestamp += Duration::nanoseconds(123_456_789);
+    let bytes = pb::Trade::from(&trade).encode_to_vec();
+    assert_eq!(Trade::try_from(pb::Trade::decode(bytes.as_slice()).unwrap()).unwrap(), trade);
+    let unknown = Trade { side: Side::Unknown, instrument: Instrument { market_type: MarketType::Unknown, ..sample_instrument() }, ..trade.clone() };
+    assert_eq!(pb::Trade::from(&unknown).instrument.unwrap().market_type, pb::MarketType::Unspecified as i32);
+    assert_eq!(Trade::try_from(pb::Trade::from(&unknown)).unwrap(), unknown);
+
+    // Свечи с кастомными метриками, без volume_usdt, синтетические и на нестандартных таймфреймах
+    let mut config = CandleConfig::default();
+    config.custom_metrics.push(Box::new(BuySellVolume));
+    let gen = CandleGenerator { config };
+    let trades: Vec<Trade> = (0..4).map(|i| sample_trade(t0 + i * 61_000, 100.0 + i as f64 * 0.1, 0.25, Side::Buy)).collect();
+    let mut candles = gen.aggregate(trades.iter(), Timeframe::m1);
+    candles[0].volume_usdt = None;
+    candles[1].synthetic = true;
+    candles[1].revision = 2;
+    candles[2].interval = Timeframe::from_duration(Duration::milliseconds(1_500)).unwrap();
+    candles[3].interval = Timeframe::Bar(BarKind::Renko { brick: 0.5, reversal: 2 });
+    candles[3].incomplete = true;
+    for candle in &candles {
+        let decoded = pb::Candle::decode(pb::Candle::from(candle).encode_to_vec().as_slice()).unwrap();
+        assert_eq!(Candle::try_from(decoded).unwrap(), *candle);
+    }
+    for tf in [Timeframe::M1, Timeframe::w1, Timeframe::Bar(BarKind::VolumeRun { initial: 100, span: 20 })] {
+        assert_eq!(Timeframe::try_from(pb::Timeframe::from(&tf)).unwrap(), tf);
+    }
+
+    // Нет обязательного поля или незнакомое значение enum — ошибка
+    let mut message = pb::Candle::from(&candles[0]);
+    message.interval = None;
+    assert_eq!(Candle::try_from(message).unwrap_err(), ProtoError("missing interval".to_string()));
+    let mut message = pb::Trade::from(&trade);
+    message.side = 7;
+    assert!(Trade::try_from(message).is_err());
+    let mut message = pb::Trade::from(&trade);
+    message.price = "abc".to_string();
+    assert!(Trade::try_from(message).is_err());
+    let mut message = pb::Trade::from(&trade);
+    message.amount = "NaN".to_string();
+    assert_eq!(Trade::try_from(message).unwrap_err(), ProtoError("bad amount \"NaN\"".to_string()));
+
+    // Некорректный порог бара не доходит до `aggregate_bars`
+    for kind in [BarKind::Tick(0), BarKind::Volume(0.0), BarKind::Renko { brick: 10.0, reversal: 0 }] {
+        let err = BarKind::try_from(pb::BarKind::from(&kind)).unwrap_err();
+        assert_eq!(err, ProtoError(format!("invalid bar threshold {}", kind)));
+    }
+}
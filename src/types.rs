@@ -1,10 +1,18 @@
-use chrono::{DateTime, Utc};
-use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Duration, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
 // Timeframe codes use lowercase (e.g., m1, h1, d1) to avoid ambiguity with monthly candles (M1), per .cursor/rules/terms.md and industry standards.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Timeframe {
+    s1,
+    s5,
+    s15,
+    s30,
     m1,
     m5,
     m15,
@@ -12,6 +20,259 @@ pub enum Timeframe {
     h1,
     h4,
     d1,
+    /// Неделя, начало — понедельник (ISO 8601)
+    w1,
+    /// Календарный месяц
+    M1,
+    /// Произвольная фиксированная ширина (например, 3m, 2h, 1500ms), интервалы отсчитываются от
+    /// Unix epoch. Код — единица и число: `m3`, `h2`, `ms1500`. Создаётся через `from_duration`
+    Custom(CustomWidth),
+    /// Бары, закрываемые не по времени, а по потоку трейдов (см. `BarBuilder`)
+    Bar(BarKind),
+}
+
+/// Ширина `Timeframe::Custom`: положительное целое число миллисекунд, не совпадающее с шириной
+/// стандартного таймфрейма — поэтому код таймфрейма однозначно разбирается обратно
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomWidth(Duration);
+
+impl CustomWidth {
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+/// Правило закрытия бара
+#[derive(Debug, Clone, Copy)]
+pub enum BarKind {
+    /// Каждые N трейдов
+    Tick(u64),
+    /// Каждые N единиц `amount`
+    Volume(f64),
+    /// Каждые N единиц объёма в USDT (`volume_usdt`)
+    Dollar(f64),
+    /// Бар закрывается, когда high − low достигает заданного диапазона
+    Range(f64),
+    /// Renko: кирпич размера `brick`; разворот требует движения на `reversal` кирпичей от закрытия
+    /// и строится от открытия последнего кирпича
+    Renko { brick: f64, reversal: u32 },
+    /// Tick imbalance bars (López de Prado): закрытие, когда |Σ b_t| ≥ E[T]·|E[b]|.
+    /// Первый бар — `initial` трейдов; ожидания — EWMA по барам с периодом `span`. Порог не ниже
+    /// √E[T]·E[v], чтобы сбалансированный бар не обнулял его
+    TickImbalance { initial: u64, span: u32 },
+    /// Volume imbalance bars: |Σ b_t·v_t| ≥ E[T]·|E[b·v]|
+    VolumeImbalance { initial: u64, span: u32 },
+    /// Tick run bars: max(Σ покупок, Σ продаж) ≥ E[T]·max(P[b=1], P[b=−1])
+    TickRun { initial: u64, span: u32 },
+    /// Volume run bars: то же по объёму
+    VolumeRun { initial: u64, span: u32 },
+}
+
+impl BarKind {
+    /// Все пороги и параметры положительны и конечны (иначе бар не закрывается никогда или на
+    /// каждом трейде)
+    pub fn is_valid(&self) -> bool {
+        let (_, v, extra) = self.code();
+        let valid = |x: f64| x > 0.0 && x.is_finite();
+        valid(v) && extra.is_none_or(|(_, x)| valid(x))
+    }
+
+    /// Код: имя, основной параметр и необязательный дополнительный с буквой-разделителем
+    fn code(&self) -> (&'static str, f64, Option<(char, f64)>) {
+        match self {
+            BarKind::Tick(n) => ("tick", *n as f64, None),
+            BarKind::Volume(v) => ("vol", *v, None),
+            BarKind::Dollar(v) => ("usdt", *v, None),
+            BarKind::Range(v) => ("range", *v, None),
+            BarKind::Renko { brick, reversal } => ("renko", *brick, Some(('r', *reversal as f64))),
+            BarKind::TickImbalance { initial, span } => ("timb", *initial as f64, Some(('e', *span as f64))),
+            BarKind::VolumeImbalance { initial, span } => ("vimb", *initial as f64, Some(('e', *span as f64))),
+            BarKind::TickRun { initial, span } => ("trun", *initial as f64, Some(('e', *span as f64))),
+            BarKind::VolumeRun { initial, span } => ("vrun", *initial as f64, Some(('e', *span as f64))),
+        }
+    }
+}
+
+// Пороги сравниваются побитово, чтобы `Timeframe` оставался ключом HashMap
+impl PartialEq for BarKind {
+    fn eq(&self, other: &Self) -> bool {
+        let bits = |(name, v, extra): (&'static str, f64, Option<(char, f64)>)| (name, v.to_bits(), extra.map(|(c, x)| (c, x.to_bits())));
+        bits(self.code()) == bits(other.code())
+    }
+}
+
+impl Eq for BarKind {}
+
+impl std::hash::Hash for BarKind {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let (name, v, extra) = self.code();
+        name.hash(state);
+        v.to_bits().hash(state);
+        extra.map(|(c, x)| (c, x.to_bits())).hash(state);
+    }
+}
+
+impl fmt::Display for BarKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, v, extra) = self.code();
+        write!(f, "{}{}", name, v)?;
+        if let Some((c, x)) = extra {
+            write!(f, "{}{}", c, x)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BarKind {
+    type Err = ParseTimeframeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseTimeframeError(s.to_string());
+        let split = s.find(|c: char| c.is_ascii_digit()).ok_or_else(err)?;
+        let (name, value) = s.split_at(split);
+        let kind = match name {
+            "tick" => BarKind::Tick(value.parse().map_err(|_| err())?),
+            "vol" => BarKind::Volume(value.parse().map_err(|_| err())?),
+            "usdt" => BarKind::Dollar(value.parse().map_err(|_| err())?),
+            "range" => BarKind::Range(value.parse().map_err(|_| err())?),
+            "renko" => {
+                let (brick, reversal) = value.split_once('r').ok_or_else(err)?;
+                BarKind::Renko {
+                    brick: brick.parse().map_err(|_| err())?,
+                    reversal: reversal.parse().map_err(|_| err())?,
+                }
+            }
+            "timb" | "vimb" | "trun" | "vrun" => {
+                let (initial, span) = value.split_once('e').ok_or_else(err)?;
+                let initial = initial.parse().map_err(|_| err())?;
+                let span = span.parse().map_err(|_| err())?;
+                match name {
+                    "timb" => BarKind::TickImbalance { initial, span },
+                    "vimb" => BarKind::VolumeImbalance { initial, span },
+                    "trun" => BarKind::TickRun { initial, span },
+                    _ => BarKind::VolumeRun { initial, span },
+                }
+            }
+            _ => return Err(err()),
+        };
+        if kind.is_valid() { Ok(kind) } else { Err(err()) }
+    }
+}
+
+impl Timeframe {
+    /// Длительность интервала (для `M1` — номинальные 30 дней, фактическая граница — календарная;
+    /// для баров — ноль)
+    pub fn duration(&self) -> Duration {
+        match self {
+            Timeframe::s1 => Duration::seconds(1),
+            Timeframe::s5 => Duration::seconds(5),
+            Timeframe::s15 => Duration::seconds(15),
+            Timeframe::s30 => Duration::seconds(30),
+            Timeframe::m1 => Duration::minutes(1),
+            Timeframe::m5 => Duration::minutes(5),
+            Timeframe::m15 => Duration::minutes(15),
+            Timeframe::m30 => Duration::minutes(30),
+            Timeframe::h1 => Duration::hours(1),
+            Timeframe::h4 => Duration::hours(4),
+            Timeframe::d1 => Duration::days(1),
+            Timeframe::w1 => Duration::weeks(1),
+            Timeframe::M1 => Duration::days(30),
+            Timeframe::Custom(w) => w.duration(),
+            Timeframe::Bar(_) => Duration::zero(),
+        }
+    }
+
+    /// Таймфрейм фиксированной ширины: стандартный, если такой есть, иначе `Custom`.
+    /// `None` для неположительной ширины и ширины не из целых миллисекунд
+    pub fn from_duration(d: Duration) -> Option<Timeframe> {
+        if d <= Duration::zero() || d.subsec_nanos() % 1_000_000 != 0 {
+            return None;
+        }
+        const FIXED: [Timeframe; 12] = [
+            Timeframe::s1, Timeframe::s5, Timeframe::s15, Timeframe::s30,
+            Timeframe::m1, Timeframe::m5, Timeframe::m15, Timeframe::m30,
+            Timeframe::h1, Timeframe::h4, Timeframe::d1, Timeframe::w1,
+        ];
+        Some(FIXED.into_iter().find(|tf| tf.duration() == d).unwrap_or(Timeframe::Custom(CustomWidth(d))))
+    }
+}
+
+/// Ошибка разбора кода таймфрейма
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTimeframeError(pub String);
+
+impl fmt::Display for ParseTimeframeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid timeframe code: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseTimeframeError {}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = match self {
+            Timeframe::Custom(w) => w.duration(),
+            Timeframe::Bar(kind) => return write!(f, "{}", kind),
+            _ => return write!(f, "{:?}", self),
+        };
+        // Самая крупная единица, в которую ширина укладывается целиком
+        let ms = width.num_milliseconds();
+        let (unit, n) = [("w", 604_800_000), ("d", 86_400_000), ("h", 3_600_000), ("m", 60_000), ("s", 1_000), ("ms", 1)]
+            .into_iter()
+            .find(|(_, u)| ms % u == 0)
+            .map(|(c, u)| (c, ms / u))
+            .unwrap();
+        write!(f, "{}{}", unit, n)
+    }
+}
+
+impl FromStr for Timeframe {
+    type Err = ParseTimeframeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "M1" {
+            return Ok(Timeframe::M1);
+        }
+        if let Ok(kind) = s.parse::<BarKind>() {
+            return Ok(Timeframe::Bar(kind));
+        }
+        let err = || ParseTimeframeError(s.to_string());
+        let (unit, digits) = match s.strip_prefix("ms") {
+            Some(digits) => (Duration::milliseconds(1), digits),
+            None => {
+                let mut chars = s.chars();
+                let unit = match chars.next().ok_or_else(err)? {
+                    's' => Duration::seconds(1),
+                    'm' => Duration::minutes(1),
+                    'h' => Duration::hours(1),
+                    'd' => Duration::days(1),
+                    'w' => Duration::weeks(1),
+                    _ => return Err(err()),
+                };
+                (unit, chars.as_str())
+            }
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+        let n: i32 = digits.parse().map_err(|_| err())?;
+        Timeframe::from_duration(unit.checked_mul(n).ok_or_else(err)?).ok_or_else(err)
+    }
+}
+
+// Сериализуется кодом таймфрейма ("m1", "M1", "m3")
+impl Serialize for Timeframe {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timeframe {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(de::Error::custom)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -28,6 +289,30 @@ pub enum MarketType {
     Unknown,
 }
 
+/// `spot`, `futures`, `margin`, `unknown`
+impl fmt::Display for MarketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MarketType::Spot => "spot",
+            MarketType::Futures => "futures",
+            MarketType::Margin => "margin",
+            MarketType::Unknown => "unknown",
+        })
+    }
+}
+
+/// Без учёта регистра; незнакомое имя — `Unknown`
+impl From<&str> for MarketType {
+    fn from(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "spot" => MarketType::Spot,
+            "futures" => MarketType::Futures,
+            "margin" => MarketType::Margin,
+            _ => MarketType::Unknown,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Instrument {
     pub pair: Pair,
@@ -35,49 +320,156 @@ pub struct Instrument {
     pub market_type: MarketType,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Side {
     Buy,
     Sell,
     Unknown,
 }
 
+/// Тип цен и объёмов. С feature `decimal` — `rust_decimal::Decimal` (точные суммы; в serde — строкой),
+/// иначе `f64`
+#[cfg(not(feature = "decimal"))]
+pub type Num = f64;
+#[cfg(feature = "decimal")]
+pub type Num = rust_decimal::Decimal;
+
+/// `f64` → `Num` (для порогов, курсов и литералов). Для `Decimal` — кратчайшее десятичное
+/// представление: `num(0.1)` — ровно 0.1; NaN и бесконечности — 0. Для внешних данных — `try_num`
+#[cfg(not(feature = "decimal"))]
+pub fn num(x: f64) -> Num {
+    x
+}
+#[cfg(feature = "decimal")]
+pub fn num(x: f64) -> Num {
+    try_num(x).unwrap_or_default()
+}
+
+/// `f64` → `Num`; `None` для NaN, бесконечностей и (с `decimal`) значений вне диапазона `Decimal`
+#[cfg(not(feature = "decimal"))]
+pub fn try_num(x: f64) -> Option<Num> {
+    x.is_finite().then_some(x)
+}
+#[cfg(feature = "decimal")]
+pub fn try_num(x: f64) -> Option<Num> {
+    use rust_decimal::prelude::FromPrimitive;
+    Num::from_f64(x)
+}
+
+/// Строка → `Num` для внешних данных; `None` для нечисел, NaN и бесконечностей
+#[cfg(not(feature = "decimal"))]
+pub(crate) fn parse_num(s: &str) -> Option<Num> {
+    s.parse::<Num>().ok().filter(|x| x.is_finite())
+}
+#[cfg(feature = "decimal")]
+pub(crate) fn parse_num(s: &str) -> Option<Num> {
+    s.parse().ok()
+}
+
+/// `Num` → `f64` (для кастомных метрик и порогов баров)
+#[cfg(not(feature = "decimal"))]
+pub fn num_to_f64(x: Num) -> f64 {
+    x
+}
+#[cfg(feature = "decimal")]
+pub fn num_to_f64(x: Num) -> f64 {
+    use rust_decimal::prelude::ToPrimitive;
+    x.to_f64().unwrap_or(f64::NAN)
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Trade {
     pub instrument: Instrument,
     pub id: String,
-    pub price: f64,
-    pub amount: f64,
+    pub price: Num,
+    pub amount: Num,
+    pub side: Side,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Trade {
+    /// Заимствованное представление для горячего пути (`CandleBuilder::push_ref`)
+    pub fn view(&self) -> TradeRef<'_> {
+        TradeRef {
+            instrument: &self.instrument,
+            id: &self.id,
+            price: self.price,
+            amount: self.amount,
+            side: self.side,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// Трейд без владения строками: инструмент и id заимствуются (например, у общего `Instrument`
+/// потока и буфера парсера), так что агрегация не аллоцирует на каждый трейд
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TradeRef<'a> {
+    pub instrument: &'a Instrument,
+    pub id: &'a str,
+    pub price: Num,
+    pub amount: Num,
     pub side: Side,
     pub timestamp: DateTime<Utc>,
 }
 
+impl TradeRef<'_> {
+    pub fn to_trade(&self) -> Trade {
+        Trade {
+            instrument: self.instrument.clone(),
+            id: self.id.to_string(),
+            price: self.price,
+            amount: self.amount,
+            side: self.side,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Candle {
-    pub instrument: Instrument,
+    /// Общий для всех свечей одного билдера: клонирование свечи не копирует строки инструмента
+    pub instrument: Arc<Instrument>,
     pub interval: Timeframe,
     pub timestamp: DateTime<Utc>,
     #[serde(rename = "o")]
-    pub open: f64,
+    pub open: Num,
     #[serde(rename = "h")]
-    pub high: f64,
+    pub high: Num,
     #[serde(rename = "l")]
-    pub low: f64,
+    pub low: Num,
     #[serde(rename = "c")]
-    pub close: f64,
+    pub close: Num,
     #[serde(rename = "v")]
-    pub volume: f64,
+    pub volume: Num,
     #[serde(rename = "tc")]
     pub trade_count: u64,
     #[serde(rename = "vusdt")]
-    pub volume_usdt: Option<f64>,
+    pub volume_usdt: Option<Num>,
     /// Кастомные метрики (buy/sell volume, VWAP и др.), AGI-ready
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub custom: HashMap<String, f64>,
+    /// Свеча покрывает меньше младших свечей, чем положено интервалу (только при `mark_incomplete`),
+    /// или последний бар не набрал порог
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// Номер корректировки уже выданной свечи (`LatePolicy::Merge`), 0 — первая выдача
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub revision: u32,
+    /// Свеча за интервал без трейдов (`fill_gaps`): OHLC = предыдущий close, объём 0
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub synthetic: bool,
+}
+
+fn is_zero(v: &u32) -> bool {
+    *v == 0
 }
 
+/// Курс quote → USDT на момент трейда
+pub type UsdtRateFn = Box<dyn Fn(&Pair, DateTime<Utc>) -> Option<f64> + Send + Sync>;
+
 pub enum UsdtVolumeSource {
     Fixed(f64),
-    Callback(Box<dyn Fn(&Pair, DateTime<Utc>) -> Option<f64> + Send + Sync>),
+    Callback(UsdtRateFn),
     None,
 } 
\ No newline at end of file
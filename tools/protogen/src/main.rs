@@ -0,0 +1,33 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+const GENERATED: &str = "src/proto/candle_generator.v1.rs";
+
+fn main() -> ExitCode {
+    let check = std::env::args().any(|a| a == "--check");
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    let out = std::env::temp_dir().join(format!("candle_generator_protogen_{}", std::process::id()));
+    std::fs::create_dir_all(&out).expect("cannot create output dir");
+    // Свой protoc, чтобы вывод не зависел от установленной версии
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc"));
+    prost_build::Config::new()
+        .out_dir(&out)
+        .compile_protos(&[root.join("proto/candle_generator.proto")], &[root.join("proto")])
+        .expect("cannot compile proto/candle_generator.proto");
+    let fresh = std::fs::read_to_string(out.join("candle_generator.v1.rs")).expect("no generated file");
+    std::fs::remove_dir_all(&out).ok();
+
+    let target = root.join(GENERATED);
+    if !check {
+        std::fs::write(&target, fresh).expect("cannot write generated file");
+        println!("{} updated", GENERATED);
+        return ExitCode::SUCCESS;
+    }
+    if std::fs::read_to_string(&target).ok().as_deref() == Some(fresh.as_str()) {
+        println!("{} is up to date", GENERATED);
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("{} is out of date: run `cargo run --manifest-path tools/protogen/Cargo.toml`", GENERATED);
+        ExitCode::FAILURE
+    }
+}